@@ -0,0 +1,24 @@
+use std::fs::File;
+use osmpbf_parser::{Parser,Scan,NodeLocations,SparseLocations};
+
+type Error = Box<dyn std::error::Error+Send+Sync+'static>;
+
+fn main() -> Result<(),Error> {
+  let args = std::env::args().collect::<Vec<String>>();
+  let h = File::open(&args[1])?;
+  let file_len = h.metadata()?.len();
+  let mut scan = Scan::new(Parser::new(Box::new(h)));
+  scan.scan(0, file_len)?;
+  let mut locations = SparseLocations::new();
+  scan.load_locations(&mut locations)?;
+  for (offset,byte_len,_len) in scan.get_way_blob_offsets().collect::<Vec<_>>() {
+    for item in scan.parser.read_primitive(offset,byte_len)?.decode()? {
+      if let osmpbf_parser::Element::Way(way) = item {
+        let coords = locations.get_way_coords(&way)?;
+        let missing = coords.iter().filter(|c| c.is_none()).count();
+        println!["way {}: {} refs, {} missing {:?}", way.id, coords.len(), missing, coords.first()];
+      }
+    }
+  }
+  Ok(())
+}
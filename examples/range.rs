@@ -7,7 +7,7 @@ fn main() -> Result<(),Error> {
   let args = std::env::args().collect::<Vec<String>>();
   let h = File::open(&args[1])?;
   let file_len = h.metadata()?.len();
-  let mut opd = Parser::new(Box::new(h));
+  let opd = Parser::new(Box::new(h));
   let mut offset = 0;
   while offset < file_len {
     let (byte_len,items) = opd.read(offset)?;
@@ -31,6 +31,11 @@ fn main() -> Result<(),Error> {
           min_id = relation.id.min(min_id);
           max_id = relation.id.max(max_id);
         },
+        Element::Changeset(changeset) => {
+          etype = "changeset";
+          min_id = changeset.id.min(min_id);
+          max_id = changeset.id.max(max_id);
+        },
       }
     }
     if !items.is_empty() {
@@ -0,0 +1,49 @@
+use osmpbf_parser::{StreamParser,Element};
+
+type Error = Box<dyn std::error::Error+Send+Sync+'static>;
+
+// pass --recover to skip over damaged fileblocks instead of stopping at the first error
+fn main() -> Result<(),Error> {
+  let stdin = std::io::stdin();
+  let mut opd = StreamParser::new(stdin.lock());
+  opd.set_recover(std::env::args().any(|arg| arg == "--recover"));
+  for result in opd.by_ref() {
+    let (offset,byte_len,items) = result?;
+    let mut etype = "";
+    let mut min_id = i64::MAX;
+    let mut max_id = i64::MIN;
+    for item in items.iter() {
+      match item {
+        Element::Node(node) => {
+          etype = "node";
+          min_id = node.id.min(min_id);
+          max_id = node.id.max(max_id);
+        },
+        Element::Way(way) => {
+          etype = "way";
+          min_id = way.id.min(min_id);
+          max_id = way.id.max(max_id);
+        },
+        Element::Relation(relation) => {
+          etype = "relation";
+          min_id = relation.id.min(min_id);
+          max_id = relation.id.max(max_id);
+        },
+        Element::Changeset(changeset) => {
+          etype = "changeset";
+          min_id = changeset.id.min(min_id);
+          max_id = changeset.id.max(max_id);
+        },
+      }
+    }
+    if !items.is_empty() {
+      println!["{}: offset={} byte_len={} items.len()={} id range {}..{}",
+        etype, offset, byte_len, items.len(), min_id, max_id];
+    }
+  }
+  for skipped in opd.skipped() {
+    eprintln!["skipped {} bytes at offset {}..{}: {}",
+      skipped.len(), skipped.start, skipped.end, skipped.error];
+  }
+  Ok(())
+}
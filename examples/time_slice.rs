@@ -0,0 +1,38 @@
+use std::fs::File;
+use std::io::{BufReader,BufWriter};
+use osmpbf_parser::{Parser,StreamParser,PbfWriter,TimeFilter,TimeSlice};
+
+type Error = Box<dyn std::error::Error+Send+Sync+'static>;
+
+// usage: time_slice INPUT OUTPUT TIME [END_TIME]
+// times are in seconds since the unix epoch. with one time the output is a snapshot of the map at
+// that time, with two it is a history file of every version current at some point in between
+fn main() -> Result<(),Error> {
+  let args = std::env::args().collect::<Vec<String>>();
+  let parser = Parser::new(Box::new(File::open(&args[1])?));
+  let slice = match args.get(4) {
+    Some(end) => TimeSlice::Between(args[3].parse()?, end.parse()?),
+    None => TimeSlice::At(args[3].parse()?),
+  };
+
+  let (_,mut header) = parser.read_header(0)?;
+  header.required_features.retain(|f| f != "HistoricalInformation");
+  if let TimeSlice::Between(_,_) = slice {
+    header.required_features.push("HistoricalInformation".into());
+  }
+  header.writingprogram = Some("osmpbf-parser time_slice".into());
+  let mut writer = PbfWriter::with_header(BufWriter::new(File::create(&args[2])?), header);
+
+  // stream the elements so that only one block is held in memory at a time
+  let mut error = None;
+  let elements = StreamParser::new(BufReader::new(File::open(&args[1])?))
+    .scan(&mut error, |error, r| match r {
+      Ok((_,_,items)) => Some(items),
+      Err(e) => { **error = Some(e); None },
+    })
+    .flatten();
+  writer.write_all(TimeFilter::new(elements, slice))?;
+  if let Some(e) = error { return Err(e) }
+  writer.finish()?;
+  Ok(())
+}
@@ -0,0 +1,22 @@
+use std::fs::File;
+use osmpbf_parser::Parser;
+
+type Error = Box<dyn std::error::Error+Send+Sync+'static>;
+
+// prints one line per problem and exits with status 1 if any were found
+fn main() -> Result<(),Error> {
+  let args = std::env::args().collect::<Vec<String>>();
+  if args.len() < 2 {
+    eprintln!["usage: verify FILE"];
+    std::process::exit(2);
+  }
+  let h = File::open(&args[1])?;
+  let file_len = h.metadata()?.len();
+  let parser = Parser::new(Box::new(h));
+  let report = parser.verify(0, file_len)?;
+  println!["{}", report];
+  if !report.is_ok() {
+    std::process::exit(1);
+  }
+  Ok(())
+}
@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use osmpbf_parser::{Blob,BlobRef};
+use quick_protobuf::{MessageRead,BytesReader};
+
+// the input is a single Blob message, decoded through both the owned and the borrowed paths
+fuzz_target!(|data: &[u8]| {
+  if let Ok(blob) = Blob::from_reader(&mut BytesReader::from_bytes(data), data) {
+    let _ = blob.decode_header();
+    if let Ok(block) = blob.decode_primitive() {
+      let _ = block.decode();
+    }
+  }
+  if let Ok(blob) = BlobRef::from_bytes(data) {
+    if let Ok(block) = blob.decode_primitive() {
+      let _ = block.decode_with_locations(0);
+    }
+  }
+});
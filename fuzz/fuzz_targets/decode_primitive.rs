@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use osmpbf_parser::proto::osmformat::PrimitiveBlock;
+
+// the input is an uncompressed PrimitiveBlock, which reaches the element decoder directly
+fuzz_target!(|data: &[u8]| {
+  if let Ok(block) = PrimitiveBlock::from_bytes(data) {
+    let _ = block.decode();
+  }
+});
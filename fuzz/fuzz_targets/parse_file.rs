@@ -0,0 +1,28 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use osmpbf_parser::{Parser,Scan};
+
+// walk the input as a pbf file with random access reads, with and without recovery,
+// then scan it and look up ids
+fuzz_target!(|data: &[u8]| {
+  let len = data.len() as u64;
+  let parser = Parser::new(Box::new(data.to_vec()));
+  let _ = parser.read_header(0);
+  let mut offset = 0;
+  while offset < len {
+    match parser.read(offset) {
+      Ok((n,_)) => offset += n,
+      Err(_) => break,
+    }
+  }
+  for r in parser.blocks(0, len).recover(true) {
+    if r.is_err() { break }
+  }
+  let mut scan = Scan::new(parser);
+  let _ = scan.scan_recover(0, len);
+  if scan.scan(0, len).is_ok() {
+    let _ = scan.get_node(1);
+    let _ = scan.get_way(1);
+    let _ = scan.get_relation(1);
+  }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use osmpbf_parser::StreamParser;
+
+fuzz_target!(|data: &[u8]| {
+  for r in StreamParser::new(data) {
+    if r.is_err() { break }
+  }
+  let mut stream = StreamParser::new(data);
+  stream.set_recover(true);
+  for r in stream.by_ref() {
+    if r.is_err() { break }
+  }
+  assert!(stream.skipped().iter().all(|s| s.end <= data.len() as u64));
+});
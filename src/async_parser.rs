@@ -0,0 +1,235 @@
+use crate::{Blob,BlobHeader,ScanTable,element,Element,Error};
+use crate::decode::{check_blob_header_size,check_blob_size};
+use crate::proto::osmformat::{HeaderBlock,PrimitiveBlock};
+use quick_protobuf::{MessageRead,Reader};
+use tokio::io::{AsyncRead,AsyncReadExt,AsyncSeek,AsyncSeekExt};
+use tokio::sync::Mutex;
+use std::io::SeekFrom;
+
+pub struct AsyncParser<F: AsyncRead+AsyncSeek+Unpin> {
+  handle: Mutex<Box<F>>,
+  offload: bool,
+}
+
+impl<F> AsyncParser<F> where F: AsyncRead+AsyncSeek+Unpin {
+  pub fn new(handle: Box<F>) -> Self {
+    Self { handle: Mutex::new(handle), offload: false }
+  }
+  // run decompression and decoding on tokio's blocking thread pool instead of the executor
+  pub fn set_offload(&mut self, offload: bool) {
+    self.offload = offload;
+  }
+  pub async fn read_fileblock(&self, offset: u64) -> Result<(u64,BlobHeader,Blob),Error> {
+    let (s,blob_header) = self.read_blob_header(offset).await?;
+    let blob = self.read_blob(offset + s, blob_header.datasize as usize).await?;
+    Ok((s + blob_header.datasize as u64, blob_header, blob))
+  }
+  pub async fn read_blob_header(&self, offset: u64) -> Result<(u64,BlobHeader),Error> {
+    let mut handle = self.handle.lock().await;
+    let mut len_buf = [0,0,0,0];
+    handle.seek(SeekFrom::Start(offset)).await?;
+    handle.read_exact(&mut len_buf).await?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    check_blob_header_size(len)?;
+    let mut buf = vec![0u8;len];
+    handle.read_exact(&mut buf).await?;
+    let mut reader = Reader::from_bytes(buf);
+    let blob_header = reader.read(BlobHeader::from_reader)?;
+    check_blob_size(blob_header.datasize as i64)?;
+    Ok(((len+4) as u64, blob_header))
+  }
+  pub async fn read_blob(&self, offset: u64, len: usize) -> Result<Blob,Error> {
+    check_blob_size(len as i64)?;
+    let mut buf = vec![0u8;len];
+    {
+      let mut handle = self.handle.lock().await;
+      handle.seek(SeekFrom::Start(offset)).await?;
+      handle.read_exact(&mut buf).await?;
+    }
+    let mut reader = Reader::from_bytes(buf);
+    let blob = reader.read(Blob::from_reader)?;
+    Ok(blob)
+  }
+  pub async fn read_header(&self, offset: u64) -> Result<(u64,HeaderBlock),Error> {
+    let (len,blob_header,blob) = self.read_fileblock(offset).await?;
+    if blob_header.type_pb != "OSMHeader" {
+      return Err(format![
+        "expected OSMHeader at offset {}, found {}", offset, blob_header.type_pb
+      ].into());
+    }
+    let header_block = self.run(move || blob.decode_header()).await?;
+    header_block.check_required_features()?;
+    Ok((len, header_block))
+  }
+  pub async fn read_primitive(&self, offset: u64, len: usize) -> Result<PrimitiveBlock,Error> {
+    let blob = self.read_blob(offset, len).await?;
+    self.run(move || blob.decode_primitive()).await
+  }
+  pub async fn read_elements(&self, offset: u64, len: usize) -> Result<Vec<Element>,Error> {
+    let blob = self.read_blob(offset, len).await?;
+    self.run(move || blob.decode_primitive()?.decode()).await
+  }
+  pub async fn read(&self, offset: u64) -> Result<(u64,Vec<element::Element>),Error> {
+    let (s,blob_header) = self.read_blob_header(offset).await?;
+    let len = s + blob_header.datasize as u64;
+    if blob_header.type_pb == "OSMHeader" {
+      Ok((len, vec![]))
+    } else {
+      Ok((len, self.read_elements(offset + s, blob_header.datasize as usize).await?))
+    }
+  }
+  async fn run<T,G>(&self, f: G) -> Result<T,Error>
+  where T: Send+'static, G: FnOnce() -> Result<T,Error>+Send+'static {
+    if self.offload {
+      tokio::task::spawn_blocking(f).await?
+    } else {
+      f()
+    }
+  }
+}
+
+pub struct AsyncScan<F: AsyncRead+AsyncSeek+Unpin> {
+  pub parser: AsyncParser<F>,
+  pub table: ScanTable,
+}
+
+impl<F> AsyncScan<F> where F: AsyncRead+AsyncSeek+Unpin {
+  pub fn new(parser: AsyncParser<F>) -> Self {
+    Self {
+      parser,
+      table: ScanTable::default(),
+    }
+  }
+  pub fn from_table(parser: AsyncParser<F>, table: ScanTable) -> Self {
+    Self { parser, table }
+  }
+  pub async fn scan(&mut self, start: u64, end: u64) -> Result<(),Error> {
+    let mut offset = start;
+    while offset < end {
+      let (blob_header_len,blob_header) = self.parser.read_blob_header(offset).await?;
+      let blob_offset = offset + blob_header_len;
+      let blob_len = blob_header.datasize as usize;
+      let len = blob_header_len + blob_len as u64;
+      if blob_header.type_pb == "OSMHeader" {
+        offset += len;
+        continue;
+      }
+      let items = self.parser.read_elements(blob_offset, blob_len).await?;
+      self.table.insert_items(blob_offset, blob_len, &items);
+      offset += len;
+    }
+    Ok(())
+  }
+  pub async fn get_node(&self, id: i64) -> Result<Option<element::Node>,Error> {
+    for (offset,byte_len,_len) in self.table.get_node_blob_offsets_for_id(id) {
+      for item in self.parser.read_elements(offset,byte_len).await? {
+        match item {
+          Element::Node(node) => {
+            if node.id == id { return Ok(Some(node)) }
+          },
+          _ => { break }
+        }
+      }
+    }
+    Ok(None)
+  }
+  pub async fn get_way(&self, id: i64) -> Result<Option<element::Way>,Error> {
+    for (offset,byte_len,_len) in self.table.get_way_blob_offsets_for_id(id) {
+      for item in self.parser.read_elements(offset,byte_len).await? {
+        match item {
+          Element::Way(way) => {
+            if way.id == id { return Ok(Some(way)) }
+          },
+          _ => { break }
+        }
+      }
+    }
+    Ok(None)
+  }
+  pub async fn get_relation(&self, id: i64) -> Result<Option<element::Relation>,Error> {
+    for (offset,byte_len,_len) in self.table.get_relation_blob_offsets_for_id(id) {
+      for item in self.parser.read_elements(offset,byte_len).await? {
+        match item {
+          Element::Relation(relation) => {
+            if relation.id == id { return Ok(Some(relation)) }
+          },
+          _ => { break }
+        }
+      }
+    }
+    Ok(None)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Parser,PbfWriter,MemberType};
+  use crate::test_util::{node,way,relation,write,scan_data};
+  use std::io::Cursor;
+
+  fn data() -> Vec<u8> {
+    let mut elements = (1..=300).map(|id| node(id, id * 1000, -id * 1000)).collect::<Vec<_>>();
+    elements.extend((1..=50).map(|id| way(id, &[id,id+1,id+2])));
+    elements.push(relation(1, "route", &[(MemberType::Way,1),(MemberType::Node,7)]));
+    let mut writer = PbfWriter::new(vec![]);
+    writer.set_block_size(64);
+    writer.write_all(elements).unwrap();
+    writer.finish().unwrap()
+  }
+
+  #[tokio::test]
+  async fn matches_sync_parser() {
+    let data = data();
+    for offload in [false,true].iter() {
+      let mut parser = AsyncParser::new(Box::new(Cursor::new(data.clone())));
+      parser.set_offload(*offload);
+      let sync = Parser::new(Box::new(data.clone()));
+      let (len,header) = parser.read_header(0).await.unwrap();
+      assert_eq![(len,header), sync.read_header(0).unwrap()];
+      let mut offset = 0;
+      let mut blocks = 0;
+      while offset < data.len() as u64 {
+        let (len,elements) = parser.read(offset).await.unwrap();
+        assert_eq![(len,elements), sync.read(offset).unwrap()];
+        offset += len;
+        blocks += 1;
+      }
+      assert_eq![blocks, 1 + 5 + 1 + 1];
+      let sync_scan = scan_data(data.clone());
+      let mut scan = AsyncScan::new(parser);
+      scan.scan(0, data.len() as u64).await.unwrap();
+      let offsets = |table: &ScanTable| {
+        let mut offsets = table.get_node_blob_offsets()
+          .chain(table.get_way_blob_offsets())
+          .chain(table.get_relation_blob_offsets())
+          .collect::<Vec<_>>();
+        offsets.sort_unstable();
+        offsets
+      };
+      assert_eq![offsets(&scan.table), offsets(&sync_scan.table)];
+      assert_eq![offsets(&scan.table).len(), 7];
+      for id in [1,64,65,300,301].iter() {
+        assert_eq![scan.get_node(*id).await.unwrap(), sync_scan.get_node(*id).unwrap()];
+      }
+      assert_eq![scan.get_way(50).await.unwrap(), sync_scan.get_way(50).unwrap()];
+      assert![scan.get_way(50).await.unwrap().is_some()];
+      assert_eq![scan.get_relation(1).await.unwrap(), sync_scan.get_relation(1).unwrap()];
+    }
+  }
+
+  #[tokio::test]
+  async fn header_checks() {
+    let mut writer = PbfWriter::new(vec![]);
+    writer.header_mut().unwrap().required_features.push("Sort.Type_then_ID_v2".into());
+    writer.write(node(1, 0, 0)).unwrap();
+    let data = writer.finish().unwrap();
+    let parser = AsyncParser::new(Box::new(Cursor::new(data)));
+    assert![parser.read_header(0).await.is_err()];
+    let data = write(vec![node(1, 0, 0)]);
+    let parser = AsyncParser::new(Box::new(Cursor::new(data)));
+    let (len,_) = parser.read_header(0).await.unwrap();
+    // the next fileblock is data, not a header
+    assert![parser.read_header(len).await.is_err()];
+  }
+}
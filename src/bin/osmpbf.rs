@@ -0,0 +1,440 @@
+use std::fs::File;
+use std::io::{Write,BufWriter};
+use desert::{ToBytes,FromBytes};
+use osmpbf_parser::{Parser,Scan,ScanTable,Element,MetaStats,Filter,PbfWriter,BBox,Extract,Region,Strategy,Polygon,
+  Closure,ExtractIds};
+use osmpbf_parser::proto::osmformat::HeaderBBox;
+use osmpbf_parser::element::nano_to_degrees;
+
+type Error = Box<dyn std::error::Error+Send+Sync+'static>;
+
+const USAGE: &str = "usage: osmpbf COMMAND FILE [ARGS] [-i INDEX]
+
+commands:
+  info FILE                      header fields, blob counts and bbox
+  blobs FILE                     offset, length, type and id range of each blob
+  cat FILE [TYPE]                print every element, or only one type of element
+  index FILE                     scan the file and save the table to INDEX
+  get FILE TYPE ID               print a node, way or relation
+  stats FILE [-i INDEX]          element counts, id and timestamp ranges, tags and compression.
+                                 with -i, only the counts stored in the index
+  tags FILE [N]                  the N most used keys, tags and key pairs (default 20)
+  meta FILE [N]                  the N most active users and largest changesets (default 20),
+                                 edits per month and the newest edit per 1 degree region
+  filter FILE OUT EXPR [-c]      write the elements matching a tag filter expression to OUT.
+                                 with -c, also the elements they reference, to any depth
+  extract FILE OUT BBOX [STRATEGY]
+                                 write the part of FILE inside BBOX (left,bottom,right,top in
+                                 degrees, or a .poly file) to OUT. STRATEGY is simple,
+                                 complete_ways (default) or smart
+  closure FILE OUT IDS [-p]      write the elements in IDS (comma separated ids with an n, w or r
+                                 prefix, like n1,w2,r3) and everything they reference to OUT.
+                                 with -p, also their parent relations
+  poly FILE ID                   print the boundary of relation ID as a .poly file
+  verify FILE                    check every blob and list the problems found
+
+INDEX is the saved scan table of FILE, FILE.index unless -i is given. get, extract, closure and
+poly use it when it exists and scan the whole file when it does not";
+
+// the arguments after the command: -c and -p flags, -i INDEX, and everything else in order
+struct Args {
+  args: Vec<String>,
+  flags: Vec<String>,
+  index: Option<String>,
+}
+
+impl Args {
+  fn parse(mut input: impl Iterator<Item=String>) -> Result<Self,Error> {
+    let mut args = Self { args: vec![], flags: vec![], index: None };
+    while let Some(arg) = input.next() {
+      match arg.as_str() {
+        "-c" | "-p" => args.flags.push(arg),
+        "-i" | "--index" => {
+          args.index = Some(input.next().ok_or_else(|| format!["{} needs an INDEX", arg])?);
+        },
+        _ => args.args.push(arg),
+      }
+    }
+    Ok(args)
+  }
+  fn get(&self, i: usize) -> &str {
+    &self.args[i]
+  }
+  // whether every flag given is one of allowed
+  fn only(&self, allowed: &[&str]) -> bool {
+    self.flags.iter().all(|f| allowed.contains(&f.as_str()))
+  }
+  fn has(&self, flag: &str) -> bool {
+    self.flags.iter().any(|f| f == flag)
+  }
+  fn index_file(&self) -> String {
+    self.index.clone().unwrap_or_else(|| format!["{}.index", self.get(0)])
+  }
+}
+
+fn main() {
+  let mut input = std::env::args().skip(1);
+  let command = input.next().unwrap_or_default();
+  let args = match Args::parse(input) {
+    Ok(args) => args,
+    Err(e) => {
+      eprintln!["osmpbf: {}\n\n{}", e, USAGE];
+      std::process::exit(2);
+    },
+  };
+  let a = |i| args.get(i);
+  let none = args.only(&[]);
+  let result = match (command.as_str(), args.args.len()) {
+    ("help",_) | ("-h",_) | ("--help",_) => {
+      println!["{}", USAGE];
+      Ok(())
+    },
+    ("info",1) if none => info(a(0)),
+    ("blobs",1) if none => blobs(a(0)),
+    ("cat",1) if none => cat(a(0), None),
+    ("cat",2) if none => cat(a(0), Some(a(1))),
+    ("index",1) if none => index(a(0), &args.index_file()),
+    ("get",3) if none => get(a(0), a(1), a(2), &args.index_file()),
+    ("stats",1) if none => stats(a(0), args.index.as_deref()),
+    ("tags",1) if none => tags(a(0), "20"),
+    ("tags",2) if none => tags(a(0), a(1)),
+    ("meta",1) if none => meta(a(0), "20"),
+    ("meta",2) if none => meta(a(0), a(1)),
+    ("filter",3) if args.only(&["-c"]) => filter(a(0), a(1), a(2), args.has("-c")),
+    ("extract",3) if none => extract(a(0), a(1), a(2), "complete_ways", &args.index_file()),
+    ("extract",4) if none => extract(a(0), a(1), a(2), a(3), &args.index_file()),
+    ("closure",3) if args.only(&["-p"]) => {
+      closure(a(0), a(1), a(2), args.has("-p"), &args.index_file())
+    },
+    ("poly",2) if none => poly(a(0), a(1), &args.index_file()),
+    ("verify",1) if none => verify(a(0)),
+    _ => {
+      eprintln!["{}", USAGE];
+      std::process::exit(2);
+    },
+  };
+  if let Err(e) = result {
+    eprintln!["osmpbf: {}", e];
+    std::process::exit(1);
+  }
+}
+
+fn open(file: &str) -> Result<(Parser<File>,u64),Error> {
+  let h = File::open(file)?;
+  let file_len = h.metadata()?.len();
+  Ok((Parser::new(Box::new(h)), file_len))
+}
+
+// load a saved scan table, or scan the whole file when there is none
+fn load_scan(file: &str, index_file: &str) -> Result<Scan<File>,Error> {
+  let (parser,file_len) = open(file)?;
+  match std::fs::read(index_file) {
+    Ok(buf) => Ok(Scan::from_table(parser, read_index(&buf, file, index_file, file_len)?)),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+      let mut scan = Scan::new(parser);
+      scan.scan(0, file_len)?;
+      Ok(scan)
+    },
+    Err(e) => Err(e.into()),
+  }
+}
+
+// an index saved for another file would point past its end
+fn read_index(buf: &[u8], file: &str, index_file: &str, file_len: u64) -> Result<ScanTable,Error> {
+  let table = ScanTable::from_bytes(buf)?.1;
+  let offsets = [
+    &table.node_interval_offsets, &table.way_interval_offsets, &table.relation_interval_offsets,
+  ];
+  let end = offsets.iter().flat_map(|offsets| offsets.values().flatten())
+    .map(|(offset,byte_len,_)| offset + *byte_len as u64)
+    .max().unwrap_or(0);
+  if end > file_len {
+    return Err(format![
+      "{} has blobs up to byte {}, past the end of {}", index_file, end, file ].into());
+  }
+  Ok(table)
+}
+
+fn element_type(element: &Element) -> &'static str {
+  match element {
+    Element::Node(_) => "node",
+    Element::Way(_) => "way",
+    Element::Relation(_) => "relation",
+    Element::Changeset(_) => "changeset",
+  }
+}
+
+fn info(file: &str) -> Result<(),Error> {
+  let (parser,file_len) = open(file)?;
+  let (mut offset,header) = parser.read_header(0)?;
+  println!["file size: {}", file_len];
+  println!["required features: {}", header.required_features.join(" ")];
+  println!["optional features: {}", header.optional_features.join(" ")];
+  if let Some(program) = &header.writingprogram {
+    println!["writing program: {}", program];
+  }
+  if let Some(source) = &header.source {
+    println!["source: {}", source];
+  }
+  if let Some(timestamp) = header.osmosis_replication_timestamp {
+    println!["replication timestamp: {}", timestamp];
+  }
+  if let Some(seq) = header.osmosis_replication_sequence_number {
+    println!["replication sequence number: {}", seq];
+  }
+  if let Some(url) = &header.osmosis_replication_base_url {
+    println!["replication base url: {}", url];
+  }
+  if let Some(bbox) = &header.bbox {
+    // header bbox values are in nanodegrees
+    println!["bbox: {},{},{},{}", nano_to_degrees(bbox.left), nano_to_degrees(bbox.bottom),
+      nano_to_degrees(bbox.right), nano_to_degrees(bbox.top)];
+  }
+  let mut blobs = 1;
+  let mut counts = [0u64;4];
+  let mut blob_counts = [0u64;4];
+  while offset < file_len {
+    let (byte_len,items) = parser.read(offset)?;
+    blobs += 1;
+    let mut seen = [false;4];
+    for item in items.iter() {
+      let i = match item {
+        Element::Node(_) => 0,
+        Element::Way(_) => 1,
+        Element::Relation(_) => 2,
+        Element::Changeset(_) => 3,
+      };
+      counts[i] += 1;
+      seen[i] = true;
+    }
+    for i in 0..4 {
+      if seen[i] { blob_counts[i] += 1 }
+    }
+    offset += byte_len;
+  }
+  println!["blobs: {}", blobs];
+  for (i,name) in ["nodes","ways","relations","changesets"].iter().enumerate() {
+    println!["{}: {} in {} blobs", name, counts[i], blob_counts[i]];
+  }
+  Ok(())
+}
+
+fn blobs(file: &str) -> Result<(),Error> {
+  let (parser,file_len) = open(file)?;
+  let mut offset = 0;
+  while offset < file_len {
+    let (byte_len,blob_header,blob) = parser.read_fileblock(offset)?;
+    if blob_header.type_pb == "OSMHeader" {
+      println!["header: offset={} byte_len={}", offset, byte_len];
+    } else {
+      let items = blob.decode_primitive()?.decode()?;
+      let etype = items.first().map(element_type).unwrap_or("empty");
+      let min_id = items.iter().map(Element::id).min().unwrap_or(0);
+      let max_id = items.iter().map(Element::id).max().unwrap_or(0);
+      println!["{}: offset={} byte_len={} items.len()={} id range {}..{}",
+        etype, offset, byte_len, items.len(), min_id, max_id];
+    }
+    offset += byte_len;
+  }
+  Ok(())
+}
+
+fn cat(file: &str, etype: Option<&str>) -> Result<(),Error> {
+  if let Some(t) = etype {
+    if !["node","way","relation","changeset"].contains(&t) {
+      return Err(format!["unknown element type {}", t].into());
+    }
+  }
+  let (parser,file_len) = open(file)?;
+  let stdout = std::io::stdout();
+  let mut out = BufWriter::new(stdout.lock());
+  let (mut offset,_) = parser.read_header(0)?;
+  while offset < file_len {
+    let (byte_len,items) = parser.read(offset)?;
+    for item in items.iter() {
+      if etype.map(|t| t == element_type(item)).unwrap_or(true) {
+        writeln![out, "{:?}", item]?;
+      }
+    }
+    offset += byte_len;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+fn index(file: &str, index_file: &str) -> Result<(),Error> {
+  let (parser,file_len) = open(file)?;
+  let mut scan = Scan::new(parser);
+  scan.scan(0, file_len)?;
+  std::fs::write(index_file, scan.table.to_bytes()?)?;
+  eprintln!["wrote {}", index_file];
+  Ok(())
+}
+
+fn get(file: &str, etype: &str, id: &str, index_file: &str) -> Result<(),Error> {
+  let id = id.parse::<i64>()?;
+  let scan = load_scan(file, index_file)?;
+  let found = match etype {
+    "node" => scan.get_node(id)?.map(|node| format!["{:?}", node]),
+    "way" => scan.get_way(id)?.map(|way| format!["{:?}", way]),
+    "relation" => scan.get_relation(id)?.map(|relation| format!["{:?}", relation]),
+    t => return Err(format!["unknown element type {}", t].into()),
+  };
+  match found {
+    Some(s) => println!["{}", s],
+    None => return Err(format!["{} {} not found", etype, id].into()),
+  }
+  Ok(())
+}
+
+fn stats(file: &str, index_file: Option<&str>) -> Result<(),Error> {
+  let (parser,file_len) = open(file)?;
+  let stats = match index_file {
+    Some(f) => read_index(&std::fs::read(f)?, file, f, file_len)?.stats(),
+    None => parser.stats(0, file_len)?,
+  };
+  println!["{}", stats];
+  Ok(())
+}
+
+fn tags(file: &str, n: &str) -> Result<(),Error> {
+  let n = n.parse::<usize>()?;
+  let (parser,file_len) = open(file)?;
+  let stats = parser.tag_stats(0, file_len, (n*10).max(10_000))?;
+  let [nodes,ways,relations] = stats.tagged_elements();
+  println!["tagged elements: {} nodes, {} ways, {} relations", nodes, ways, relations];
+  println!["\nkey\tcount\tnodes\tways\trelations\tvalues"];
+  for k in stats.keys().iter().take(n) {
+    println!["{}\t{}\t{}\t{}\t{}\t{}", k.key, k.count.total, k.count.nodes, k.count.ways,
+      k.count.relations, k.values];
+  }
+  println!["\ntag\tcount\tnodes\tways\trelations"];
+  for t in stats.tags().iter().take(n) {
+    println!["{}={}\t{}\t{}\t{}\t{}", t.key, t.value, t.count.total, t.count.nodes, t.count.ways,
+      t.count.relations];
+  }
+  println!["\nkeys\tcount"];
+  for p in stats.key_pairs().iter().take(n) {
+    println!["{} {}\t{}", p.a, p.b, p.count.total];
+  }
+  Ok(())
+}
+
+fn meta(file: &str, n: &str) -> Result<(),Error> {
+  let n = n.parse::<usize>()?;
+  let (parser,file_len) = open(file)?;
+  let stats = parser.meta_stats(0, file_len, MetaStats::new().capacity((n*10).max(10_000)))?;
+  let opt = |x: Option<i64>| x.map(|x| x.to_string()).unwrap_or_else(|| "-".into());
+  println!["uid\tuser\tedits\tnodes\tways\trelations\tchangesets\tfirst\tlast"];
+  for u in stats.users().iter().take(n) {
+    println!["{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", u.uid, u.user.as_deref().unwrap_or("-"), u.edits,
+      u.nodes, u.ways, u.relations, u.changesets, opt(u.first_timestamp), opt(u.last_timestamp)];
+  }
+  println!["\nchangeset\tuid\tedits\tnodes\tways\trelations\tfirst\tlast"];
+  for c in stats.changesets().iter().take(n) {
+    println!["{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", c.id, opt(c.uid.map(|x| x as i64)), c.edits, c.nodes,
+      c.ways, c.relations, opt(c.first_timestamp), opt(c.last_timestamp)];
+  }
+  println!["\nmonth\tedits\tnodes\tways\trelations\tusers\tchangesets"];
+  for b in stats.buckets().iter() {
+    println!["{}\t{}\t{}\t{}\t{}\t{}\t{}", b.start, b.edits, b.nodes, b.ways, b.relations, b.users,
+      b.changesets];
+  }
+  println!["\nregion\tedits\toldest\tnewest"];
+  for r in stats.regions().iter() {
+    println!["{},{}\t{}\t{}\t{}", r.bbox.left / 1_000_000_000, r.bbox.bottom / 1_000_000_000, r.edits,
+      r.oldest.timestamp, r.newest.timestamp];
+  }
+  Ok(())
+}
+
+fn filter(file: &str, output: &str, expr: &str, complete: bool) -> Result<(),Error> {
+  let mut filter = Filter::parse(expr)?;
+  let (parser,file_len) = open(file)?;
+  if complete {
+    filter.set_references(parser.references(0, file_len, &filter)?);
+  }
+  let (mut offset,mut header) = parser.read_header(0)?;
+  header.writingprogram = Some("osmpbf filter".into());
+  let mut writer = PbfWriter::with_header(BufWriter::new(File::create(output)?), header);
+  while offset < file_len {
+    let (byte_len,items) = parser.read_filtered(offset, &filter)?;
+    writer.write_all(items)?;
+    offset += byte_len;
+  }
+  writer.finish()?.flush()?;
+  Ok(())
+}
+
+fn extract(file: &str, output: &str, bbox: &str, strategy: &str, index_file: &str)
+-> Result<(),Error> {
+  let region = if bbox.ends_with(".poly") {
+    Region::Polygon(Polygon::parse_poly(&std::fs::read_to_string(bbox)?)?)
+  } else {
+    let b = bbox.split(',').map(|x| x.parse::<f64>().map(|x| (x * 1e9).round() as i64))
+      .collect::<Result<Vec<_>,_>>()?;
+    if b.len() != 4 {
+      return Err(format!["expected left,bottom,right,top for the bbox, found {}", bbox].into());
+    }
+    Region::BBox(BBox { left: b[0], bottom: b[1], right: b[2], top: b[3] })
+  };
+  let strategy = match strategy {
+    "simple" => Strategy::Simple,
+    "complete_ways" => Strategy::CompleteWays,
+    "smart" => Strategy::Smart,
+    s => return Err(format!["unknown strategy {}", s].into()),
+  };
+  let scan = load_scan(file, index_file)?;
+  let (_,mut header) = scan.parser.read_header(0)?;
+  header.writingprogram = Some("osmpbf extract".into());
+  header.bbox = region.bbox()
+    .map(|b| HeaderBBox { left: b.left, right: b.right, top: b.top, bottom: b.bottom });
+  let mut writer = PbfWriter::with_header(BufWriter::new(File::create(output)?), header);
+  let ids = Extract::new(region).strategy(strategy).write(&scan, &mut writer)?;
+  writer.finish()?.flush()?;
+  eprintln!["wrote {} nodes, {} ways and {} relations", ids.nodes.len(), ids.ways.len(),
+    ids.relations.len()];
+  Ok(())
+}
+
+fn closure(file: &str, output: &str, list: &str, parents: bool, index_file: &str)
+-> Result<(),Error> {
+  let mut ids = ExtractIds::default();
+  for x in list.split(',') {
+    let set = match x.get(..1) {
+      Some("n") => &mut ids.nodes,
+      Some("w") => &mut ids.ways,
+      Some("r") => &mut ids.relations,
+      _ => return Err(format!["expected an id like n1, w2 or r3, found {:?}", x].into()),
+    };
+    set.insert(x[1..].parse::<i64>()?);
+  }
+  let scan = load_scan(file, index_file)?;
+  let (_,mut header) = scan.parser.read_header(0)?;
+  header.writingprogram = Some("osmpbf closure".into());
+  let mut writer = PbfWriter::with_header(BufWriter::new(File::create(output)?), header);
+  let ids = Closure::new(ids).parents(parents).write(&scan, &mut writer)?;
+  writer.finish()?.flush()?;
+  eprintln!["wrote {} nodes, {} ways and {} relations", ids.nodes.len(), ids.ways.len(),
+    ids.relations.len()];
+  Ok(())
+}
+
+fn poly(file: &str, id: &str, index_file: &str) -> Result<(),Error> {
+  let scan = load_scan(file, index_file)?;
+  match scan.get_relation_polygon(id.parse::<i64>()?)? {
+    Some(polygon) => print!["{}", polygon.to_poly(id)],
+    None => return Err(format!["relation {} not found", id].into()),
+  }
+  Ok(())
+}
+
+fn verify(file: &str) -> Result<(),Error> {
+  let (parser,file_len) = open(file)?;
+  let report = parser.verify(0, file_len)?;
+  println!["{}", report];
+  if !report.is_ok() {
+    std::process::exit(1);
+  }
+  Ok(())
+}
@@ -1,88 +1,332 @@
 use crate::proto;
-use crate::proto::osmformat::{HeaderBlock,PrimitiveBlock};
-use quick_protobuf::{MessageRead,Reader};
+use crate::proto::osmformat::{HeaderBlock,PrimitiveBlock,PrimitiveGroup};
+use quick_protobuf::{MessageRead,BytesReader};
 use flate2::read::ZlibDecoder;
 use std::io::Read;
+use std::borrow::Cow;
 use crate::{element,Blob};
 
 type Error = Box<dyn std::error::Error+Send+Sync+'static>;
 
+// limits from the pbf spec. anything larger is treated as a corrupt file rather than allocated
+pub const MAX_BLOB_HEADER_SIZE: usize = 64*1024;
+pub const MAX_BLOB_SIZE: usize = 32*1024*1024;
+
+pub(crate) fn check_blob_header_size(len: usize) -> Result<(),Error> {
+  if len > MAX_BLOB_HEADER_SIZE {
+    return Err(format!["blob header size {} exceeds the limit of {} bytes", len, MAX_BLOB_HEADER_SIZE].into());
+  }
+  Ok(())
+}
+
+pub(crate) fn check_blob_size(len: i64) -> Result<usize,Error> {
+  if len < 0 || len as u64 > MAX_BLOB_SIZE as u64 {
+    return Err(format!["blob size {} is outside of the limit of {} bytes", len, MAX_BLOB_SIZE].into());
+  }
+  Ok(len as usize)
+}
+
+// why blob data could not be read, so that verify can report it under the right kind
+#[derive(Debug,Clone,PartialEq)]
+pub(crate) enum BlobError {
+  Compression(String),
+  Size(String),
+}
+
+impl std::fmt::Display for BlobError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      BlobError::Compression(msg) => write![f, "{}", msg],
+      BlobError::Size(msg) => write![f, "{}", msg],
+    }
+  }
+}
+
+impl std::error::Error for BlobError {}
+
+// the uncompressed data of a blob. raw data is borrowed
+pub(crate) fn blob_data<'a>(raw: Option<&'a [u8]>, zlib_data: Option<&[u8]>,
+lzma_data: Option<&[u8]>, raw_size: Option<i32>) -> Result<Cow<'a,[u8]>,BlobError> {
+  if let Some(data) = raw {
+    check_blob_size(data.len() as i64).map_err(|e| BlobError::Size(e.to_string()))?;
+    Ok(Cow::Borrowed(data))
+  } else if let Some(input) = zlib_data {
+    Ok(Cow::Owned(inflate(input, raw_size)?))
+  } else if lzma_data.is_some() {
+    Err(BlobError::Compression("lzma compression is not supported".into()))
+  } else {
+    Err(BlobError::Compression("blob has no data".into()))
+  }
+}
+
+// decompress at most raw_size bytes (or MAX_BLOB_SIZE when raw_size is missing)
+// and check that the output has the advertised size
+fn inflate(input: &[u8], raw_size: Option<i32>) -> Result<Vec<u8>,BlobError> {
+  let limit = match raw_size {
+    Some(n) => check_blob_size(n as i64).map_err(|e| BlobError::Size(e.to_string()))?,
+    None => MAX_BLOB_SIZE,
+  };
+  let mut data = Vec::with_capacity(limit.min(input.len().saturating_mul(8)));
+  ZlibDecoder::new(input).take(limit as u64 + 1).read_to_end(&mut data)
+    .map_err(|e| BlobError::Compression(format!["zlib: {}", e]))?;
+  if data.len() > limit {
+    return Err(BlobError::Size(format!["decompressed blob exceeds {} bytes", limit]));
+  }
+  if raw_size.is_some() && data.len() != limit {
+    return Err(BlobError::Size(format![
+      "decompressed blob is {} bytes, expected raw_size {}", data.len(), limit
+    ]));
+  }
+  Ok(data)
+}
+
+// message types with nested messages, for check_wire
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Wire {
+  Leaf,
+  HeaderBlock,
+  PrimitiveBlock,
+  PrimitiveGroup,
+  Element,
+  DenseNodes,
+}
+
+impl Wire {
+  fn field(self, number: u64) -> Option<Wire> {
+    match (self,number) {
+      (Wire::HeaderBlock,1) => Some(Wire::Leaf),
+      (Wire::PrimitiveBlock,1) => Some(Wire::Leaf),
+      (Wire::PrimitiveBlock,2) => Some(Wire::PrimitiveGroup),
+      (Wire::PrimitiveGroup,2) => Some(Wire::DenseNodes),
+      (Wire::PrimitiveGroup,_) => Some(Wire::Element),
+      (Wire::Element,4) | (Wire::Element,11) => Some(Wire::Leaf),
+      (Wire::DenseNodes,5) => Some(Wire::Leaf),
+      _ => None,
+    }
+  }
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64,Error> {
+  let mut x = 0;
+  for i in 0..10 {
+    let b = *bytes.get(*pos).ok_or("unexpected end of protobuf varint")?;
+    *pos += 1;
+    x |= ((b & 0x7f) as u64) << (7*i);
+    if b < 0x80 { return Ok(x) }
+  }
+  Err("protobuf varint is too long".into())
+}
+
+// walk the wire format and check that every length fits inside its enclosing message before
+// handing the bytes to quick-protobuf, which does not check nested lengths
+fn check_wire(bytes: &[u8], wire: Wire) -> Result<(),Error> {
+  let mut pos = 0;
+  while pos < bytes.len() {
+    let tag = read_varint(bytes, &mut pos)?;
+    let len = match tag & 7 {
+      0 => { read_varint(bytes, &mut pos)?; 0 },
+      1 => 8,
+      2 => read_varint(bytes, &mut pos)?,
+      5 => 4,
+      t => return Err(format!["unsupported protobuf wire type {}", t].into()),
+    };
+    if len > (bytes.len() - pos) as u64 {
+      return Err(format!["protobuf field {} overruns its message", tag >> 3].into());
+    }
+    let end = pos + len as usize;
+    if tag & 7 == 2 {
+      if let Some(sub) = wire.field(tag >> 3) {
+        check_wire(&bytes[pos..end], sub)?;
+      }
+    }
+    pos = end;
+  }
+  Ok(())
+}
+
+impl HeaderBlock {
+  pub fn from_bytes(data: &[u8]) -> Result<Self,Error> {
+    check_wire(data, Wire::HeaderBlock)?;
+    Ok(HeaderBlock::from_reader(&mut BytesReader::from_bytes(data), data)?)
+  }
+}
+
+impl PrimitiveBlock {
+  pub fn from_bytes(data: &[u8]) -> Result<Self,Error> {
+    check_wire(data, Wire::PrimitiveBlock)?;
+    Ok(PrimitiveBlock::from_reader(&mut BytesReader::from_bytes(data), data)?)
+  }
+}
+
 impl Blob {
   pub fn decode_header(&self) -> Result<HeaderBlock,Error> {
-    let data = self.get_data()?;
-    let header_block = Reader::from_bytes(data)
-      .read(HeaderBlock::from_reader)?;
-    Ok(header_block)
+    HeaderBlock::from_bytes(&self.get_data()?)
   }
   pub fn decode_primitive(&self) -> Result<PrimitiveBlock,Error> {
-    let data = self.get_data()?;
-    let primitive_block = Reader::from_bytes(data)
-      .read(PrimitiveBlock::from_reader)?;
-    Ok(primitive_block)
+    PrimitiveBlock::from_bytes(&self.get_data()?)
   }
   pub fn get_data(&self) -> Result<Vec<u8>,Error> {
-    if let Some(data) = &self.raw {
-      Ok(data.clone())
-    } else if let Some(input) = &self.zlib_data {
-      let mut z = ZlibDecoder::new(&input[..]);
-      let mut data = vec![];
-      z.read_to_end(&mut data)?;
-      Ok(data)
+    let data = blob_data(
+      self.raw.as_deref(), self.zlib_data.as_deref(), self.lzma_data.as_deref(), self.raw_size
+    )?;
+    Ok(data.into_owned())
+  }
+}
+
+// Blob fields borrowed from the underlying bytes, for sources that can lend out slices
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct BlobRef<'a> {
+  pub raw: Option<&'a [u8]>,
+  pub raw_size: Option<i32>,
+  pub zlib_data: Option<&'a [u8]>,
+  pub lzma_data: Option<&'a [u8]>,
+}
+
+impl<'a> MessageRead<'a> for BlobRef<'a> {
+  fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> quick_protobuf::Result<Self> {
+    let mut msg = Self::default();
+    while !r.is_eof() {
+      match r.next_tag(bytes)? {
+        10 => msg.raw = Some(r.read_bytes(bytes)?),
+        16 => msg.raw_size = Some(r.read_int32(bytes)?),
+        26 => msg.zlib_data = Some(r.read_bytes(bytes)?),
+        34 => msg.lzma_data = Some(r.read_bytes(bytes)?),
+        t => r.read_unknown(bytes, t)?,
+      }
+    }
+    Ok(msg)
+  }
+}
+
+impl<'a> BlobRef<'a> {
+  pub fn from_bytes(bytes: &'a [u8]) -> Result<Self,Error> {
+    Ok(BlobRef::from_reader(&mut BytesReader::from_bytes(bytes), bytes)?)
+  }
+  pub fn decode_header(&self) -> Result<HeaderBlock,Error> {
+    HeaderBlock::from_bytes(&self.get_data()?)
+  }
+  pub fn decode_primitive(&self) -> Result<PrimitiveBlock,Error> {
+    PrimitiveBlock::from_bytes(&self.get_data()?)
+  }
+  pub fn get_data(&self) -> Result<Cow<'a,[u8]>,Error> {
+    Ok(blob_data(self.raw, self.zlib_data, self.lzma_data, self.raw_size)?)
+  }
+}
+
+pub const REQUIRED_FEATURES: &[&str] = &["OsmSchema-V0.6","DenseNodes","HistoricalInformation"];
+pub const OPTIONAL_FEATURES: &[&str] = &["LocationsOnWays"];
+
+impl HeaderBlock {
+  pub fn has_locations_on_ways(&self) -> bool {
+    self.optional_features.iter().chain(self.required_features.iter())
+      .any(|f| f == "LocationsOnWays")
+  }
+  // history files hold every version of each element, so the same id appears many times
+  pub fn is_history(&self) -> bool {
+    self.required_features.iter().any(|f| f == "HistoricalInformation")
+  }
+  pub fn check_required_features(&self) -> Result<(),Error> {
+    let unsupported = self.required_features.iter()
+      .filter(|f| !REQUIRED_FEATURES.contains(&f.as_str()) && !OPTIONAL_FEATURES.contains(&f.as_str()))
+      .cloned()
+      .collect::<Vec<String>>();
+    if unsupported.is_empty() {
+      Ok(())
     } else {
-      panic!["unsupported compression type"];
+      Err(format!["unsupported required features: {}", unsupported.join(", ")].into())
     }
   }
 }
 
 impl PrimitiveBlock {
-  pub fn decode(&self) -> Vec<element::Element> {
+  pub fn decode(&self) -> Result<Vec<element::Element>,Error> {
     let mut elements = vec![];
     for g in self.primitivegroup.iter() {
-      for node in g.nodes.iter() {
-        elements.push(element::Element::Node(element::Node {
-          id: node.id,
-          tags: self.tags(&node.keys, &node.vals),
-          info: node.info.as_ref().map(|info| self.info(info)),
-          lon: (self.lon_offset + (self.granularity as i64 * node.lon)) as f64 * 1e-9,
-          lat: (self.lat_offset + (self.granularity as i64 * node.lat)) as f64 * 1e-9,
-        }));
+      elements.extend(self.decode_group(g)?);
+    }
+    Ok(elements)
+  }
+  pub fn decode_with_locations(&self, offset: u64)
+  -> Result<Vec<(element::ElementLocation,element::Element)>,Error> {
+    let mut elements = vec![];
+    for (group,g) in self.primitivegroup.iter().enumerate() {
+      elements.extend(self.decode_group(g)?.into_iter().enumerate().map(|(index,element)| {
+        (element::ElementLocation { offset, group, index }, element)
+      }));
+    }
+    Ok(elements)
+  }
+  // delta-coded values use wrapping arithmetic so that corrupt input decodes to garbage
+  // instead of overflowing
+  pub fn decode_group(&self, g: &PrimitiveGroup) -> Result<Vec<element::Element>,Error> {
+    let mut elements = vec![];
+    for node in g.nodes.iter() {
+      let nano_lon = self.nano_lon(node.lon);
+      let nano_lat = self.nano_lat(node.lat);
+      elements.push(element::Element::Node(element::Node {
+        id: node.id,
+        tags: self.tags(&node.keys, &node.vals)?,
+        info: node.info.as_ref().map(|info| self.info(info)).transpose()?,
+        nano_lon,
+        nano_lat,
+      }));
+    }
+    if let Some(dense) = &g.dense {
+      let n = dense.id.len();
+      if dense.lon.len() != n || dense.lat.len() != n {
+        return Err(format![
+          "dense nodes have {} ids, {} lons and {} lats", n, dense.lon.len(), dense.lat.len()
+        ].into());
       }
-      if let Some(dense) = &g.dense {
-        let mut prev_id = 0;
-        let mut prev_lon = 0;
-        let mut prev_lat = 0;
-        let mut prev_timestamp = 0;
-        let mut prev_changeset = 0;
-        let mut prev_uid = 0;
-        let mut prev_user_sid = 0;
-        let mut tag_i = 0;
-        let mut info_i = 0;
-        let z = dense.id.iter().zip(dense.lon.iter().zip(dense.lat.iter()));
-        for (d_id,(d_lon,d_lat)) in z {
-          let id = *d_id + prev_id;
-          let lon = *d_lon + prev_lon;
-          let lat = *d_lat + prev_lat;
-
-          let mut tags = vec![];
-          while tag_i+1 < dense.keys_vals.len() && dense.keys_vals[tag_i] != 0 {
-            tags.push((
-              self.get_string(dense.keys_vals[tag_i+0] as usize),
-              self.get_string(dense.keys_vals[tag_i+1] as usize),
-            ));
-            tag_i += 2;
+      if let Some(info) = &dense.denseinfo {
+        let lens = [
+          info.version.len(), info.timestamp.len(), info.changeset.len(),
+          info.uid.len(), info.user_sid.len(), info.visible.len()
+        ];
+        if lens.iter().any(|len| *len != 0 && *len != n) {
+          return Err(format!["dense info arrays {:?} do not match {} ids", lens, n].into());
+        }
+      }
+      let mut prev_id: i64 = 0;
+      let mut prev_lon: i64 = 0;
+      let mut prev_lat: i64 = 0;
+      let mut prev_timestamp: i64 = 0;
+      let mut prev_changeset: i64 = 0;
+      let mut prev_uid: i32 = 0;
+      let mut prev_user_sid: i32 = 0;
+      let mut tag_i = 0;
+      let mut info_i = 0;
+      let z = dense.id.iter().zip(dense.lon.iter().zip(dense.lat.iter()));
+      for (d_id,(d_lon,d_lat)) in z {
+        let id = d_id.wrapping_add(prev_id);
+        let lon = d_lon.wrapping_add(prev_lon);
+        let lat = d_lat.wrapping_add(prev_lat);
+
+        let mut tags = vec![];
+        while tag_i < dense.keys_vals.len() && dense.keys_vals[tag_i] != 0 {
+          if tag_i+1 >= dense.keys_vals.len() {
+            return Err("dense keys_vals ends with a key without a value".into());
           }
-          tag_i += 1;
-          let info = dense.denseinfo.as_ref().map(|info| {
-            let timestamp = info.timestamp.get(info_i).map(|x| prev_timestamp+*x);
-            let changeset = info.changeset.get(info_i).map(|x| prev_changeset+*x);
-            let uid = info.uid.get(info_i).map(|x| prev_uid+*x);
-            let user_sid = info.user_sid.get(info_i).map(|x| prev_user_sid+*x);
+          tags.push((
+            self.get_string(dense.keys_vals[tag_i] as usize)?,
+            self.get_string(dense.keys_vals[tag_i+1] as usize)?,
+          ));
+          tag_i += 2;
+        }
+        tag_i += 1;
+        let info = match dense.denseinfo.as_ref() {
+          None => None,
+          Some(info) => {
+            let timestamp = info.timestamp.get(info_i).map(|x| x.wrapping_add(prev_timestamp));
+            let changeset = info.changeset.get(info_i).map(|x| x.wrapping_add(prev_changeset));
+            let uid = info.uid.get(info_i).map(|x| x.wrapping_add(prev_uid));
+            let user_sid = info.user_sid.get(info_i).map(|x| x.wrapping_add(prev_user_sid));
             let einfo = element::Info {
               version: info.version.get(info_i).cloned().unwrap_or(0),
-              timestamp,
+              timestamp: timestamp.map(|t| self.timestamp(t)),
               changeset,
               uid,
-              user: user_sid.map(|i| self.get_string(i as usize)),
+              user: user_sid.map(|i| self.get_string(i as usize)).transpose()?,
               visible: info.visible.get(info_i).cloned(),
             };
             info_i += 1;
@@ -90,80 +334,334 @@ impl PrimitiveBlock {
             prev_changeset = changeset.unwrap_or(0);
             prev_uid = uid.unwrap_or(0);
             prev_user_sid = user_sid.unwrap_or(0);
-            einfo
-          });
-          elements.push(element::Element::Node(element::Node {
-            id: id,
-            tags,
-            info,
-            lon: (self.lon_offset + (self.granularity as i64 * lon)) as f64 * 1e-9,
-            lat: (self.lat_offset + (self.granularity as i64 * lat)) as f64 * 1e-9,
-          }));
-          prev_id = id;
-          prev_lon = lon;
-          prev_lat = lat;
-        }
-      }
-      for way in g.ways.iter() {
-        let mut refs = vec![];
-        let mut prev_ref = 0;
-        for r in way.refs.iter() {
-          //refs.push(r + prev_ref);
-          refs.push(prev_ref + *r);
-          prev_ref += r;
-        }
-        elements.push(element::Element::Way(element::Way {
-          id: way.id,
-          tags: self.tags(&way.keys, &way.vals),
-          info: way.info.as_ref().map(|info| self.info(info)),
-          refs,
+            Some(einfo)
+          },
+        };
+        let nano_lon = self.nano_lon(lon);
+        let nano_lat = self.nano_lat(lat);
+        elements.push(element::Element::Node(element::Node {
+          id,
+          tags,
+          info,
+          nano_lon,
+          nano_lat,
         }));
+        prev_id = id;
+        prev_lon = lon;
+        prev_lat = lat;
       }
-      for relation in g.relations.iter() {
-        let mut members = vec![];
-        let mut prev_mem_id = 0;
-        let z = relation.memids.iter().zip(relation.roles_sid.iter()).zip(relation.types.iter());
-        for ((mem_id,role_sid),mem_type) in z {
-          members.push(element::Member {
-            id: mem_id + prev_mem_id,
-            role: self.get_string(*role_sid as usize),
-            member_type: match mem_type {
-              proto::osmformat::mod_Relation::MemberType::NODE => element::MemberType::Node,
-              proto::osmformat::mod_Relation::MemberType::WAY => element::MemberType::Way,
-              proto::osmformat::mod_Relation::MemberType::RELATION => element::MemberType::Relation,
-            },
-          });
-          prev_mem_id = mem_id + prev_mem_id;
-        }
-        elements.push(element::Element::Relation(element::Relation {
-          id: relation.id,
-          tags: self.tags(&relation.keys, &relation.vals),
-          info: relation.info.as_ref().map(|info| self.info(info)),
-          members,
-        }));
+    }
+    for way in g.ways.iter() {
+      if way.lon.len() != way.lat.len()
+      || (!way.lon.is_empty() && way.lon.len() != way.refs.len()) {
+        return Err(format![
+          "way {} has {} refs, {} lons and {} lats",
+          way.id, way.refs.len(), way.lon.len(), way.lat.len()
+        ].into());
       }
+      let mut refs = vec![];
+      let mut prev_ref: i64 = 0;
+      for r in way.refs.iter() {
+        prev_ref = prev_ref.wrapping_add(*r);
+        refs.push(prev_ref);
+      }
+      let mut nano_coords = vec![];
+      let mut prev_lon: i64 = 0;
+      let mut prev_lat: i64 = 0;
+      for (d_lon,d_lat) in way.lon.iter().zip(way.lat.iter()) {
+        let lon = d_lon.wrapping_add(prev_lon);
+        let lat = d_lat.wrapping_add(prev_lat);
+        nano_coords.push((self.nano_lon(lon), self.nano_lat(lat)));
+        prev_lon = lon;
+        prev_lat = lat;
+      }
+      elements.push(element::Element::Way(element::Way {
+        id: way.id,
+        tags: self.tags(&way.keys, &way.vals)?,
+        info: way.info.as_ref().map(|info| self.info(info)).transpose()?,
+        refs,
+        nano_coords,
+      }));
     }
-    elements
+    for relation in g.relations.iter() {
+      if relation.memids.len() != relation.roles_sid.len()
+      || relation.memids.len() != relation.types.len() {
+        return Err(format![
+          "relation {} has {} memids, {} roles and {} types", relation.id,
+          relation.memids.len(), relation.roles_sid.len(), relation.types.len()
+        ].into());
+      }
+      let mut members = vec![];
+      let mut prev_mem_id: i64 = 0;
+      let z = relation.memids.iter().zip(relation.roles_sid.iter()).zip(relation.types.iter());
+      for ((mem_id,role_sid),mem_type) in z {
+        prev_mem_id = prev_mem_id.wrapping_add(*mem_id);
+        members.push(element::Member {
+          id: prev_mem_id,
+          role: self.get_string(*role_sid as usize)?,
+          member_type: match mem_type {
+            proto::osmformat::mod_Relation::MemberType::NODE => element::MemberType::Node,
+            proto::osmformat::mod_Relation::MemberType::WAY => element::MemberType::Way,
+            proto::osmformat::mod_Relation::MemberType::RELATION => element::MemberType::Relation,
+          },
+        });
+      }
+      elements.push(element::Element::Relation(element::Relation {
+        id: relation.id,
+        tags: self.tags(&relation.keys, &relation.vals)?,
+        info: relation.info.as_ref().map(|info| self.info(info)).transpose()?,
+        members,
+      }));
+    }
+    for changeset in g.changesets.iter() {
+      let created_at = changeset.created_at;
+      let closed_at = created_at.and_then(|t| Some(t.wrapping_add(changeset.closetime_delta?)));
+      elements.push(element::Element::Changeset(element::Changeset {
+        id: changeset.id,
+        tags: self.tags(&changeset.keys, &changeset.vals)?,
+        info: changeset.info.as_ref().map(|info| self.info(info)).transpose()?,
+        created_at: created_at.map(|t| self.timestamp(t)),
+        closed_at: closed_at.map(|t| self.timestamp(t)),
+        open: changeset.open,
+        bbox: changeset.bbox.as_ref().map(|bbox| element::BBox {
+          left: bbox.left,
+          right: bbox.right,
+          top: bbox.top,
+          bottom: bbox.bottom,
+        }),
+      }));
+    }
+    Ok(elements)
   }
-  fn tags<'a>(&self, keys: &[u32], values: &[u32]) -> element::Tags {
+  fn tags(&self, keys: &[u32], values: &[u32]) -> Result<element::Tags,Error> {
+    if keys.len() != values.len() {
+      return Err(format!["{} keys but {} values", keys.len(), values.len()].into());
+    }
     keys.iter().zip(values.iter()).map(|(ki,vi)| {
-      let key = self.get_string(*ki as usize);
-      let value = self.get_string(*vi as usize);
-      (key, value)
+      Ok((self.get_string(*ki as usize)?, self.get_string(*vi as usize)?))
     }).collect()
   }
-  fn info(&self, info: &proto::osmformat::Info) -> element::Info {
-    element::Info {
+  fn info(&self, info: &proto::osmformat::Info) -> Result<element::Info,Error> {
+    Ok(element::Info {
       version: info.version,
-      timestamp: info.timestamp,
+      timestamp: info.timestamp.map(|t| self.timestamp(t)),
       changeset: info.changeset,
       uid: info.uid,
-      user: info.user_sid.map(|i| self.get_string(i as usize)),
+      user: info.user_sid.map(|i| self.get_string(i as usize)).transpose()?,
       visible: info.visible,
+    })
+  }
+  fn nano_lon(&self, lon: i64) -> i64 {
+    self.lon_offset.wrapping_add((self.granularity as i64).wrapping_mul(lon))
+  }
+  fn nano_lat(&self, lat: i64) -> i64 {
+    self.lat_offset.wrapping_add((self.granularity as i64).wrapping_mul(lat))
+  }
+  // timestamps are stored in units of date_granularity milliseconds. convert to seconds
+  fn timestamp(&self, t: i64) -> i64 {
+    t.saturating_mul(self.date_granularity as i64).div_euclid(1000)
+  }
+  pub fn get_string(&self, i: usize) -> Result<String,Error> {
+    let s = self.stringtable.s.get(i)
+      .ok_or_else(|| format!["string index {} out of range ({} strings)", i, self.stringtable.s.len()])?;
+    Ok(String::from_utf8(s.to_vec())?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Parser,PbfWriter,element::Element};
+  use crate::test_util::{way,write,read_all};
+  use proto::osmformat::{StringTable,Node,DenseNodes,DenseInfo,Way,Info,ChangeSet,HeaderBBox};
+
+  // a block holding one group, with the default granularity and the given date_granularity
+  fn block(date_granularity: i32, group: PrimitiveGroup) -> PrimitiveBlock {
+    PrimitiveBlock {
+      stringtable: StringTable { s: vec![vec![],b"alice".to_vec()] },
+      primitivegroup: vec![group],
+      granularity: 100,
+      lat_offset: 0,
+      lon_offset: 0,
+      date_granularity,
+    }
+  }
+
+  fn timestamps(elements: &[Element]) -> Vec<Option<i64>> {
+    elements.iter().map(|e| e.info().as_ref().and_then(|info| info.timestamp)).collect()
+  }
+
+  #[test]
+  fn date_granularity() {
+    let info = |timestamp| {
+      Some(Info { version: 1, timestamp, user_sid: Some(1), ..Info::default() })
+    };
+    let group = |stamps: &[i64]| PrimitiveGroup {
+      nodes: vec![Node { id: 1, info: info(Some(stamps[0])), ..Node::default() }],
+      dense: Some(DenseNodes {
+        id: vec![2,1],
+        lat: vec![0,0],
+        lon: vec![0,0],
+        denseinfo: Some(DenseInfo {
+          version: vec![1,1],
+          // delta coded
+          timestamp: vec![stamps[1], stamps[2] - stamps[1]],
+          ..DenseInfo::default()
+        }),
+        ..DenseNodes::default()
+      }),
+      ways: vec![
+        Way { id: 1, info: info(Some(stamps[3])), ..Way::default() },
+        Way { id: 2, info: info(None), ..Way::default() },
+      ],
+      ..PrimitiveGroup::default()
+    };
+    // milliseconds, the default
+    let elements = block(1000, group(&[1_600_000_000, 0, 1, -1])).decode().unwrap();
+    assert_eq![timestamps(&elements),
+      vec![Some(1_600_000_000),Some(0),Some(1),Some(-1),None]];
+    // half seconds round down, also before the epoch
+    let elements = block(500, group(&[3_200_000_001, 1, 3, -3])).decode().unwrap();
+    assert_eq![timestamps(&elements),
+      vec![Some(1_600_000_000),Some(0),Some(1),Some(-2),None]];
+    // whole minutes
+    let elements = block(60_000, group(&[26_666_667, 1, 2, -1])).decode().unwrap();
+    assert_eq![timestamps(&elements),
+      vec![Some(1_600_000_020),Some(60),Some(120),Some(-60),None]];
+    assert_eq![elements[0].info().as_ref().unwrap().user.as_deref(), Some("alice")];
+    // out of range values saturate instead of overflowing
+    let elements = block(60_000, group(&[i64::MAX, 0, 0, 0])).decode().unwrap();
+    assert_eq![timestamps(&elements)[0], Some(i64::MAX.div_euclid(1000))];
+  }
+
+  #[test]
+  fn locations_on_ways() {
+    let group = PrimitiveGroup {
+      ways: vec![
+        // refs and coordinates are both delta coded
+        Way {
+          id: 1, refs: vec![10,1,-5], lon: vec![1000,-1,20], lat: vec![-30,5,0], ..Way::default()
+        },
+        Way { id: 2, refs: vec![7], ..Way::default() },
+      ],
+      ..PrimitiveGroup::default()
+    };
+    let mut block = block(1000, group);
+    block.lon_offset = 5;
+    block.lat_offset = -7;
+    let ways = block.decode().unwrap().into_iter().map(|e| match e {
+      Element::Way(way) => way,
+      e => panic!["expected a way, found {:?}", e],
+    }).collect::<Vec<_>>();
+    assert_eq![ways[0].refs, vec![10,11,6]];
+    assert_eq![ways[0].nano_coords, vec![
+      (100_000+5, -3000-7),
+      (99_900+5, -2500-7),
+      (101_900+5, -2500-7),
+    ]];
+    assert_eq![ways[0].coords()[0], (100_005.0 / 1e9, -3007.0 / 1e9)];
+    assert![ways[1].nano_coords.is_empty()];
+
+    // coordinates for only some refs are an error
+    block.primitivegroup[0].ways[1].lon = vec![1];
+    block.primitivegroup[0].ways[1].lat = vec![1];
+    block.primitivegroup[0].ways[0].lat.pop();
+    let err = block.decode().unwrap_err().to_string();
+    assert_eq![err, "way 1 has 3 refs, 3 lons and 2 lats"];
+    block.primitivegroup[0].ways[0].lon.pop();
+    let err = block.decode().unwrap_err().to_string();
+    assert_eq![err, "way 1 has 3 refs, 2 lons and 2 lats"];
+  }
+
+  #[test]
+  fn locations_on_ways_round_trip() {
+    // coordinates are written at 1e-7 precision, so these come back unchanged
+    let mut located = way(1, &[1,2]);
+    if let Element::Way(way) = &mut located {
+      way.nano_coords = vec![(1_234_567_800,-100),(-5_000,900)];
+    }
+    let mut writer = PbfWriter::new(vec![]);
+    writer.header_mut().unwrap().optional_features.push("LocationsOnWays".into());
+    writer.write(located.clone()).unwrap();
+    let data = writer.finish().unwrap();
+    let parser = Parser::new(Box::new(data.clone()));
+    assert![parser.read_header(0).unwrap().1.has_locations_on_ways()];
+    assert_eq![read_all(&data), vec![located.clone()]];
+    // without the header feature the coordinates are left out
+    assert_eq![read_all(&write(vec![located])), vec![way(1, &[1,2])]];
+  }
+
+  #[test]
+  fn changesets() {
+    let group = PrimitiveGroup {
+      changesets: vec![
+        ChangeSet {
+          id: 5, keys: vec![1], vals: vec![1],
+          created_at: Some(1_600_000_000), closetime_delta: Some(90), open: Some(false),
+          bbox: Some(HeaderBBox { left: -10, right: 10, top: 20, bottom: -20 }),
+          ..ChangeSet::default()
+        },
+        ChangeSet { id: 6, created_at: Some(1), open: Some(true), ..ChangeSet::default() },
+        ChangeSet { id: 7, closetime_delta: Some(1_000), ..ChangeSet::default() },
+      ],
+      ..PrimitiveGroup::default()
+    };
+    let changesets = block(1000, group).decode().unwrap().into_iter().map(|e| match e {
+      Element::Changeset(changeset) => changeset,
+      e => panic!["expected a changeset, found {:?}", e],
+    }).collect::<Vec<_>>();
+    assert_eq![changesets.len(), 3];
+    assert_eq![changesets[0].tags, vec![("alice".to_string(),"alice".to_string())]];
+    assert_eq![changesets[0].created_at, Some(1_600_000_000)];
+    assert_eq![changesets[0].closed_at, Some(1_600_000_090)];
+    assert_eq![changesets[0].open, Some(false)];
+    let bbox = crate::BBox { left: -10, right: 10, top: 20, bottom: -20 };
+    assert_eq![changesets[0].bbox, Some(bbox)];
+    // an open changeset has no closetime_delta, and a delta means nothing without created_at
+    assert_eq![(changesets[1].created_at,changesets[1].closed_at), (Some(1),None)];
+    assert_eq![(changesets[2].created_at,changesets[2].closed_at), (None,None)];
+    // the delta is added before date_granularity is applied
+    let mut block = block(500, PrimitiveGroup::default());
+    block.primitivegroup[0].changesets.push(ChangeSet {
+      id: 8, created_at: Some(3), closetime_delta: Some(2), ..ChangeSet::default()
+    });
+    match &block.decode().unwrap()[0] {
+      Element::Changeset(changeset) => {
+        assert_eq![(changeset.created_at,changeset.closed_at), (Some(1),Some(2))];
+      },
+      e => panic!["expected a changeset, found {:?}", e],
     }
   }
-  pub fn get_string(&self, i: usize) -> String {
-    let s = &self.stringtable.s[i];
-    String::from_utf8(s.to_vec()).unwrap()
+
+  #[test]
+  fn string_index_out_of_range() {
+    let decode = |group: PrimitiveGroup| block(1000, group).decode().unwrap_err().to_string();
+    let node = Node { id: 1, keys: vec![1], vals: vec![2], ..Node::default() };
+    assert_eq![decode(PrimitiveGroup { nodes: vec![node], ..PrimitiveGroup::default() }),
+      "string index 2 out of range (2 strings)"];
+    let info = Info { version: 1, user_sid: Some(9), ..Info::default() };
+    let way = Way { id: 1, info: Some(info), ..Way::default() };
+    assert_eq![decode(PrimitiveGroup { ways: vec![way], ..PrimitiveGroup::default() }),
+      "string index 9 out of range (2 strings)"];
+    let dense = DenseNodes {
+      id: vec![1], lat: vec![0], lon: vec![0], keys_vals: vec![1,3,0], ..DenseNodes::default()
+    };
+    assert_eq![decode(PrimitiveGroup { dense: Some(dense), ..PrimitiveGroup::default() }),
+      "string index 3 out of range (2 strings)"];
+  }
+
+  #[test]
+  fn dense_length_mismatch() {
+    let decode = |id: Vec<i64>, lat: Vec<i64>, lon: Vec<i64>| {
+      let dense = DenseNodes { id, lat, lon, ..DenseNodes::default() };
+      block(1000, PrimitiveGroup { dense: Some(dense), ..PrimitiveGroup::default() }).decode()
+    };
+    assert_eq![decode(vec![1,1], vec![0,0], vec![0,0]).unwrap().len(), 2];
+    assert_eq![decode(vec![1,1], vec![0,0], vec![0]).unwrap_err().to_string(),
+      "dense nodes have 2 ids, 1 lons and 2 lats"];
+    assert_eq![decode(vec![1,1], vec![0], vec![0,0]).unwrap_err().to_string(),
+      "dense nodes have 2 ids, 2 lons and 1 lats"];
+    assert_eq![decode(vec![1], vec![0,0], vec![0,0]).unwrap_err().to_string(),
+      "dense nodes have 1 ids, 2 lons and 2 lats"];
   }
 }
@@ -5,8 +5,47 @@ pub enum Element {
   Node(Node),
   Way(Way),
   Relation(Relation),
+  Changeset(Changeset),
 }
 
+impl Element {
+  pub fn id(&self) -> i64 {
+    match self {
+      Element::Node(node) => node.id,
+      Element::Way(way) => way.id,
+      Element::Relation(relation) => relation.id,
+      Element::Changeset(changeset) => changeset.id,
+    }
+  }
+  pub fn info(&self) -> &Option<Info> {
+    match self {
+      Element::Node(node) => &node.info,
+      Element::Way(way) => &way.info,
+      Element::Relation(relation) => &relation.info,
+      Element::Changeset(changeset) => &changeset.info,
+    }
+  }
+  // the type as a relation member. None for changesets
+  pub fn member_type(&self) -> Option<MemberType> {
+    match self {
+      Element::Node(_) => Some(MemberType::Node),
+      Element::Way(_) => Some(MemberType::Way),
+      Element::Relation(_) => Some(MemberType::Relation),
+      Element::Changeset(_) => None,
+    }
+  }
+}
+
+// where an element was decoded from: the offset of its fileblock (as passed to Parser::read),
+// the primitive group within that block and the element's position within the group
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub struct ElementLocation {
+  pub offset: u64,
+  pub group: usize,
+  pub index: usize,
+}
+
+// timestamp is in seconds since the unix epoch, with the block's date_granularity applied
 #[derive(Debug,Clone,PartialEq)]
 pub struct Info {
   pub version: i32,
@@ -17,21 +56,86 @@ pub struct Info {
   pub visible: Option<bool>,
 }
 
+impl Info {
+  pub fn system_time(&self) -> Option<std::time::SystemTime> {
+    let t = self.timestamp?;
+    let d = std::time::Duration::from_secs(t.unsigned_abs());
+    if t >= 0 {
+      std::time::UNIX_EPOCH.checked_add(d)
+    } else {
+      std::time::UNIX_EPOCH.checked_sub(d)
+    }
+  }
+  #[cfg(feature="chrono")]
+  pub fn chrono_timestamp(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+    use chrono::TimeZone;
+    chrono::Utc.timestamp_opt(self.timestamp?, 0).single()
+  }
+  #[cfg(feature="time")]
+  pub fn offset_date_time(&self) -> Option<time::OffsetDateTime> {
+    time::OffsetDateTime::from_unix_timestamp(self.timestamp?).ok()
+  }
+}
+
+// nano_lon and nano_lat are the exact coordinates in nanodegrees, with the block's granularity
+// and offsets applied. lon() and lat() convert them to degrees
 #[derive(Debug,Clone,PartialEq)]
 pub struct Node {
   pub id: i64,
   pub tags: Tags,
   pub info: Option<Info>,
-  pub lon: f64,
-  pub lat: f64,
+  pub nano_lon: i64,
+  pub nano_lat: i64,
+}
+
+impl Node {
+  pub fn lon(&self) -> f64 {
+    nano_to_degrees(self.nano_lon)
+  }
+  pub fn lat(&self) -> f64 {
+    nano_to_degrees(self.nano_lat)
+  }
+  pub fn lon_e7(&self) -> i32 {
+    nano_to_e7(self.nano_lon)
+  }
+  pub fn lat_e7(&self) -> i32 {
+    nano_to_e7(self.nano_lat)
+  }
+}
+
+pub fn nano_to_degrees(x: i64) -> f64 {
+  x as f64 / 1e9
+}
+
+// osm api fixed-point precision: 1e-7 degrees, rounded to nearest. values out of range for a
+// coordinate, as decoded from a corrupt file, saturate instead of overflowing
+pub fn nano_to_e7(x: i64) -> i32 {
+  round_nano_to_e7(x).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+pub(crate) fn round_nano_to_e7(x: i64) -> i64 {
+  x.saturating_add(50).div_euclid(100)
 }
 
+pub fn e7_to_degrees(x: i32) -> f64 {
+  x as f64 / 1e7
+}
+
+// nano_coords holds (lon,lat) nanodegrees for each ref when the file was written with
+// LocationsOnWays (as by osmium add-locations-to-ways). otherwise it is empty
 #[derive(Debug,Clone,PartialEq)]
 pub struct Way {
   pub id: i64,
   pub tags: Tags,
   pub info: Option<Info>,
   pub refs: Vec<i64>,
+  pub nano_coords: Vec<(i64,i64)>,
+}
+
+impl Way {
+  pub fn coords(&self) -> Vec<(f64,f64)> {
+    self.nano_coords.iter().map(|(lon,lat)| (nano_to_degrees(*lon), nano_to_degrees(*lat))).collect()
+  }
 }
 
 #[derive(Debug,Clone,PartialEq)]
@@ -49,9 +153,33 @@ pub struct Member {
   pub member_type: MemberType,
 }
 
-#[derive(Debug,Clone,PartialEq)]
+// ordered as elements are sorted in a file: nodes, then ways, then relations
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub enum MemberType {
   Node,
   Way,
   Relation,
 }
+
+// the spec gives no units for changeset times. they are read like Info::timestamp, in units of
+// the block's date_granularity, and converted to seconds since the unix epoch. closed_at is
+// created_at plus the stored closetime_delta. bbox corners are in nanodegrees
+#[derive(Debug,Clone,PartialEq)]
+pub struct Changeset {
+  pub id: i64,
+  pub tags: Tags,
+  pub info: Option<Info>,
+  pub created_at: Option<i64>,
+  pub closed_at: Option<i64>,
+  pub open: Option<bool>,
+  pub bbox: Option<BBox>,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct BBox {
+  pub left: i64,
+  pub right: i64,
+  pub top: i64,
+  pub bottom: i64,
+}
@@ -0,0 +1,412 @@
+use crate::{Scan,ReadAt,PbfWriter,Element,MemberType,BBox,Polygon,Error};
+use hashbrown::{HashMap,HashSet};
+use std::io::Write;
+
+// the area to cut out. coordinates are in nanodegrees
+#[derive(Debug,Clone,PartialEq)]
+pub enum Region {
+  BBox(BBox),
+  Polygon(Polygon),
+}
+
+impl Region {
+  pub fn contains(&self, lon: i64, lat: i64) -> bool {
+    match self {
+      Region::BBox(b) => b.contains(lon, lat),
+      Region::Polygon(p) => p.contains(lon, lat),
+    }
+  }
+  pub fn bbox(&self) -> Option<BBox> {
+    match self {
+      Region::BBox(b) => Some(*b),
+      Region::Polygon(p) => p.bbox(),
+    }
+  }
+}
+
+// which elements an extract keeps, following osmium extract:
+//
+// Simple: nodes inside the region, ways with at least one node inside (their refs are kept but
+// nodes outside are not) and relations with a member that was kept.
+// CompleteWays: as Simple, plus every node of the kept ways. relations with one of those nodes as
+// a member are kept too.
+// Smart: as CompleteWays, plus every member way and node of the kept multipolygon relations, and
+// the nodes of those ways.
+//
+// relations that have a kept relation as a member are kept too, to any depth
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Strategy {
+  Simple,
+  CompleteWays,
+  Smart,
+}
+
+// ids of the elements an extract keeps
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct ExtractIds {
+  pub nodes: HashSet<i64>,
+  pub ways: HashSet<i64>,
+  pub relations: HashSet<i64>,
+}
+
+pub struct Extract {
+  region: Region,
+  bbox: Option<BBox>,
+  strategy: Strategy,
+}
+
+impl Extract {
+  // uses the CompleteWays strategy unless set with strategy()
+  pub fn new(region: Region) -> Self {
+    let bbox = region.bbox();
+    Self { region, bbox, strategy: Strategy::CompleteWays }
+  }
+  pub fn strategy(mut self, strategy: Strategy) -> Self {
+    self.strategy = strategy;
+    self
+  }
+  pub fn contains(&self, lon: i64, lat: i64) -> bool {
+    self.bbox.map(|b| b.contains(lon, lat)).unwrap_or(false) && self.region.contains(lon, lat)
+  }
+  // resolve which elements to keep. each pass only reads the blobs the scan table lists for one
+  // element type, so scan must cover the whole file
+  pub fn ids<F: ReadAt>(&self, scan: &Scan<F>) -> Result<ExtractIds,Error> {
+    let mut ids = ExtractIds::default();
+    // nodes inside the region
+    for_each(scan, scan.get_node_blob_offsets(), |e| {
+      if let Element::Node(node) = e {
+        if self.contains(node.nano_lon, node.nano_lat) { ids.nodes.insert(node.id); }
+      }
+    })?;
+    // ways with a node inside, and the nodes they need
+    let mut way_nodes = HashSet::new();
+    for_each(scan, scan.get_way_blob_offsets(), |e| {
+      if let Element::Way(way) = e {
+        if way.refs.iter().any(|r| ids.nodes.contains(r)) {
+          ids.ways.insert(way.id);
+          if self.strategy != Strategy::Simple {
+            way_nodes.extend(way.refs.iter().copied());
+          }
+        }
+      }
+    })?;
+    ids.nodes.extend(way_nodes.drain());
+    // relations with a kept member, and the members of multipolygons. parents of every relation
+    // with member relations, to follow them from the kept ones
+    let mut member_ways = HashSet::new();
+    let mut parents: HashMap<i64,Vec<i64>> = HashMap::new();
+    for_each(scan, scan.get_relation_blob_offsets(), |e| {
+      if let Element::Relation(relation) = e {
+        for m in relation.members.iter().filter(|m| m.member_type == MemberType::Relation) {
+          parents.entry(m.id).or_default().push(relation.id);
+        }
+        let keep = relation.members.iter().any(|m| match m.member_type {
+          MemberType::Node => ids.nodes.contains(&m.id),
+          MemberType::Way => ids.ways.contains(&m.id),
+          MemberType::Relation => false,
+        });
+        if !keep { return }
+        ids.relations.insert(relation.id);
+        let multipolygon = relation.tags.iter().any(|(k,v)| k == "type" && v == "multipolygon");
+        if self.strategy == Strategy::Smart && multipolygon {
+          for m in relation.members.iter() {
+            match m.member_type {
+              MemberType::Node => { way_nodes.insert(m.id); },
+              MemberType::Way if !ids.ways.contains(&m.id) => { member_ways.insert(m.id); },
+              _ => {},
+            }
+          }
+        }
+      }
+    })?;
+    let mut pending = ids.relations.iter().copied().collect::<Vec<_>>();
+    while let Some(id) = pending.pop() {
+      for parent in parents.get(&id).into_iter().flatten() {
+        if ids.relations.insert(*parent) { pending.push(*parent) }
+      }
+    }
+    drop(parents);
+    // multipolygon member ways outside the region, looked up by id
+    let offsets = member_ways.iter().flat_map(|id| scan.get_way_blob_offsets_for_id(*id));
+    for_each(scan, offsets, |e| {
+      if let Element::Way(way) = e {
+        if member_ways.contains(&way.id) {
+          ids.ways.insert(way.id);
+          way_nodes.extend(way.refs.iter().copied());
+        }
+      }
+    })?;
+    ids.nodes.extend(way_nodes);
+    Ok(ids)
+  }
+  // write the kept elements to writer in file order
+  pub fn write<F: ReadAt, W: Write>(&self, scan: &Scan<F>, writer: &mut PbfWriter<W>)
+  -> Result<ExtractIds,Error> {
+    let ids = self.ids(scan)?;
+    let offsets = scan.get_node_blob_offsets()
+      .chain(scan.get_way_blob_offsets())
+      .chain(scan.get_relation_blob_offsets());
+    write_ids(scan, &ids, offsets, writer)?;
+    Ok(ids)
+  }
+}
+
+// the elements with the given ids and everything they reference: the nodes of ways and the members
+// of relations, followed through member relations to any depth. parent relations of the given
+// elements can be added too, without their members. only the blobs that the scan table lists for
+// the wanted ids are read, except for the parents pass which reads every relation blob
+pub struct Closure {
+  ids: ExtractIds,
+  parents: bool,
+}
+
+impl Closure {
+  // parent relations are left out unless set with parents()
+  pub fn new(ids: ExtractIds) -> Self {
+    Self { ids, parents: false }
+  }
+  pub fn parents(mut self, parents: bool) -> Self {
+    self.parents = parents;
+    self
+  }
+  // resolve the closure. ids that are not in the file are dropped
+  pub fn ids<F: ReadAt>(&self, scan: &Scan<F>) -> Result<ExtractIds,Error> {
+    let mut ids = ExtractIds::default();
+    let mut nodes = self.ids.nodes.clone();
+    let mut ways = self.ids.ways.clone();
+    // relations, one level of members at a time
+    let mut pending = self.ids.relations.clone();
+    while !pending.is_empty() {
+      let offsets = pending.iter().flat_map(|id| scan.get_relation_blob_offsets_for_id(*id));
+      let mut next = HashSet::new();
+      for_each(scan, offsets, |e| {
+        if let Element::Relation(relation) = e {
+          if !pending.contains(&relation.id) || !ids.relations.insert(relation.id) { return }
+          for m in relation.members.iter() {
+            match m.member_type {
+              MemberType::Node => { nodes.insert(m.id); },
+              MemberType::Way => { ways.insert(m.id); },
+              MemberType::Relation => { next.insert(m.id); },
+            }
+          }
+        }
+      })?;
+      next.retain(|id| !ids.relations.contains(id));
+      pending = next;
+    }
+    if self.parents {
+      let mut parents = vec![];
+      for_each(scan, scan.get_relation_blob_offsets(), |e| {
+        if let Element::Relation(relation) = e {
+          if relation.members.iter().any(|m| match m.member_type {
+            MemberType::Node => self.ids.nodes.contains(&m.id),
+            MemberType::Way => self.ids.ways.contains(&m.id),
+            MemberType::Relation => self.ids.relations.contains(&m.id),
+          }) {
+            parents.push(relation.id);
+          }
+        }
+      })?;
+      ids.relations.extend(parents);
+    }
+    let offsets = ways.iter().flat_map(|id| scan.get_way_blob_offsets_for_id(*id));
+    for_each(scan, offsets, |e| {
+      if let Element::Way(way) = e {
+        if ways.contains(&way.id) {
+          ids.ways.insert(way.id);
+          nodes.extend(way.refs.iter().copied());
+        }
+      }
+    })?;
+    let offsets = nodes.iter().flat_map(|id| scan.get_node_blob_offsets_for_id(*id));
+    for_each(scan, offsets, |e| {
+      if let Element::Node(node) = e {
+        if nodes.contains(&node.id) { ids.nodes.insert(node.id); }
+      }
+    })?;
+    Ok(ids)
+  }
+  // write the closure to writer in file order
+  pub fn write<F: ReadAt, W: Write>(&self, scan: &Scan<F>, writer: &mut PbfWriter<W>)
+  -> Result<ExtractIds,Error> {
+    let ids = self.ids(scan)?;
+    let offsets = ids.nodes.iter().flat_map(|id| scan.get_node_blob_offsets_for_id(*id))
+      .chain(ids.ways.iter().flat_map(|id| scan.get_way_blob_offsets_for_id(*id)))
+      .chain(ids.relations.iter().flat_map(|id| scan.get_relation_blob_offsets_for_id(*id)));
+    write_ids(scan, &ids, offsets, writer)?;
+    Ok(ids)
+  }
+}
+
+fn write_ids<F: ReadAt, W: Write>(scan: &Scan<F>, ids: &ExtractIds,
+offsets: impl Iterator<Item=(u64,usize,usize)>, writer: &mut PbfWriter<W>) -> Result<(),Error> {
+  let mut result = Ok(());
+  for_each(scan, offsets, |e| {
+    let keep = match e {
+      Element::Node(node) => ids.nodes.contains(&node.id),
+      Element::Way(way) => ids.ways.contains(&way.id),
+      Element::Relation(relation) => ids.relations.contains(&relation.id),
+      Element::Changeset(_) => false,
+    };
+    if keep && result.is_ok() {
+      result = writer.write(e.clone());
+    }
+  })?;
+  result
+}
+
+// decode each listed blob once, in file order
+pub(crate) fn for_each<F: ReadAt>(scan: &Scan<F>, offsets: impl Iterator<Item=(u64,usize,usize)>,
+mut f: impl FnMut(&Element)) -> Result<(),Error> {
+  let mut offsets = offsets.map(|(offset,len,_)| (offset,len)).collect::<Vec<_>>();
+  offsets.sort_unstable();
+  offsets.dedup();
+  for (offset,len) in offsets {
+    scan.parser.read_primitive(offset, len)?.decode()?.iter().for_each(&mut f);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Ring;
+  use crate::test_util::{DEG,node,way,relation,scan,read_all};
+
+  type Data = Scan<Vec<u8>>;
+
+  fn ids(nodes: &[i64], ways: &[i64], relations: &[i64]) -> ExtractIds {
+    ExtractIds {
+      nodes: nodes.iter().copied().collect(),
+      ways: ways.iter().copied().collect(),
+      relations: relations.iter().copied().collect(),
+    }
+  }
+
+  // nodes 1 and 4 are inside the 0..10 degree box. way 1 crosses its edge, way 3 is a closed ring
+  // outside of it that shares multipolygon 1 with way 1. relation 6 only has node 2 of way 1, and
+  // relations 7 and 8 are parents of relation 3 and of each other
+  fn extract_data() -> Data {
+    use MemberType::{Node as N,Way as W,Relation as R};
+    scan(vec![
+      node(1,5*DEG,5*DEG), node(2,15*DEG,5*DEG), node(3,20*DEG,5*DEG), node(4,5*DEG,6*DEG),
+      node(5,30*DEG,30*DEG), node(6,31*DEG,30*DEG), node(7,31*DEG,31*DEG), node(8,40*DEG,40*DEG),
+      way(1,&[1,2]), way(2,&[2,3]), way(3,&[5,6,7,5]), way(4,&[4]),
+      relation(1,"multipolygon",&[(W,1),(W,3)]),
+      relation(2,"route",&[(N,1),(W,2)]),
+      relation(3,"collection",&[(R,1)]),
+      relation(4,"route",&[(W,2)]),
+      relation(5,"multipolygon",&[(W,2),(N,8)]),
+      relation(6,"route",&[(N,2)]),
+      relation(7,"collection",&[(R,3),(R,8)]),
+      relation(8,"collection",&[(R,7)]),
+    ])
+  }
+  fn bbox() -> Region {
+    Region::BBox(BBox { left: 0, bottom: 0, right: 10*DEG, top: 10*DEG })
+  }
+
+  #[test]
+  fn simple() {
+    let extract = Extract::new(bbox()).strategy(Strategy::Simple);
+    assert_eq![extract.ids(&extract_data()).unwrap(), ids(&[1,4], &[1,4], &[1,2,3,7,8])];
+  }
+
+  #[test]
+  fn complete_ways() {
+    let extract = Extract::new(bbox());
+    assert_eq![extract.ids(&extract_data()).unwrap(), ids(&[1,2,4], &[1,4], &[1,2,3,6,7,8])];
+  }
+
+  #[test]
+  fn smart() {
+    let extract = Extract::new(bbox()).strategy(Strategy::Smart);
+    let expected = ids(&[1,2,4,5,6,7], &[1,3,4], &[1,2,3,6,7,8]);
+    assert_eq![extract.ids(&extract_data()).unwrap(), expected];
+  }
+
+  #[test]
+  fn polygon_hole() {
+    let square = |left: i64, bottom: i64, right: i64, top: i64, hole| {
+      Ring::new(vec![(left,bottom),(right,bottom),(right,top),(left,top)], hole)
+    };
+    let region = Region::Polygon(Polygon::new(vec![
+      square(0, 0, 10*DEG, 10*DEG, false),
+      square(4*DEG, 11*DEG/2, 7*DEG, 7*DEG, true),
+    ]));
+    // node 4 at (5,6) is in the hole, node 1 at (5,5) is not
+    let extract = Extract::new(region).strategy(Strategy::Simple);
+    assert_eq![extract.ids(&extract_data()).unwrap(), ids(&[1], &[1], &[1,2,3,7,8])];
+  }
+
+  #[test]
+  fn write() {
+    let data = extract_data();
+    let mut writer = PbfWriter::new(vec![]);
+    let expected = Extract::new(bbox()).strategy(Strategy::Smart).write(&data, &mut writer).unwrap();
+    assert_eq![written(writer.finish().unwrap()), expected];
+  }
+
+  // ids of the elements in a written file, each of which must appear once
+  fn written(out: Vec<u8>) -> ExtractIds {
+    let mut written = ExtractIds::default();
+    for e in read_all(&out) {
+      let set = match e.member_type().unwrap() {
+        MemberType::Node => &mut written.nodes,
+        MemberType::Way => &mut written.ways,
+        MemberType::Relation => &mut written.relations,
+      };
+      assert![set.insert(e.id())];
+    }
+    written
+  }
+
+  // relations 1 and 2 are members of each other. relation 2 also lists a way and a relation that
+  // are not in the file, relation 4 is a parent of relation 1 and relation 5 of way 2
+  fn closure_data() -> Data {
+    use MemberType::{Node as N,Way as W,Relation as R};
+    scan(vec![
+      node(1,0,0), node(2,DEG,0), node(3,2*DEG,0), node(4,3*DEG,0), node(5,4*DEG,0),
+      way(1,&[1,2]), way(2,&[5]),
+      relation(1,"collection",&[(R,2),(W,1)]),
+      relation(2,"collection",&[(R,1),(N,3),(R,99),(W,98)]),
+      relation(3,"collection",&[(N,4)]),
+      relation(4,"collection",&[(R,1)]),
+      relation(5,"route",&[(W,2)]),
+    ])
+  }
+
+  #[test]
+  fn closure() {
+    let data = closure_data();
+    let closure = Closure::new(ids(&[], &[], &[1,100]));
+    assert_eq![closure.ids(&data).unwrap(), ids(&[1,2,3], &[1], &[1,2])];
+    let closure = closure.parents(true);
+    assert_eq![closure.ids(&data).unwrap(), ids(&[1,2,3], &[1], &[1,2,4])];
+    // starting from the other relation of the cycle gives the same members
+    let closure = Closure::new(ids(&[], &[], &[2]));
+    assert_eq![closure.ids(&data).unwrap(), ids(&[1,2,3], &[1], &[1,2])];
+  }
+
+  #[test]
+  fn closure_parents() {
+    let data = closure_data();
+    let closure = Closure::new(ids(&[4], &[2], &[]));
+    assert_eq![closure.ids(&data).unwrap(), ids(&[4,5], &[2], &[])];
+    // parents are added without their members
+    let closure = closure.parents(true);
+    assert_eq![closure.ids(&data).unwrap(), ids(&[4,5], &[2], &[3,5])];
+    let closure = Closure::new(ids(&[6], &[3], &[])).parents(true);
+    assert_eq![closure.ids(&data).unwrap(), ExtractIds::default()];
+  }
+
+  #[test]
+  fn closure_write() {
+    let data = closure_data();
+    let mut writer = PbfWriter::new(vec![]);
+    let closure = Closure::new(ids(&[], &[], &[1])).parents(true);
+    let expected = closure.write(&data, &mut writer).unwrap();
+    assert_eq![expected, ids(&[1,2,3], &[1], &[1,2,4])];
+    assert_eq![written(writer.finish().unwrap()), expected];
+  }
+}
@@ -0,0 +1,500 @@
+use crate::{Parser,ReadAt,Element,Member,MemberType,Error};
+use crate::proto::osmformat::{PrimitiveBlock,PrimitiveGroup};
+use hashbrown::{HashMap,HashSet};
+
+// a key or value pattern. a leading or trailing * matches any prefix or suffix, both match any
+// substring, and * alone matches anything
+#[derive(Debug,Clone)]
+enum Pattern {
+  Any,
+  Exact(String),
+  Prefix(String),
+  Suffix(String),
+  Contains(String),
+  #[cfg(feature="regex")]
+  Regex(regex::Regex),
+}
+
+impl Pattern {
+  fn parse(s: &str) -> Self {
+    if s == "*" {
+      Pattern::Any
+    } else if let Some(p) = s.strip_prefix('*').and_then(|p| p.strip_suffix('*')) {
+      Pattern::Contains(p.to_string())
+    } else if let Some(p) = s.strip_suffix('*') {
+      Pattern::Prefix(p.to_string())
+    } else if let Some(p) = s.strip_prefix('*') {
+      Pattern::Suffix(p.to_string())
+    } else {
+      Pattern::Exact(s.to_string())
+    }
+  }
+  fn matches(&self, s: &str) -> bool {
+    match self {
+      Pattern::Any => true,
+      Pattern::Exact(x) => s == x,
+      Pattern::Prefix(x) => s.starts_with(x.as_str()),
+      Pattern::Suffix(x) => s.ends_with(x.as_str()),
+      Pattern::Contains(x) => s.contains(x.as_str()),
+      #[cfg(feature="regex")]
+      Pattern::Regex(r) => r.is_match(s),
+    }
+  }
+}
+
+#[derive(Debug,Clone)]
+enum Op {
+  // the key is present
+  Exists,
+  // the key is present with a value matching one of the patterns
+  Eq(Vec<Pattern>),
+  // the key is present with a value matching none of the patterns
+  Ne(Vec<Pattern>),
+}
+
+#[derive(Debug,Clone)]
+struct Expr {
+  // node, way, relation
+  types: [bool;3],
+  negate: bool,
+  key: Pattern,
+  op: Op,
+}
+
+impl Expr {
+  fn parse(s: &str) -> Result<Self,Error> {
+    let mut types = [true;3];
+    let mut rest = s;
+    if let Some(i) = s.find('/') {
+      let t = &s[..i];
+      if !t.is_empty() && t.chars().all(|c| "nwra".contains(c)) {
+        types = [t.contains('n'), t.contains('w'), t.contains('r')];
+        if t.contains('a') { types = [true;3] }
+        rest = &s[i+1..];
+      }
+    }
+    let negate = rest.starts_with('!');
+    if negate { rest = &rest[1..] }
+    // the key ends at the first operator
+    let (key,op) = match rest.find(['=','~','!']) {
+      None => (rest, Op::Exists),
+      Some(i) => {
+        let (key,op) = rest.split_at(i);
+        let op = if let Some(v) = op.strip_prefix("!=") {
+          Op::Ne(v.split(',').map(Pattern::parse).collect())
+        } else if let Some(v) = op.strip_prefix("!~") {
+          Op::Ne(vec![regex_pattern(v)?])
+        } else if let Some(v) = op.strip_prefix('=') {
+          Op::Eq(v.split(',').map(Pattern::parse).collect())
+        } else if let Some(v) = op.strip_prefix('~') {
+          Op::Eq(vec![regex_pattern(v)?])
+        } else {
+          return Err(format!["unexpected ! in filter expression {:?}", s].into());
+        };
+        (key,op)
+      },
+    };
+    if key.is_empty() {
+      return Err(format!["missing key in filter expression {:?}", s].into());
+    }
+    Ok(Self { types, negate, key: Pattern::parse(key), op })
+  }
+  fn matches_tags(&self, tags: &[(String,String)]) -> bool {
+    let m = tags.iter().any(|(k,v)| self.key.matches(k) && match &self.op {
+      Op::Exists => true,
+      Op::Eq(values) => values.iter().any(|p| p.matches(v)),
+      Op::Ne(values) => !values.iter().any(|p| p.matches(v)),
+    });
+    m != self.negate
+  }
+}
+
+#[cfg(feature="regex")]
+fn regex_pattern(s: &str) -> Result<Pattern,Error> {
+  Ok(Pattern::Regex(regex::Regex::new(s)?))
+}
+
+#[cfg(not(feature="regex"))]
+fn regex_pattern(_s: &str) -> Result<Pattern,Error> {
+  Err("regex filters need the regex feature".into())
+}
+
+// ids of the elements needed to make a filtered file referentially complete
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct References {
+  pub nodes: HashSet<i64>,
+  pub ways: HashSet<i64>,
+  pub relations: HashSet<i64>,
+}
+
+// a set of tag filter expressions separated by whitespace, in the syntax of osmium tags-filter.
+// an element matches when any expression matches it. each expression is [TYPES/][!]KEY[OP VALUE]:
+//
+//   TYPES  any of n, w, r or a (all, the default)
+//   !      negates the expression
+//   KEY    a key, which may start and/or end with * as a wildcard
+//   =v     the key has one of the comma separated values, which may also use *
+//   !=v    the key is present and has none of the values
+//   ~re    the key has a value matching the regex (with the regex feature)
+//   !~re   the key is present with a value not matching the regex
+//
+// for example: n/amenity=cafe,restaurant w/highway!=footway r/type=multipolygon
+#[derive(Debug,Clone)]
+pub struct Filter {
+  exprs: Vec<Expr>,
+  references: References,
+}
+
+impl std::str::FromStr for Filter {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    Filter::parse(s)
+  }
+}
+
+impl Filter {
+  pub fn parse(s: &str) -> Result<Self,Error> {
+    let exprs = s.split_whitespace().map(Expr::parse).collect::<Result<Vec<_>,_>>()?;
+    Ok(Self { exprs, references: References::default() })
+  }
+  // also match the elements in references, as found by Parser::references
+  pub fn set_references(&mut self, references: References) {
+    self.references = references;
+  }
+  pub fn references(&self) -> &References {
+    &self.references
+  }
+  // whether the tags of element match, ignoring references. changesets never match
+  pub fn matches_tags(&self, element: &Element) -> bool {
+    let (t,tags) = match element {
+      Element::Node(node) => (0,&node.tags),
+      Element::Way(way) => (1,&way.tags),
+      Element::Relation(relation) => (2,&relation.tags),
+      Element::Changeset(_) => return false,
+    };
+    self.exprs.iter().any(|e| e.types[t] && e.matches_tags(tags))
+  }
+  pub fn matches(&self, element: &Element) -> bool {
+    match element {
+      Element::Node(node) if self.references.nodes.contains(&node.id) => true,
+      Element::Way(way) if self.references.ways.contains(&way.id) => true,
+      Element::Relation(relation) if self.references.relations.contains(&relation.id) => true,
+      _ => self.matches_tags(element),
+    }
+  }
+  // decode only the groups of block that can hold a match and return the matching elements.
+  // a group is skipped when no expression covers its element type, or when the string table
+  // has no key for any expression and nothing in the group can be a reference
+  pub fn filter_block(&self, block: &PrimitiveBlock) -> Result<Vec<Element>,Error> {
+    let keys = self.block_keys(block);
+    let mut elements = vec![];
+    for g in block.primitivegroup.iter() {
+      if !self.group_may_match(g, &keys) { continue }
+      elements.extend(block.decode_group(g)?.into_iter().filter(|e| self.matches(e)));
+    }
+    Ok(elements)
+  }
+  // for each expression, whether the block's string table has a key it could match
+  fn block_keys(&self, block: &PrimitiveBlock) -> Vec<bool> {
+    self.exprs.iter().map(|e| {
+      e.negate || block.stringtable.s.iter()
+        .any(|s| std::str::from_utf8(s).map(|s| e.key.matches(s)).unwrap_or(false))
+    }).collect()
+  }
+  fn group_may_match(&self, g: &PrimitiveGroup, keys: &[bool]) -> bool {
+    let t = if g.dense.is_some() || !g.nodes.is_empty() {
+      if !self.references.nodes.is_empty() { return true }
+      0
+    } else if !g.ways.is_empty() {
+      if !self.references.ways.is_empty() { return true }
+      1
+    } else if !g.relations.is_empty() {
+      if !self.references.relations.is_empty() { return true }
+      2
+    } else {
+      return false;
+    };
+    self.exprs.iter().zip(keys.iter()).any(|(e,k)| e.types[t] && *k)
+  }
+}
+
+impl<F> Parser<F> where F: ReadAt {
+  // like read(), but only returns the elements matching filter and skips groups that can't match
+  pub fn read_filtered(&self, offset: u64, filter: &Filter) -> Result<(u64,Vec<Element>),Error> {
+    match self.read_block(offset)? {
+      (len,Some(block)) => Ok((len, filter.filter_block(&block)?)),
+      (len,None) => Ok((len, vec![])),
+    }
+  }
+  // find the elements referenced by the relations and ways that match filter: member relations
+  // to any depth, the member nodes and ways of all of those relations, and the nodes of the
+  // matching and member ways. this takes one pass over the relations, a second one only when
+  // member relations were found, and one over the ways
+  pub fn references(&self, start: u64, end: u64, filter: &Filter) -> Result<References,Error> {
+    let mut refs = References::default();
+    let tags_only = Filter { exprs: filter.exprs.clone(), references: References::default() };
+    let mut matched = HashSet::new();
+    // member relations of every relation, to follow them from the matching ones
+    let mut children: HashMap<i64,Vec<i64>> = HashMap::new();
+    self.each_group(start, end, |g| !g.relations.is_empty(), |e| {
+      if let Element::Relation(relation) = e {
+        let is_match = tags_only.matches_tags(e);
+        if is_match { matched.insert(relation.id); }
+        for Member { id, member_type, .. } in relation.members.iter() {
+          match member_type {
+            MemberType::Node if is_match => { refs.nodes.insert(*id); },
+            MemberType::Way if is_match => { refs.ways.insert(*id); },
+            MemberType::Relation => children.entry(relation.id).or_default().push(*id),
+            _ => {},
+          }
+        }
+      }
+    })?;
+    let mut pending = matched.iter().copied().collect::<Vec<_>>();
+    while let Some(id) = pending.pop() {
+      for child in children.get(&id).into_iter().flatten() {
+        if !matched.contains(child) && refs.relations.insert(*child) {
+          pending.push(*child);
+        }
+      }
+    }
+    drop(children);
+    if !refs.relations.is_empty() {
+      self.each_group(start, end, |g| !g.relations.is_empty(), |e| {
+        if let Element::Relation(relation) = e {
+          if !refs.relations.contains(&relation.id) { return }
+          for Member { id, member_type, .. } in relation.members.iter() {
+            match member_type {
+              MemberType::Node => { refs.nodes.insert(*id); },
+              MemberType::Way => { refs.ways.insert(*id); },
+              MemberType::Relation => {},
+            }
+          }
+        }
+      })?;
+    }
+    let member_ways = std::mem::take(&mut refs.ways);
+    self.each_group(start, end, |g| !g.ways.is_empty(), |e| {
+      if let Element::Way(way) = e {
+        if member_ways.contains(&way.id) || tags_only.matches_tags(e) {
+          refs.nodes.extend(way.refs.iter().copied());
+        }
+      }
+    })?;
+    refs.ways = member_ways;
+    Ok(refs)
+  }
+  fn each_group(&self, start: u64, end: u64, want: impl Fn(&PrimitiveGroup) -> bool,
+  mut f: impl FnMut(&Element)) -> Result<(),Error> {
+    for r in self.primitive_blocks(start, end) {
+      let (_,block) = r?;
+      for g in block.primitivegroup.iter().filter(|g| want(g)) {
+        block.decode_group(g)?.iter().for_each(&mut f);
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Expr,Op,Pattern,Filter,References};
+  use crate::{Parser,Element,MemberType};
+  use crate::test_util::{node,way,relation,write,read_all,bad_strings_block};
+
+  fn tags(kv: &[(&str,&str)]) -> Vec<(String,String)> {
+    kv.iter().map(|(k,v)| (k.to_string(),v.to_string())).collect()
+  }
+
+  #[test]
+  fn type_prefixes() {
+    assert_eq![Expr::parse("amenity").unwrap().types, [true,true,true]];
+    assert_eq![Expr::parse("n/amenity").unwrap().types, [true,false,false]];
+    assert_eq![Expr::parse("wr/amenity").unwrap().types, [false,true,true]];
+    assert_eq![Expr::parse("na/amenity").unwrap().types, [true,true,true]];
+    // not a type prefix, so the slash is part of the key
+    let e = Expr::parse("x/y=z").unwrap();
+    assert_eq![e.types, [true,true,true]];
+    assert![e.matches_tags(&tags(&[("x/y","z")]))];
+  }
+
+  #[test]
+  fn operators() {
+    let e = Expr::parse("highway=primary,secondary").unwrap();
+    assert![matches![e.op, Op::Eq(ref v) if v.len() == 2]];
+    assert![e.matches_tags(&tags(&[("highway","secondary")]))];
+    assert![!e.matches_tags(&tags(&[("highway","footway")]))];
+
+    let e = Expr::parse("highway!=footway,path").unwrap();
+    assert![matches![e.op, Op::Ne(_)]];
+    assert![e.matches_tags(&tags(&[("highway","primary")]))];
+    assert![!e.matches_tags(&tags(&[("highway","path")]))];
+    assert![!e.matches_tags(&tags(&[("name","x")]))];
+
+    let e = Expr::parse("!highway").unwrap();
+    assert![e.negate && matches![e.op, Op::Exists]];
+    assert![e.matches_tags(&tags(&[("name","x")]))];
+    assert![!e.matches_tags(&tags(&[("highway","x")]))];
+
+    // the key ends at the first operator, so values may hold operator characters
+    let e = Expr::parse("note=a~b!=c").unwrap();
+    assert![e.matches_tags(&tags(&[("note","a~b!=c")]))];
+  }
+
+  #[cfg(feature="regex")]
+  #[test]
+  fn regex_operators() {
+    let e = Expr::parse("name~^Ber").unwrap();
+    assert![e.matches_tags(&tags(&[("name","Berlin")]))];
+    assert![!e.matches_tags(&tags(&[("name","Hamburg")]))];
+    let e = Expr::parse("name!~^Ber").unwrap();
+    assert![matches![e.op, Op::Ne(_)]];
+    assert![!e.matches_tags(&tags(&[("name","Berlin")]))];
+    assert![e.matches_tags(&tags(&[("name","Hamburg")]))];
+  }
+
+  #[cfg(not(feature="regex"))]
+  #[test]
+  fn regex_operators() {
+    assert![Expr::parse("name~^Ber").is_err()];
+    assert![Expr::parse("name!~^Ber").is_err()];
+  }
+
+  #[test]
+  fn patterns() {
+    assert![matches![Pattern::parse("*"), Pattern::Any]];
+    assert![matches![Pattern::parse("name"), Pattern::Exact(ref x) if x == "name"]];
+    assert![matches![Pattern::parse("name:*"), Pattern::Prefix(ref x) if x == "name:"]];
+    assert![matches![Pattern::parse("*:en"), Pattern::Suffix(ref x) if x == ":en"]];
+    assert![matches![Pattern::parse("*name*"), Pattern::Contains(ref x) if x == "name"]];
+    let e = Expr::parse("*name*=*berg*").unwrap();
+    assert![e.matches_tags(&tags(&[("old_name_1","Heidelberg")]))];
+    assert![e.matches_tags(&tags(&[("name","bergen")]))];
+    assert![!e.matches_tags(&tags(&[("old_nam","Heidelberg")]))];
+    assert![!e.matches_tags(&tags(&[("name","Hamburg")]))];
+    let e = Expr::parse("name:*=*").unwrap();
+    assert![e.matches_tags(&tags(&[("name:de","x")]))];
+    assert![!e.matches_tags(&tags(&[("name","x")]))];
+  }
+
+  #[test]
+  fn errors() {
+    assert![Expr::parse("!").is_err()];
+    assert![Expr::parse("a!b").is_err()];
+    assert![Expr::parse("=value").is_err()];
+    assert![Expr::parse("n/=value").is_err()];
+    assert![Expr::parse("!=value").is_err()];
+  }
+
+  fn tagged(mut element: Element, kv: &[(&str,&str)]) -> Element {
+    match &mut element {
+      Element::Node(node) => node.tags.extend(tags(kv)),
+      Element::Way(way) => way.tags.extend(tags(kv)),
+      Element::Relation(relation) => relation.tags.extend(tags(kv)),
+      Element::Changeset(changeset) => changeset.tags.extend(tags(kv)),
+    }
+    element
+  }
+
+  fn ids(ids: &[i64]) -> hashbrown::HashSet<i64> {
+    ids.iter().copied().collect()
+  }
+
+  fn references(data: &[u8], expr: &str) -> References {
+    let parser = Parser::new(Box::new(data.to_vec()));
+    parser.references(0, data.len() as u64, &Filter::parse(expr).unwrap()).unwrap()
+  }
+
+  fn filtered(data: &[u8], filter: &Filter) -> Vec<Element> {
+    let parser = Parser::new(Box::new(data.to_vec()));
+    let mut offset = parser.read_header(0).unwrap().0;
+    let mut elements = vec![];
+    while offset < data.len() as u64 {
+      let (len,items) = parser.read_filtered(offset, filter).unwrap();
+      elements.extend(items);
+      offset += len;
+    }
+    elements
+  }
+
+  #[test]
+  fn member_relations() {
+    use MemberType::{Node as N,Way as W,Relation as R};
+    let data = write(vec![
+      node(1, 0, 0), node(2, 0, 0), node(3, 0, 0), node(4, 0, 0), node(5, 0, 0),
+      way(10, &[1,2]), way(11, &[3]), way(12, &[4]),
+      tagged(way(13, &[5]), &[("route","bus")]),
+      // 1 -> 2 -> 3 -> 1 is a cycle through the matching relation
+      tagged(relation(1, "route", &[(R,2)]), &[("route","bus")]),
+      relation(2, "route", &[(R,3),(W,10)]),
+      relation(3, "route", &[(R,1),(N,3)]),
+      // 4 <-> 5 is a cycle that nothing matching refers to
+      relation(4, "route", &[(R,5),(W,11)]),
+      relation(5, "route", &[(R,4),(W,12)]),
+      // a member of 3 that matches on its own
+      tagged(relation(6, "route", &[(R,3)]), &[("route","bus")]),
+    ]);
+    let refs = references(&data, "route=bus");
+    assert_eq![refs.relations, ids(&[2,3])];
+    assert_eq![refs.ways, ids(&[10])];
+    // the nodes of member ways and of matching ways
+    assert_eq![refs.nodes, ids(&[1,2,3,5])];
+    // without ways in the filter, way 13 and its node are left out
+    let relations_only = references(&data, "r/route=bus");
+    assert_eq![(&relations_only.relations,&relations_only.ways), (&refs.relations,&refs.ways)];
+    assert_eq![relations_only.nodes, ids(&[1,2,3])];
+    assert_eq![references(&data, "n/route=bus"), References::default()];
+
+    let mut filter = Filter::parse("route=bus").unwrap();
+    filter.set_references(refs);
+    let kept = filtered(&data, &filter).iter().map(|e| (e.member_type(),e.id()))
+      .collect::<Vec<_>>();
+    assert_eq![kept, vec![
+      (Some(N),1), (Some(N),2), (Some(N),3), (Some(N),5), (Some(W),10), (Some(W),13),
+      (Some(R),1), (Some(R),2), (Some(R),3), (Some(R),6),
+    ]];
+  }
+
+  #[test]
+  fn filtered_file() {
+    let mut elements = (1..=300).map(|i| {
+      let n = node(i, 0, 0);
+      if i % 7 == 0 { tagged(n, &[("amenity","cafe")]) } else { n }
+    }).collect::<Vec<_>>();
+    elements.push(tagged(way(1, &[1,2]), &[("amenity","cafe")]));
+    elements.push(tagged(way(2, &[2,3]), &[("highway","primary")]));
+    let mut writer = crate::PbfWriter::new(vec![]);
+    writer.set_block_size(100);
+    writer.write_all(elements).unwrap();
+    let data = writer.finish().unwrap();
+    for expr in ["amenity=cafe", "n/amenity", "w/highway", "!amenity", "name"] {
+      let filter = Filter::parse(expr).unwrap();
+      let expected = read_all(&data).into_iter().filter(|e| filter.matches(e)).collect::<Vec<_>>();
+      assert_eq![filtered(&data, &filter), expected, "{}", expr];
+    }
+    assert_eq![filtered(&data, &Filter::parse("n/amenity").unwrap()).len(), 42];
+  }
+
+  // the group in bad_strings_block fails to decode, so a filter that gets back no error has
+  // skipped it
+  #[test]
+  fn skipped_groups() {
+    let mut data = write(vec![]);
+    data.extend(bad_strings_block());
+    let parser = Parser::new(Box::new(data.clone()));
+    let offset = parser.read_header(0).unwrap().0;
+    let read = |filter: &Filter| parser.read_filtered(offset, filter).map(|(_,items)| items);
+    // no expression covers nodes
+    assert![read(&Filter::parse("w/amenity r/type").unwrap()).unwrap().is_empty()];
+    // the string table has no key the expression could match
+    assert![read(&Filter::parse("amenity n/name*").unwrap()).unwrap().is_empty()];
+    assert![read(&Filter::parse("n/amenity w/*").unwrap()).unwrap().is_empty()];
+    // a negated expression or a node reference needs the group decoded
+    assert![read(&Filter::parse("n/!amenity").unwrap()).is_err()];
+    let mut filter = Filter::parse("w/amenity").unwrap();
+    filter.set_references(References { nodes: ids(&[1]), ..References::default() });
+    assert![read(&filter).is_err()];
+    // a bare wildcard matches any key, even the empty string 0
+    assert![read(&Filter::parse("n/*").unwrap()).is_err()];
+  }
+}
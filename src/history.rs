@@ -0,0 +1,244 @@
+use crate::{Scan,ReadAt,element,Element,Error};
+
+// lookups for history files, where each id can appear once per version and the versions of
+// one id can be spread over several blobs
+
+impl<F> Scan<F> where F: ReadAt {
+  // every version of a node, sorted by version
+  pub fn get_node_versions(&self, id: i64) -> Result<Vec<element::Node>,Error> {
+    self.get_versions(
+      self.get_node_blob_offsets_for_id(id),
+      |item| match item { Element::Node(node) if node.id == id => Some(node), _ => None },
+      |node| version_of(&node.info),
+    )
+  }
+  pub fn get_way_versions(&self, id: i64) -> Result<Vec<element::Way>,Error> {
+    self.get_versions(
+      self.get_way_blob_offsets_for_id(id),
+      |item| match item { Element::Way(way) if way.id == id => Some(way), _ => None },
+      |way| version_of(&way.info),
+    )
+  }
+  pub fn get_relation_versions(&self, id: i64) -> Result<Vec<element::Relation>,Error> {
+    self.get_versions(
+      self.get_relation_blob_offsets_for_id(id),
+      |item| match item { Element::Relation(relation) if relation.id == id => Some(relation), _ => None },
+      |relation| version_of(&relation.info),
+    )
+  }
+  // the version of a node that was current at timestamp (in seconds since the epoch).
+  // None if the node did not exist yet or had been deleted at that time
+  pub fn get_node_at(&self, id: i64, timestamp: i64) -> Result<Option<element::Node>,Error> {
+    Ok(version_at(self.get_node_versions(id)?, timestamp, false, |node| &node.info))
+  }
+  pub fn get_way_at(&self, id: i64, timestamp: i64) -> Result<Option<element::Way>,Error> {
+    Ok(version_at(self.get_way_versions(id)?, timestamp, false, |way| &way.info))
+  }
+  pub fn get_relation_at(&self, id: i64, timestamp: i64) -> Result<Option<element::Relation>,Error> {
+    Ok(version_at(self.get_relation_versions(id)?, timestamp, false, |relation| &relation.info))
+  }
+  fn get_versions<T,M,V>(&self, offsets: Vec<(u64,usize,usize)>, matches: M, version: V)
+  -> Result<Vec<T>,Error> where M: Fn(Element) -> Option<T>, V: Fn(&T) -> i32 {
+    let mut versions = vec![];
+    for (offset,byte_len,_len) in offsets {
+      let items = self.parser.read_primitive(offset,byte_len)?.decode()?;
+      versions.extend(items.into_iter().filter_map(&matches));
+    }
+    versions.sort_by_key(|v| version(v));
+    Ok(versions)
+  }
+}
+
+fn version_of(info: &Option<element::Info>) -> i32 {
+  info.as_ref().map(|info| info.version).unwrap_or(0)
+}
+
+// with untimed, versions without a timestamp count as present at any time. otherwise they are
+// skipped, as for the get_*_at lookups
+fn version_at<T,I>(versions: Vec<T>, timestamp: i64, untimed: bool, info: I) -> Option<T>
+where I: Fn(&T) -> &Option<element::Info> {
+  let current = versions.into_iter()
+    .filter(|v| {
+      info(v).as_ref().and_then(|i| i.timestamp).map(|t| t <= timestamp).unwrap_or(untimed)
+    })
+    .last()?;
+  let visible = info(&current).as_ref().and_then(|i| i.visible).unwrap_or(true);
+  if visible { Some(current) } else { None }
+}
+
+// which versions a TimeFilter keeps. times are in seconds since the unix epoch
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum TimeSlice {
+  // the state of the map at a point in time: for each element, the version current at that time
+  // unless it was deleted
+  At(i64),
+  // every version that was current at some point in [start,end]: the version current at start
+  // (if it existed) and every version created up to end, deletions included
+  Between(i64,i64),
+}
+
+// filters a history file's elements, which are sorted by type, id and version, down to a time
+// slice. changesets pass through unchanged
+pub struct TimeFilter<I: Iterator<Item=Element>> {
+  elements: std::iter::Peekable<I>,
+  slice: TimeSlice,
+  output: std::collections::VecDeque<Element>,
+}
+
+impl<I> TimeFilter<I> where I: Iterator<Item=Element> {
+  pub fn new(elements: I, slice: TimeSlice) -> Self {
+    Self { elements: elements.peekable(), slice, output: std::collections::VecDeque::new() }
+  }
+  fn push_versions(&mut self, versions: Vec<Element>) {
+    match self.slice {
+      TimeSlice::At(t) => {
+        if let Some(current) = version_at(versions, t, true, Element::info) {
+          self.output.push_back(current);
+        }
+      },
+      TimeSlice::Between(start,end) => {
+        let mut current = None;
+        for v in versions {
+          let t = element_timestamp(&v);
+          if t <= start {
+            current = Some(v);
+          } else if t <= end {
+            if let Some(c) = current.take() { self.push_visible(c) }
+            self.output.push_back(v);
+          }
+        }
+        if let Some(c) = current { self.push_visible(c) }
+      },
+    }
+  }
+  fn push_visible(&mut self, element: Element) {
+    if element.info().as_ref().and_then(|i| i.visible).unwrap_or(true) {
+      self.output.push_back(element);
+    }
+  }
+}
+
+impl<I> Iterator for TimeFilter<I> where I: Iterator<Item=Element> {
+  type Item = Element;
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.output.is_empty() {
+      let first = self.elements.next()?;
+      let key = match first.member_type() {
+        Some(t) => (t,first.id()),
+        None => return Some(first),
+      };
+      let mut versions = vec![first];
+      while let Some(next) = self.elements.peek() {
+        if next.member_type() != Some(key.0) || next.id() != key.1 { break }
+        versions.extend(self.elements.next());
+      }
+      self.push_versions(versions);
+    }
+    self.output.pop_front()
+  }
+}
+
+// elements without a timestamp are treated as always present
+fn element_timestamp(element: &Element) -> i64 {
+  element.info().as_ref().and_then(|i| i.timestamp).unwrap_or(i64::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{PbfWriter,MemberType};
+  use crate::test_util::{node,way,relation,with_info,scan_data};
+
+  fn versions<T>(elements: &[T], info: impl Fn(&T) -> &Option<element::Info>) -> Vec<i32> {
+    elements.iter().map(|e| version_of(info(e))).collect()
+  }
+
+  #[test]
+  fn versions_across_blobs() {
+    let elements = vec![
+      with_info(node(4, 0, 0), 1, Some(100), true),
+      with_info(node(5, 0, 0), 1, Some(100), true),
+      with_info(node(5, 0, 0), 2, Some(200), true),
+      with_info(node(5, 0, 0), 3, Some(300), false),
+      with_info(node(5, 0, 0), 4, Some(400), true),
+      with_info(node(6, 0, 0), 1, Some(100), true),
+      with_info(way(1, &[4,5]), 1, Some(100), true),
+      with_info(way(1, &[5,6]), 2, Some(250), true),
+      with_info(relation(1, "route", &[(MemberType::Way,1)]), 1, None, true),
+      with_info(relation(1, "route", &[]), 2, Some(150), true),
+    ];
+    let mut writer = PbfWriter::new(vec![]);
+    writer.header_mut().unwrap().required_features.push("HistoricalInformation".into());
+    writer.set_block_size(2);
+    writer.write_all(elements).unwrap();
+    let scan = scan_data(writer.finish().unwrap());
+    // node 5 is spread over three blobs
+    assert_eq![scan.get_node_blob_offsets_for_id(5).len(), 3];
+    let nodes = scan.get_node_versions(5).unwrap();
+    assert_eq![versions(&nodes, |n| &n.info), vec![1,2,3,4]];
+    assert![nodes.iter().all(|n| n.id == 5)];
+    assert_eq![versions(&scan.get_node_versions(4).unwrap(), |n| &n.info), vec![1]];
+    assert_eq![versions(&scan.get_way_versions(1).unwrap(), |w| &w.info), vec![1,2]];
+    assert_eq![versions(&scan.get_relation_versions(1).unwrap(), |r| &r.info), vec![1,2]];
+    assert![scan.get_node_versions(7).unwrap().is_empty()];
+
+    let node_at = |t| scan.get_node_at(5, t).unwrap().map(|n| version_of(&n.info));
+    assert_eq![node_at(99), None];
+    assert_eq![node_at(100), Some(1)];
+    assert_eq![node_at(299), Some(2)];
+    // deleted from version 3 until version 4
+    assert_eq![node_at(300), None];
+    assert_eq![node_at(399), None];
+    assert_eq![node_at(400), Some(4)];
+    assert_eq![node_at(i64::MAX), Some(4)];
+    let way_at = |t| scan.get_way_at(1, t).unwrap().map(|w| w.refs);
+    assert_eq![way_at(249), Some(vec![4,5])];
+    assert_eq![way_at(250), Some(vec![5,6])];
+    // a version without a timestamp is never current
+    assert![scan.get_relation_at(1, 149).unwrap().is_none()];
+    let relation = scan.get_relation_at(1, 150).unwrap().unwrap();
+    assert_eq![version_of(&relation.info), 2];
+  }
+
+  fn slice(elements: &[Element], slice: TimeSlice) -> Vec<(Option<MemberType>,i64,i32)> {
+    TimeFilter::new(elements.iter().cloned(), slice)
+      .map(|e| (e.member_type(), e.id(), version_of(e.info())))
+      .collect()
+  }
+
+  #[test]
+  fn time_slices() {
+    let changeset = Element::Changeset(element::Changeset {
+      id: 99, tags: vec![], info: None, created_at: None, closed_at: None, open: None, bbox: None,
+    });
+    let elements = vec![
+      with_info(node(1, 0, 0), 1, Some(100), true),
+      with_info(node(1, 0, 0), 2, Some(200), true),
+      with_info(node(1, 0, 0), 3, Some(300), false),
+      // the first version has no timestamp
+      with_info(node(2, 0, 0), 1, None, true),
+      with_info(node(2, 0, 0), 2, Some(250), true),
+      with_info(node(3, 0, 0), 1, Some(500), true),
+      with_info(way(1, &[1,2]), 1, Some(100), true),
+      with_info(way(1, &[1,2]), 2, Some(150), false),
+      changeset,
+    ];
+    let (n,w) = (Some(MemberType::Node),Some(MemberType::Way));
+    // versions without a timestamp count as present from the start
+    assert_eq![slice(&elements, TimeSlice::At(50)), vec![(n,2,1),(None,99,0)]];
+    assert_eq![slice(&elements, TimeSlice::At(120)), vec![(n,1,1),(n,2,1),(w,1,1),(None,99,0)]];
+    assert_eq![slice(&elements, TimeSlice::At(250)), vec![(n,1,2),(n,2,2),(None,99,0)]];
+    // node 1 and way 1 stay deleted
+    assert_eq![slice(&elements, TimeSlice::At(1000)), vec![(n,2,2),(n,3,1),(None,99,0)]];
+
+    // the version current at start, then every later version up to end, deletions included
+    assert_eq![slice(&elements, TimeSlice::Between(120,400)), vec![
+      (n,1,1),(n,1,2),(n,1,3),(n,2,1),(n,2,2),(w,1,1),(w,1,2),(None,99,0),
+    ]];
+    // a version deleted by start is left out, and so is a version created after end
+    assert_eq![slice(&elements, TimeSlice::Between(150,260)), vec![
+      (n,1,1),(n,1,2),(n,2,1),(n,2,2),(None,99,0),
+    ]];
+    assert_eq![slice(&elements, TimeSlice::Between(350,400)), vec![(n,2,2),(None,99,0)]];
+  }
+}
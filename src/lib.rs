@@ -1,57 +1,309 @@
 #![doc=include_str!("../readme.md")]
-use quick_protobuf::{MessageRead,Reader};
-use std::io::{Read,Seek,SeekFrom};
+use quick_protobuf::{MessageRead,Reader,BytesReader};
 
 pub mod proto;
 pub use proto::fileformat::{Blob,BlobHeader};
+use proto::osmformat::{HeaderBlock,PrimitiveBlock};
 mod decode;
+pub use decode::{BlobRef,REQUIRED_FEATURES,OPTIONAL_FEATURES,MAX_BLOB_HEADER_SIZE,MAX_BLOB_SIZE};
+use decode::{check_blob_header_size,check_blob_size};
 pub mod element;
-pub use element::{Element,Info,Node,Way,Relation,Member,MemberType};
+pub use element::{Element,ElementLocation,Info,Node,Way,Relation,Member,MemberType,Changeset,BBox};
 mod scan;
-pub use scan::{Scan,ScanTable};
+pub use scan::{Scan,ScanTable,IntervalOffsets};
+mod history;
+pub use history::{TimeFilter,TimeSlice};
+mod write;
+pub use write::PbfWriter;
 mod scan_bytes;
+mod read_at;
+pub use read_at::{ReadAt,SeekReader};
+#[cfg(feature="mmap")] pub use read_at::MmapSource;
+mod range;
+pub use range::{RangeSource,CachedRange};
+#[cfg(feature="http")] pub use range::HttpSource;
+mod multi;
+pub use multi::MultiSource;
+mod stream;
+pub use stream::StreamParser;
+mod recover;
+pub use recover::{SkippedRange,Blocks};
+mod stats;
+pub use stats::{Stats,TypeStats,StatsCollector};
+mod top;
+mod tag_stats;
+pub use tag_stats::{TagStats,Count,KeyCount,TagCount,KeyPairCount};
+mod meta_stats;
+pub use meta_stats::{MetaStats,UserStats,ChangesetStats,BucketStats,RegionStats,Edit,TimeBucket};
+mod filter;
+pub use filter::{Filter,References};
+mod polygon;
+pub use polygon::{Polygon,Ring};
+mod extract;
+pub use extract::{Extract,ExtractIds,Region,Strategy,Closure};
+mod verify;
+pub use verify::{VerifyReport,Problem,ProblemKind};
+mod locations;
+pub use locations::{NodeLocations,SparseLocations};
+#[cfg(feature="mmap")] pub use locations::DenseLocations;
+#[cfg(feature="async")] mod async_parser;
+#[cfg(feature="async")] pub use async_parser::{AsyncParser,AsyncScan};
+#[cfg(test)] mod test_util;
 
 pub type Error = Box<dyn std::error::Error+Send+Sync+'static>;
 
-pub struct Parser<F: Read+Seek> {
+pub struct Parser<F: ReadAt> {
   handle: Box<F>,
 }
 
-impl<F> Parser<F> where F: Read+Seek {
+impl<F> Parser<F> where F: ReadAt {
   pub fn new(handle: Box<F>) -> Self {
     Self { handle }
   }
-  pub fn read_fileblock(&mut self, offset: u64) -> Result<(u64,BlobHeader,Blob),Error> {
+  pub fn read_fileblock(&self, offset: u64) -> Result<(u64,BlobHeader,Blob),Error> {
     let (s,blob_header) = self.read_blob_header(offset)?;
     let blob = self.read_blob(offset + s, blob_header.datasize as usize)?;
     Ok((s + blob_header.datasize as u64, blob_header, blob))
   }
-  pub fn read_blob_header(&mut self, offset: u64) -> Result<(u64,BlobHeader),Error> {
+  pub fn read_blob_header(&self, offset: u64) -> Result<(u64,BlobHeader),Error> {
     let mut len_buf = [0,0,0,0];
-    self.handle.seek(SeekFrom::Start(offset))?;
-    self.handle.read_exact(&mut len_buf)?;
+    self.handle.read_at(offset, &mut len_buf)?;
     let len = u32::from_be_bytes(len_buf) as usize;
-    self.handle.seek(SeekFrom::Start(offset+4))?;
+    check_blob_header_size(len)?;
+    if let Some(buf) = self.handle.slice_at(offset+4, len) {
+      let blob_header = BlobHeader::from_reader(&mut BytesReader::from_bytes(buf), buf)?;
+      check_blob_size(blob_header.datasize as i64)?;
+      return Ok(((len+4) as u64, blob_header));
+    }
     let mut buf = vec![0u8;len];
-    self.handle.read_exact(&mut buf)?;
+    self.handle.read_at(offset+4, &mut buf)?;
     let mut reader = Reader::from_bytes(buf);
     let blob_header = reader.read(BlobHeader::from_reader)?;
+    check_blob_size(blob_header.datasize as i64)?;
     Ok(((len+4) as u64, blob_header))
   }
-  pub fn read_blob(&mut self, offset: u64, len: usize) -> Result<Blob,Error> {
-    self.handle.seek(SeekFrom::Start(offset))?;
+  pub fn read_blob(&self, offset: u64, len: usize) -> Result<Blob,Error> {
+    check_blob_size(len as i64)?;
     let mut buf = vec![0u8;len];
-    self.handle.read_exact(&mut buf)?;
+    self.handle.read_at(offset, &mut buf)?;
     let mut reader = Reader::from_bytes(buf);
     let blob = reader.read(Blob::from_reader)?;
     Ok(blob)
   }
-  pub fn read(&mut self, offset: u64) -> Result<(u64,Vec<element::Element>),Error> {
-    let (len,_blob_header,blob) = self.read_fileblock(offset)?;
-    if offset == 0 { // header
-      Ok((len, vec![]))
+  pub fn read_header(&self, offset: u64) -> Result<(u64,HeaderBlock),Error> {
+    let (len,blob_header,blob) = self.read_fileblock(offset)?;
+    if blob_header.type_pb != "OSMHeader" {
+      return Err(format!["expected OSMHeader at offset {}, found {}", offset, blob_header.type_pb].into());
+    }
+    let header_block = blob.decode_header()?;
+    header_block.check_required_features()?;
+    Ok((len, header_block))
+  }
+  pub fn read_primitive(&self, offset: u64, len: usize) -> Result<PrimitiveBlock,Error> {
+    match self.handle.slice_at(offset, len) {
+      Some(buf) => BlobRef::from_bytes(buf)?.decode_primitive(),
+      None => self.read_blob(offset, len)?.decode_primitive(),
+    }
+  }
+  // the byte length of the fileblock at offset and its primitive block, or None for a header
+  pub fn read_block(&self, offset: u64) -> Result<(u64,Option<PrimitiveBlock>),Error> {
+    let (s,blob_header) = self.read_blob_header(offset)?;
+    let len = s + blob_header.datasize as u64;
+    if blob_header.type_pb == "OSMHeader" {
+      Ok((len, None))
     } else {
-      Ok((len, blob.decode_primitive()?.decode()))
+      Ok((len, Some(self.read_primitive(offset + s, blob_header.datasize as usize)?)))
+    }
+  }
+  // iterate over the (offset, primitive block) pairs of the fileblocks between start and end,
+  // skipping header blocks. the first error ends the iteration
+  pub fn primitive_blocks(&self, start: u64, end: u64) -> PrimitiveBlocks<'_,F> {
+    PrimitiveBlocks { parser: self, offset: start, end }
+  }
+  pub fn read(&self, offset: u64) -> Result<(u64,Vec<element::Element>),Error> {
+    match self.read_block(offset)? {
+      (len,Some(block)) => Ok((len, block.decode()?)),
+      (len,None) => Ok((len, vec![])),
+    }
+  }
+  pub fn read_with_locations(&self, offset: u64)
+  -> Result<(u64,Vec<(element::ElementLocation,element::Element)>),Error> {
+    match self.read_block(offset)? {
+      (len,Some(block)) => Ok((len, block.decode_with_locations(offset)?)),
+      (len,None) => Ok((len, vec![])),
     }
   }
+  pub fn read_element_at(&self, location: &element::ElementLocation)
+  -> Result<Option<element::Element>,Error> {
+    let primitive_block = match self.read_block(location.offset)? {
+      (_,Some(block)) => block,
+      (_,None) => return Ok(None),
+    };
+    match primitive_block.primitivegroup.get(location.group) {
+      Some(g) => Ok(primitive_block.decode_group(g)?.into_iter().nth(location.index)),
+      None => Ok(None),
+    }
+  }
+}
+
+pub struct PrimitiveBlocks<'a,F: ReadAt> {
+  parser: &'a Parser<F>,
+  offset: u64,
+  end: u64,
+}
+
+impl<'a,F> Iterator for PrimitiveBlocks<'a,F> where F: ReadAt {
+  type Item = Result<(u64,PrimitiveBlock),Error>;
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.offset < self.end {
+      let offset = self.offset;
+      match self.parser.read_block(offset) {
+        Ok((len,block)) => {
+          self.offset += len;
+          if let Some(block) = block { return Some(Ok((offset,block))) }
+        },
+        Err(e) => {
+          self.offset = self.end;
+          return Some(Err(e));
+        },
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::{DEG,node,way,relation,write};
+
+  #[test]
+  fn read_element_at_round_trip() {
+    let mut elements = (1..=25).map(|id| node(id, id * DEG / 10, -id * DEG / 10))
+      .collect::<Vec<_>>();
+    elements.extend((1..=5).map(|id| way(id, &[id,id+1])));
+    elements.push(relation(1, "route", &[(MemberType::Way,1)]));
+    let mut writer = PbfWriter::new(vec![]);
+    writer.set_block_size(10);
+    writer.write_all(elements.clone()).unwrap();
+    let data = writer.finish().unwrap();
+    let parser = Parser::new(Box::new(data.clone()));
+    let mut offset = 0;
+    let mut read = vec![];
+    while offset < data.len() as u64 {
+      let (len,located) = parser.read_with_locations(offset).unwrap();
+      for (location,element) in located {
+        assert_eq![location.offset, offset];
+        assert_eq![parser.read_element_at(&location).unwrap().as_ref(), Some(&element)];
+        read.push(element);
+      }
+      offset += len;
+    }
+    assert_eq![read, elements];
+    // the header block and positions past the end of a group hold no element
+    let location = |offset,group,index| ElementLocation { offset, group, index };
+    assert_eq![parser.read_element_at(&location(0, 0, 0)).unwrap(), None];
+    let (header_len,_) = parser.read_header(0).unwrap();
+    let element = parser.read_element_at(&location(header_len, 0, 9)).unwrap();
+    assert_eq![element, Some(elements[9].clone())];
+    assert_eq![parser.read_element_at(&location(header_len, 0, 10)).unwrap(), None];
+    assert_eq![parser.read_element_at(&location(header_len, 1, 0)).unwrap(), None];
+  }
+
+  #[test]
+  fn read_element_at_later_group() {
+    // a block with a group of nodes followed by a group of ways. neither has strings besides the
+    // empty one, so their string tables are the same
+    let data = write(vec![node(1, DEG, DEG), node(2, 2*DEG, 0), way(7, &[1,2])]);
+    let parser = Parser::new(Box::new(data.clone()));
+    let blocks = parser.primitive_blocks(0, data.len() as u64)
+      .map(|r| r.unwrap().1)
+      .collect::<Vec<_>>();
+    let mut block = blocks[0].clone();
+    block.primitivegroup.extend(blocks[1].primitivegroup.iter().cloned());
+    let mut file = vec![];
+    write::write_fileblock(&mut file, "OSMData", &write::serialize(&block).unwrap()).unwrap();
+    let parser = Parser::new(Box::new(file));
+    let location = |group,index| ElementLocation { offset: 0, group, index };
+    assert_eq![parser.read_element_at(&location(0, 1)).unwrap(), Some(node(2, 2*DEG, 0))];
+    assert_eq![parser.read_element_at(&location(1, 0)).unwrap(), Some(way(7, &[1,2]))];
+    assert_eq![parser.read_element_at(&location(1, 1)).unwrap(), None];
+    assert_eq![parser.read_element_at(&location(2, 0)).unwrap(), None];
+    let (_,located) = parser.read_with_locations(0).unwrap();
+    assert_eq![located.iter().map(|(l,_)| (l.group,l.index)).collect::<Vec<_>>(),
+      vec![(0,0),(0,1),(1,0)]];
+  }
+
+  // a fileblock with the given blob header and blob bytes, as the writer frames them
+  fn frame(blob_header: &BlobHeader, blob: &[u8]) -> Vec<u8> {
+    let header_buf = write::serialize(blob_header).unwrap();
+    let mut data = (header_buf.len() as u32).to_be_bytes().to_vec();
+    data.extend(header_buf);
+    data.extend_from_slice(blob);
+    data
+  }
+
+  fn blob_header(datasize: i32) -> BlobHeader {
+    BlobHeader { type_pb: "OSMData".into(), indexdata: None, datasize }
+  }
+
+  fn is_eof(err: &Error) -> bool {
+    let kind = err.downcast_ref::<std::io::Error>().map(|e| e.kind());
+    kind == Some(std::io::ErrorKind::UnexpectedEof)
+  }
+
+  #[test]
+  fn truncated_fileblock() {
+    let data = write(vec![node(1, 0, 0), node(2, 0, 0)]);
+    let (header_len,_) = Parser::new(Box::new(data.clone())).read_header(0).unwrap();
+    for cut in [2, header_len as usize + 2, header_len as usize + 10, data.len() - 1].iter() {
+      let parser = Parser::new(Box::new(data[..*cut].to_vec()));
+      let offset = if *cut < header_len as usize { 0 } else { header_len };
+      assert![is_eof(&parser.read(offset).unwrap_err()), "cut at {}", cut];
+    }
+    // the same through a source that can't lend out slices
+    let cursor = std::io::Cursor::new(data[..data.len()-1].to_vec());
+    let parser = Parser::new(Box::new(SeekReader::new(cursor)));
+    assert![is_eof(&parser.read(header_len).unwrap_err())];
+  }
+
+  #[test]
+  fn oversized_blob_header() {
+    let mut data = ((MAX_BLOB_HEADER_SIZE + 1) as u32).to_be_bytes().to_vec();
+    data.extend(vec![0;16]);
+    let err = Parser::new(Box::new(data)).read(0).unwrap_err();
+    assert_eq![err.to_string(), "blob header size 65537 exceeds the limit of 65536 bytes"];
+  }
+
+  #[test]
+  fn oversized_blob() {
+    let parser = Parser::new(Box::new(frame(&blob_header(MAX_BLOB_SIZE as i32 + 1), &[])));
+    let err = parser.read(0).unwrap_err();
+    assert_eq![err.to_string(), "blob size 33554433 is outside of the limit of 33554432 bytes"];
+    let parser = Parser::new(Box::new(frame(&blob_header(-1), &[])));
+    let err = parser.read(0).unwrap_err();
+    assert_eq![err.to_string(), "blob size -1 is outside of the limit of 33554432 bytes"];
+  }
+
+  #[test]
+  fn zlib_raw_size_mismatch() {
+    use std::io::Write;
+    let block = write::serialize(&PrimitiveBlock { granularity: 100, ..PrimitiveBlock::default() })
+      .unwrap();
+    let mut z = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+    z.write_all(&block).unwrap();
+    let zlib_data = z.finish().unwrap();
+    let read = |raw_size: i32| {
+      let blob = write::serialize(&Blob {
+        raw_size: Some(raw_size), zlib_data: Some(zlib_data.clone()), ..Blob::default()
+      }).unwrap();
+      Parser::new(Box::new(frame(&blob_header(blob.len() as i32), &blob))).read(0)
+    };
+    let n = block.len() as i32;
+    assert![read(n).unwrap().1.is_empty()];
+    assert_eq![read(n + 1).unwrap_err().to_string(),
+      format!["decompressed blob is {} bytes, expected raw_size {}", n, n + 1]];
+    assert_eq![read(n - 1).unwrap_err().to_string(),
+      format!["decompressed blob exceeds {} bytes", n - 1]];
+  }
 }
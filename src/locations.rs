@@ -0,0 +1,264 @@
+use crate::{Scan,ReadAt,element,Element,Error};
+
+// coordinates are stored as 1e-7 fixed-point i32 pairs with the sign bit flipped so that
+// zero-filled storage (fresh sparse file pages) reads back as "no location" instead of 0,0
+#[cfg(feature="mmap")]
+const EMPTY: (i32,i32) = (i32::MIN,i32::MIN);
+
+#[cfg(feature="mmap")]
+fn encode(x: i32) -> u32 { (x as u32) ^ 0x8000_0000 }
+#[cfg(feature="mmap")]
+fn decode(x: u32) -> i32 { (x ^ 0x8000_0000) as i32 }
+fn to_fixed(x: f64) -> i32 { (x * 1e7).round() as i32 }
+
+// call finish() after the last set. lookups return an error when a store still needs it
+pub trait NodeLocations {
+  fn set_e7(&mut self, id: i64, lon: i32, lat: i32) -> Result<(),Error>;
+  fn get_e7(&self, id: i64) -> Result<Option<(i32,i32)>,Error>;
+  fn finish(&mut self) -> Result<(),Error> { Ok(()) }
+  fn set(&mut self, id: i64, lon: f64, lat: f64) -> Result<(),Error> {
+    self.set_e7(id, to_fixed(lon), to_fixed(lat))
+  }
+  fn get(&self, id: i64) -> Result<Option<(f64,f64)>,Error> {
+    Ok(self.get_e7(id)?.map(|(lon,lat)| (element::e7_to_degrees(lon), element::e7_to_degrees(lat))))
+  }
+  fn add_elements(&mut self, items: &[Element]) -> Result<(),Error> {
+    for item in items.iter() {
+      if let Element::Node(node) = item {
+        self.set_e7(node.id, node.lon_e7(), node.lat_e7())?;
+      }
+    }
+    Ok(())
+  }
+  fn get_way_coords(&self, way: &element::Way) -> Result<Vec<Option<(f64,f64)>>,Error> {
+    way.refs.iter().map(|r| self.get(*r)).collect()
+  }
+}
+
+// records are kept sorted by id for binary search. ids set out of order are only sorted (and
+// deduplicated, keeping the last write) by finish()
+#[derive(Debug,Clone)]
+pub struct SparseLocations {
+  records: Vec<(i64,i32,i32)>,
+  sorted: bool,
+}
+
+impl Default for SparseLocations {
+  fn default() -> Self { Self::new() }
+}
+
+impl SparseLocations {
+  pub fn new() -> Self {
+    Self { records: vec![], sorted: true }
+  }
+  pub fn len(&self) -> usize {
+    self.records.len()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.records.is_empty()
+  }
+}
+
+impl NodeLocations for SparseLocations {
+  fn set_e7(&mut self, id: i64, lon: i32, lat: i32) -> Result<(),Error> {
+    if let Some((prev_id,_,_)) = self.records.last() {
+      if *prev_id >= id { self.sorted = false }
+    }
+    self.records.push((id,lon,lat));
+    Ok(())
+  }
+  fn get_e7(&self, id: i64) -> Result<Option<(i32,i32)>,Error> {
+    if !self.sorted {
+      return Err("ids were set out of order, call finish() before looking them up".into());
+    }
+    Ok(self.records.binary_search_by_key(&id, |(r_id,_,_)| *r_id).ok()
+      .map(|i| (self.records[i].1, self.records[i].2)))
+  }
+  fn finish(&mut self) -> Result<(),Error> {
+    if !self.sorted {
+      // stable sort keeps the last write for an id at the end of its run
+      self.records.sort_by_key(|(id,_,_)| *id);
+      let mut deduped: Vec<(i64,i32,i32)> = Vec::with_capacity(self.records.len());
+      for r in self.records.drain(..) {
+        match deduped.last_mut() {
+          Some(last) if last.0 == r.0 => *last = r,
+          _ => deduped.push(r),
+        }
+      }
+      self.records = deduped;
+      self.sorted = true;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature="mmap")]
+pub struct DenseLocations {
+  file: std::fs::File,
+  mmap: memmap2::MmapMut,
+  capacity: u64,
+}
+
+#[cfg(feature="mmap")]
+impl DenseLocations {
+  const RECORD_SIZE: u64 = 8;
+  const GROW_RECORDS: u64 = 1<<24;
+  // the largest id that can be stored, several times the current osm node ids. the file takes
+  // 8 bytes for every id up to the largest one set, so this caps it at 512GiB
+  pub const MAX_ID: i64 = 1<<36;
+
+  pub fn create<P: AsRef<std::path::Path>>(path: P) -> Result<Self,Error> {
+    let file = std::fs::OpenOptions::new()
+      .read(true).write(true).create(true).truncate(true)
+      .open(path)?;
+    file.set_len(Self::GROW_RECORDS * Self::RECORD_SIZE)?;
+    Self::from_file(file)
+  }
+  pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self,Error> {
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+    Self::from_file(file)
+  }
+  fn from_file(file: std::fs::File) -> Result<Self,Error> {
+    let capacity = file.metadata()?.len() / Self::RECORD_SIZE;
+    let mmap = unsafe { memmap2::MmapMut::map_mut(&file)? };
+    Ok(Self { file, mmap, capacity })
+  }
+  pub fn capacity(&self) -> u64 {
+    self.capacity
+  }
+  fn grow(&mut self, id: u64) -> Result<(),Error> {
+    let capacity = (id / Self::GROW_RECORDS + 1) * Self::GROW_RECORDS;
+    let len = capacity.checked_mul(Self::RECORD_SIZE)
+      .ok_or_else(|| format!["dense index for node id {} is too large", id])?;
+    self.mmap.flush()?;
+    self.file.set_len(len)?;
+    self.mmap = unsafe { memmap2::MmapMut::map_mut(&self.file)? };
+    self.capacity = capacity;
+    Ok(())
+  }
+  fn get_fixed(&self, id: u64) -> (i32,i32) {
+    if id >= self.capacity { return EMPTY }
+    let i = (id * Self::RECORD_SIZE) as usize;
+    let mut lon = [0u8;4];
+    let mut lat = [0u8;4];
+    lon.copy_from_slice(&self.mmap[i..i+4]);
+    lat.copy_from_slice(&self.mmap[i+4..i+8]);
+    (decode(u32::from_le_bytes(lon)), decode(u32::from_le_bytes(lat)))
+  }
+}
+
+#[cfg(feature="mmap")]
+impl NodeLocations for DenseLocations {
+  fn set_e7(&mut self, id: i64, lon: i32, lat: i32) -> Result<(),Error> {
+    if id < 0 {
+      return Err(format!["negative node id {} cannot be stored in a dense index", id].into());
+    }
+    if id > Self::MAX_ID {
+      return Err(format![
+        "node id {} is above the dense index limit of {}", id, Self::MAX_ID
+      ].into());
+    }
+    let id = id as u64;
+    if id >= self.capacity { self.grow(id)? }
+    let i = (id * Self::RECORD_SIZE) as usize;
+    self.mmap[i..i+4].copy_from_slice(&encode(lon).to_le_bytes());
+    self.mmap[i+4..i+8].copy_from_slice(&encode(lat).to_le_bytes());
+    Ok(())
+  }
+  fn get_e7(&self, id: i64) -> Result<Option<(i32,i32)>,Error> {
+    if id < 0 { return Ok(None) }
+    match self.get_fixed(id as u64) {
+      EMPTY => Ok(None),
+      fixed => Ok(Some(fixed)),
+    }
+  }
+  fn finish(&mut self) -> Result<(),Error> {
+    self.mmap.flush()?;
+    Ok(())
+  }
+}
+
+impl<F> Scan<F> where F: ReadAt {
+  pub fn load_locations<L: NodeLocations>(&self, locations: &mut L) -> Result<(),Error> {
+    let mut offsets = self.get_node_blob_offsets().collect::<Vec<_>>();
+    offsets.sort_unstable();
+    for (offset,byte_len,_len) in offsets {
+      locations.add_elements(&self.parser.read_primitive(offset,byte_len)?.decode()?)?;
+    }
+    locations.finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::{DEG,node,way,scan};
+
+  #[test]
+  fn sparse() {
+    let mut locations = SparseLocations::new();
+    locations.set_e7(1, 10, 20).unwrap();
+    locations.set_e7(5, -10, -20).unwrap();
+    locations.set(9, 13.5, -0.00000005).unwrap();
+    // ids set in order can be looked up without finish()
+    assert_eq![locations.get_e7(1).unwrap(), Some((10,20))];
+    assert_eq![locations.get_e7(5).unwrap(), Some((-10,-20))];
+    assert_eq![locations.get_e7(9).unwrap(), Some((135_000_000,-1))];
+    assert_eq![locations.get(5).unwrap(), Some((-0.000001,-0.000002))];
+    assert_eq![locations.get_e7(2).unwrap(), None];
+    // out of order ids and repeated ids need finish(), which keeps the last write
+    locations.set_e7(3, 30, 30).unwrap();
+    locations.set_e7(5, 50, 50).unwrap();
+    assert![locations.get_e7(1).is_err()];
+    locations.finish().unwrap();
+    assert_eq![locations.len(), 4];
+    assert_eq![locations.get_e7(3).unwrap(), Some((30,30))];
+    assert_eq![locations.get_e7(5).unwrap(), Some((50,50))];
+    assert_eq![locations.get_e7(1).unwrap(), Some((10,20))];
+  }
+
+  #[test]
+  fn load_locations() {
+    let scan = scan(vec![
+      node(1, DEG/2, -DEG/4), node(2, 1, 149), node(3, -180*DEG, 90*DEG), way(1, &[1,4,3]),
+    ]);
+    let mut locations = SparseLocations::new();
+    scan.load_locations(&mut locations).unwrap();
+    assert_eq![locations.len(), 3];
+    assert_eq![locations.get_e7(2).unwrap(), Some((0,1))];
+    let way = scan.get_way(1).unwrap().unwrap();
+    assert_eq![
+      locations.get_way_coords(&way).unwrap(),
+      vec![Some((0.5,-0.25)), None, Some((-180.0,90.0))]
+    ];
+  }
+
+  #[cfg(feature="mmap")]
+  #[test]
+  fn dense() {
+    let path = std::env::temp_dir().join(format!["osmpbf-dense-{}", std::process::id()]);
+    let mut locations = DenseLocations::create(&path).unwrap();
+    let initial = locations.capacity();
+    locations.set_e7(0, 0, 0).unwrap();
+    locations.set_e7(7, i32::MIN, i32::MAX).unwrap();
+    assert![locations.set_e7(-1, 0, 0).is_err()];
+    assert![locations.set_e7(DenseLocations::MAX_ID + 1, 0, 0).is_err()];
+    assert![locations.set_e7(i64::MAX, 0, 0).is_err()];
+    // zero-filled records read as missing, not as 0,0
+    assert_eq![locations.get_e7(0).unwrap(), Some((0,0))];
+    assert_eq![locations.get_e7(1).unwrap(), None];
+    assert_eq![locations.get_e7(-1).unwrap(), None];
+    assert_eq![locations.get_e7(initial as i64 * 4).unwrap(), None];
+    // ids past the end grow the file
+    let far = initial as i64 + 5;
+    locations.set_e7(far, 1, 2).unwrap();
+    assert![locations.capacity() > far as u64];
+    locations.finish().unwrap();
+    drop(locations);
+    let locations = DenseLocations::open(&path).unwrap();
+    assert_eq![locations.get_e7(7).unwrap(), Some((i32::MIN,i32::MAX))];
+    assert_eq![locations.get_e7(far).unwrap(), Some((1,2))];
+    drop(locations);
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,503 @@
+use crate::{Parser,ReadAt,Element,MemberType,Info,BBox,Error};
+use crate::top::{TopCounter,Tally,Hll};
+use hashbrown::HashMap;
+use std::collections::BTreeMap;
+
+// edits are element versions. edits is an upper bound on the true count and edits - error a lower
+// bound, exact unless the counter evicted entries to stay within its capacity. changesets is an
+// estimate. timestamps are in seconds since the unix epoch.
+//
+// a user that was evicted and seen again restarts with edits at the counter's floor, but nodes,
+// ways, relations, changesets and first_timestamp only cover the edits since then, so they
+// undercount when error > 0. the changesets sketch is dropped on eviction to keep memory bounded
+#[derive(Debug,Clone,PartialEq)]
+pub struct UserStats {
+  pub uid: i32,
+  pub user: Option<String>,
+  pub edits: u64,
+  pub error: u64,
+  pub nodes: u64,
+  pub ways: u64,
+  pub relations: u64,
+  pub changesets: u64,
+  pub first_timestamp: Option<i64>,
+  pub last_timestamp: Option<i64>,
+}
+
+// counted like UserStats: after an eviction nodes, ways, relations and first_timestamp only cover
+// the edits since the changeset was seen again
+#[derive(Debug,Clone,PartialEq)]
+pub struct ChangesetStats {
+  pub id: i64,
+  pub uid: Option<i32>,
+  pub user: Option<String>,
+  pub edits: u64,
+  pub error: u64,
+  pub nodes: u64,
+  pub ways: u64,
+  pub relations: u64,
+  pub first_timestamp: Option<i64>,
+  pub last_timestamp: Option<i64>,
+}
+
+// edits with a timestamp in [start, start + bucket length). users and changesets are estimates
+#[derive(Debug,Clone,PartialEq)]
+pub struct BucketStats {
+  pub start: i64,
+  pub edits: u64,
+  pub nodes: u64,
+  pub ways: u64,
+  pub relations: u64,
+  pub users: u64,
+  pub changesets: u64,
+}
+
+// one version of an element
+#[derive(Debug,Clone,PartialEq)]
+pub struct Edit {
+  pub element_type: MemberType,
+  pub id: i64,
+  pub version: i32,
+  pub timestamp: i64,
+  pub uid: Option<i32>,
+  pub changeset: Option<i64>,
+}
+
+// edits located in a grid cell. bbox is the cell in nanodegrees
+#[derive(Debug,Clone,PartialEq)]
+pub struct RegionStats {
+  pub bbox: BBox,
+  pub edits: u64,
+  pub oldest: Edit,
+  pub newest: Edit,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TimeBucket {
+  Day,
+  Month,
+  Year,
+}
+
+impl TimeBucket {
+  // start of the bucket holding timestamp t, in utc
+  pub fn start(&self, t: i64) -> i64 {
+    let days = t.div_euclid(86400);
+    match self {
+      TimeBucket::Day => days * 86400,
+      TimeBucket::Month => {
+        let (y,m,_) = civil_from_days(days);
+        days_from_civil(y, m, 1) * 86400
+      },
+      TimeBucket::Year => {
+        let (y,_,_) = civil_from_days(days);
+        days_from_civil(y, 1, 1) * 86400
+      },
+    }
+  }
+}
+
+// proleptic gregorian calendar conversions from http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(z: i64) -> (i64,i64,i64) {
+  let z = z + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+  let doy = doe - (365*yoe + yoe/4 - yoe/100);
+  let mp = (5*doy + 2) / 153;
+  let d = doy - (153*mp + 2)/5 + 1;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 };
+  (yoe + era*400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+  let y = if m <= 2 { y - 1 } else { y };
+  let era = y.div_euclid(400);
+  let yoe = y.rem_euclid(400);
+  let doy = (153*(if m > 2 { m - 3 } else { m + 9 }) + 2)/5 + d - 1;
+  let doe = yoe*365 + yoe/4 - yoe/100 + doy;
+  era*146097 + doe - 719468
+}
+
+fn min_opt(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+  match (a,b) {
+    (Some(a),Some(b)) => Some(a.min(b)),
+    (a,b) => a.or(b),
+  }
+}
+
+fn max_opt(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+  match (a,b) {
+    (Some(a),Some(b)) => Some(a.max(b)),
+    (a,b) => a.or(b),
+  }
+}
+
+fn count_type(counts: [&mut u64;3], t: &MemberType) {
+  let [nodes,ways,relations] = counts;
+  match t {
+    MemberType::Node => *nodes += 1,
+    MemberType::Way => *ways += 1,
+    MemberType::Relation => *relations += 1,
+  }
+}
+
+impl Tally for UserStats {
+  fn with_floor(floor: u64) -> Self {
+    UserStats {
+      uid: 0, user: None, edits: floor, error: floor, nodes: 0, ways: 0, relations: 0,
+      changesets: 0, first_timestamp: None, last_timestamp: None,
+    }
+  }
+  fn total(&self) -> u64 {
+    self.edits
+  }
+  fn merge(&mut self, other: &Self) {
+    self.uid = other.uid;
+    if other.user.is_some() { self.user = other.user.clone() }
+    self.edits += other.edits;
+    self.error += other.error;
+    self.nodes += other.nodes;
+    self.ways += other.ways;
+    self.relations += other.relations;
+    self.first_timestamp = min_opt(self.first_timestamp, other.first_timestamp);
+    self.last_timestamp = max_opt(self.last_timestamp, other.last_timestamp);
+  }
+  fn add_floor(&mut self, floor: u64) {
+    self.edits += floor;
+    self.error += floor;
+  }
+}
+
+impl Tally for ChangesetStats {
+  fn with_floor(floor: u64) -> Self {
+    ChangesetStats {
+      id: 0, uid: None, user: None, edits: floor, error: floor, nodes: 0, ways: 0, relations: 0,
+      first_timestamp: None, last_timestamp: None,
+    }
+  }
+  fn total(&self) -> u64 {
+    self.edits
+  }
+  fn merge(&mut self, other: &Self) {
+    self.id = other.id;
+    if other.uid.is_some() { self.uid = other.uid }
+    if other.user.is_some() { self.user = other.user.clone() }
+    self.edits += other.edits;
+    self.error += other.error;
+    self.nodes += other.nodes;
+    self.ways += other.ways;
+    self.relations += other.relations;
+    self.first_timestamp = min_opt(self.first_timestamp, other.first_timestamp);
+    self.last_timestamp = max_opt(self.last_timestamp, other.last_timestamp);
+  }
+  fn add_floor(&mut self, floor: u64) {
+    self.edits += floor;
+    self.error += floor;
+  }
+}
+
+#[derive(Debug,Clone)]
+struct Bucket {
+  edits: u64,
+  nodes: u64,
+  ways: u64,
+  relations: u64,
+  users: Hll,
+  changesets: Hll,
+}
+
+// aggregates the Info metadata of a stream of elements: edits per user, per changeset, per time
+// bucket and per grid cell. users and changesets are kept in bounded counters like TagStats, so a
+// planet file keeps the most active ones. elements without info are skipped. buckets and regions
+// need timestamps, and regions need coordinates, so they count nodes and ways with
+// LocationsOnWays (located at their first coordinate)
+#[derive(Debug,Clone)]
+pub struct MetaStats {
+  bucket: TimeBucket,
+  region_size: i64,
+  users: TopCounter<i32,UserStats>,
+  user_changesets: HashMap<i32,Hll>,
+  changesets: TopCounter<i64,ChangesetStats>,
+  buckets: BTreeMap<i64,Bucket>,
+  regions: HashMap<(i64,i64),RegionStats>,
+}
+
+const DEFAULT_CAPACITY: usize = 10_000;
+// smallest region grid cell in nanodegrees, which keeps the grid to at most 1440x720 cells
+const MIN_REGION_SIZE: i64 = 250_000_000;
+
+impl Default for MetaStats {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl MetaStats {
+  // monthly buckets and 1 degree regions
+  pub fn new() -> Self {
+    Self {
+      bucket: TimeBucket::Month,
+      region_size: 1_000_000_000,
+      users: TopCounter::new(DEFAULT_CAPACITY),
+      user_changesets: HashMap::new(),
+      changesets: TopCounter::new(DEFAULT_CAPACITY),
+      buckets: BTreeMap::new(),
+      regions: HashMap::new(),
+    }
+  }
+  // the following settings apply to elements added afterwards
+  pub fn bucket(mut self, bucket: TimeBucket) -> Self {
+    self.bucket = bucket;
+    self
+  }
+  // width and height of the region grid cells in degrees, at least 0.25
+  pub fn region_size(mut self, degrees: f64) -> Self {
+    self.region_size = ((degrees * 1e9).round() as i64).max(MIN_REGION_SIZE);
+    self
+  }
+  // number of users and changesets to keep
+  pub fn capacity(mut self, capacity: usize) -> Self {
+    self.users = TopCounter::new(capacity);
+    self.changesets = TopCounter::new(capacity);
+    self
+  }
+  pub fn add_element(&mut self, element: &Element) {
+    let (t,info) = match (element.member_type(), element.info()) {
+      (Some(t),Some(info)) => (t,info),
+      _ => return,
+    };
+    let id = element.id();
+    let coord = match element {
+      Element::Node(node) => Some((node.nano_lon,node.nano_lat)),
+      Element::Way(way) => way.nano_coords.first().copied(),
+      _ => None,
+    };
+    self.add_user(&t, info);
+    self.add_changeset(&t, info);
+    let timestamp = match info.timestamp {
+      Some(timestamp) => timestamp,
+      None => return,
+    };
+    let b = self.buckets.entry(self.bucket.start(timestamp)).or_insert_with(|| Bucket {
+      edits: 0, nodes: 0, ways: 0, relations: 0, users: Hll::new(), changesets: Hll::new(),
+    });
+    b.edits += 1;
+    count_type([&mut b.nodes, &mut b.ways, &mut b.relations], &t);
+    if let Some(uid) = info.uid { b.users.add(&uid) }
+    if let Some(changeset) = info.changeset { b.changesets.add(&changeset) }
+    if let Some((lon,lat)) = coord {
+      let edit = Edit {
+        element_type: t,
+        id,
+        version: info.version,
+        timestamp,
+        uid: info.uid,
+        changeset: info.changeset,
+      };
+      let size = self.region_size;
+      let cell = (lon.div_euclid(size), lat.div_euclid(size));
+      let r = self.regions.entry(cell).or_insert_with(|| RegionStats {
+        bbox: BBox {
+          left: cell.0 * size,
+          right: (cell.0 + 1) * size,
+          bottom: cell.1 * size,
+          top: (cell.1 + 1) * size,
+        },
+        edits: 0,
+        oldest: edit.clone(),
+        newest: edit.clone(),
+      });
+      r.edits += 1;
+      if timestamp < r.oldest.timestamp { r.oldest = edit.clone() }
+      if timestamp > r.newest.timestamp { r.newest = edit }
+    }
+  }
+  fn add_user(&mut self, t: &MemberType, info: &Info) {
+    let uid = match info.uid {
+      Some(uid) => uid,
+      None => return,
+    };
+    self.users.update(uid, |u| {
+      u.uid = uid;
+      if info.user.is_some() { u.user = info.user.clone() }
+      u.edits += 1;
+      count_type([&mut u.nodes, &mut u.ways, &mut u.relations], t);
+      u.first_timestamp = min_opt(u.first_timestamp, info.timestamp);
+      u.last_timestamp = max_opt(u.last_timestamp, info.timestamp);
+    });
+    if let Some(changeset) = info.changeset {
+      self.user_changesets.entry(uid).or_insert_with(Hll::new).add(&changeset);
+    }
+    if self.user_changesets.len() > self.users.counts.len() {
+      let users = &self.users.counts;
+      self.user_changesets.retain(|uid,_| users.contains_key(uid));
+    }
+  }
+  fn add_changeset(&mut self, t: &MemberType, info: &Info) {
+    let id = match info.changeset {
+      Some(id) => id,
+      None => return,
+    };
+    self.changesets.update(id, |c| {
+      c.id = id;
+      if info.uid.is_some() { c.uid = info.uid }
+      if info.user.is_some() { c.user = info.user.clone() }
+      c.edits += 1;
+      count_type([&mut c.nodes, &mut c.ways, &mut c.relations], t);
+      c.first_timestamp = min_opt(c.first_timestamp, info.timestamp);
+      c.last_timestamp = max_opt(c.last_timestamp, info.timestamp);
+    });
+  }
+  pub fn add_elements<'a>(&mut self, elements: impl IntoIterator<Item=&'a Element>) {
+    for e in elements {
+      self.add_element(e);
+    }
+  }
+  // combine stats collected separately with the same settings
+  pub fn merge(&mut self, other: &MetaStats) {
+    self.users.merge(&other.users);
+    self.changesets.merge(&other.changesets);
+    for (uid,h) in other.user_changesets.iter() {
+      self.user_changesets.entry(*uid).or_insert_with(Hll::new).merge(h);
+    }
+    let users = &self.users.counts;
+    self.user_changesets.retain(|uid,_| users.contains_key(uid));
+    for (start,b) in other.buckets.iter() {
+      match self.buckets.get_mut(start) {
+        Some(x) => {
+          x.edits += b.edits;
+          x.nodes += b.nodes;
+          x.ways += b.ways;
+          x.relations += b.relations;
+          x.users.merge(&b.users);
+          x.changesets.merge(&b.changesets);
+        },
+        None => { self.buckets.insert(*start, b.clone()); },
+      }
+    }
+    for (cell,r) in other.regions.iter() {
+      match self.regions.get_mut(cell) {
+        Some(x) => {
+          x.edits += r.edits;
+          if r.oldest.timestamp < x.oldest.timestamp { x.oldest = r.oldest.clone() }
+          if r.newest.timestamp > x.newest.timestamp { x.newest = r.newest.clone() }
+        },
+        None => { self.regions.insert(*cell, r.clone()); },
+      }
+    }
+  }
+  // users sorted by edits, most active first
+  pub fn users(&self) -> Vec<UserStats> {
+    self.users.sorted().into_iter().map(|(uid,u)| self.user_stats(*uid, u)).collect()
+  }
+  pub fn user(&self, uid: i32) -> Option<UserStats> {
+    self.users.counts.get(&uid).map(|u| self.user_stats(uid, u))
+  }
+  fn user_stats(&self, uid: i32, u: &UserStats) -> UserStats {
+    let mut u = u.clone();
+    u.changesets = self.user_changesets.get(&uid).map(|h| h.estimate()).unwrap_or(0);
+    u
+  }
+  // changesets sorted by edits, largest first
+  pub fn changesets(&self) -> Vec<ChangesetStats> {
+    self.changesets.sorted().into_iter().map(|(_,c)| c.clone()).collect()
+  }
+  pub fn changeset(&self, id: i64) -> Option<ChangesetStats> {
+    self.changesets.counts.get(&id).cloned()
+  }
+  // buckets in time order
+  pub fn buckets(&self) -> Vec<BucketStats> {
+    self.buckets.iter().map(|(start,b)| BucketStats {
+      start: *start,
+      edits: b.edits,
+      nodes: b.nodes,
+      ways: b.ways,
+      relations: b.relations,
+      users: b.users.estimate(),
+      changesets: b.changesets.estimate(),
+    }).collect()
+  }
+  // regions with at least one edit, ordered by longitude then latitude
+  pub fn regions(&self) -> Vec<RegionStats> {
+    let mut regions = self.regions.iter().collect::<Vec<_>>();
+    regions.sort_unstable_by_key(|(cell,_)| **cell);
+    regions.into_iter().map(|(_,r)| r.clone()).collect()
+  }
+}
+
+impl<F> Parser<F> where F: ReadAt {
+  // decode every blob between start and end and add the metadata of each element to stats
+  pub fn meta_stats(&self, start: u64, end: u64, mut stats: MetaStats) -> Result<MetaStats,Error> {
+    for r in self.primitive_blocks(start, end) {
+      stats.add_elements(r?.1.decode()?.iter());
+    }
+    Ok(stats)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::{DEG,node,with_info};
+
+  // seconds since the epoch at midnight utc
+  fn date(y: i64, m: i64, d: i64) -> i64 {
+    days_from_civil(y, m, d) * 86400
+  }
+
+  #[test]
+  fn civil_dates() {
+    assert_eq![civil_from_days(0), (1970,1,1)];
+    assert_eq![civil_from_days(-1), (1969,12,31)];
+    assert_eq![civil_from_days(59), (1970,3,1)];
+    // 2000 is a leap year, 1900 and 2100 are not
+    assert_eq![civil_from_days(days_from_civil(2000, 2, 28) + 1), (2000,2,29)];
+    assert_eq![civil_from_days(days_from_civil(2000, 2, 29) + 1), (2000,3,1)];
+    assert_eq![civil_from_days(days_from_civil(1900, 2, 28) + 1), (1900,3,1)];
+    assert_eq![civil_from_days(days_from_civil(2100, 2, 28) + 1), (2100,3,1)];
+    assert_eq![civil_from_days(days_from_civil(2024, 2, 28) + 1), (2024,2,29)];
+    assert_eq![days_from_civil(2024, 3, 1) - days_from_civil(2024, 1, 1), 60];
+    assert_eq![days_from_civil(2023, 3, 1) - days_from_civil(2023, 1, 1), 59];
+    assert_eq![days_from_civil(2001, 1, 1) - days_from_civil(2000, 1, 1), 366];
+    // every day round trips across four centuries on both sides of the epoch
+    for z in -146097..146097 {
+      let (y,m,d) = civil_from_days(z);
+      assert_eq![days_from_civil(y, m, d), z];
+    }
+  }
+
+  #[test]
+  fn bucket_start() {
+    let t = date(2024, 2, 29) + 3600*23 + 59;
+    assert_eq![TimeBucket::Day.start(t), date(2024, 2, 29)];
+    assert_eq![TimeBucket::Month.start(t), date(2024, 2, 1)];
+    assert_eq![TimeBucket::Year.start(t), date(2024, 1, 1)];
+    // the last second of a year and the first of the next
+    let new_year = date(2024, 1, 1);
+    assert_eq![TimeBucket::Day.start(new_year - 1), date(2023, 12, 31)];
+    assert_eq![TimeBucket::Month.start(new_year - 1), date(2023, 12, 1)];
+    assert_eq![TimeBucket::Year.start(new_year - 1), date(2023, 1, 1)];
+    assert_eq![TimeBucket::Month.start(new_year), new_year];
+    assert_eq![TimeBucket::Year.start(new_year), new_year];
+    // timestamps before the epoch round down
+    assert_eq![TimeBucket::Day.start(-1), -86400];
+    assert_eq![TimeBucket::Month.start(-1), date(1969, 12, 1)];
+    assert_eq![TimeBucket::Year.start(-1), date(1969, 1, 1)];
+  }
+
+  #[test]
+  fn region_size() {
+    assert_eq![MetaStats::new().region_size(0.5).region_size, 500_000_000];
+    assert_eq![MetaStats::new().region_size(1e-9).region_size, MIN_REGION_SIZE];
+    let mut stats = MetaStats::new().region_size(0.0);
+    // nodes 0.1 degrees apart share cells of the smallest size
+    for i in 0..10 {
+      stats.add_element(&with_info(node(i, i*DEG/10, 0), 1, Some(i), true));
+    }
+    let regions = stats.regions();
+    assert_eq![regions.iter().map(|r| r.edits).collect::<Vec<_>>(), vec![3,2,3,2]];
+    assert_eq![regions[1].bbox, BBox { left: DEG/4, bottom: 0, right: DEG/2, top: DEG/4 }];
+    assert_eq![(regions[1].oldest.id,regions[1].newest.id), (3,4)];
+  }
+}
@@ -0,0 +1,154 @@
+use crate::{ReadAt,Error};
+
+// presents several pbf files back to back as a single source. a virtual offset maps to
+// (part index, offset within that part) with locate() and back again with offset_of()
+pub struct MultiSource<F: ReadAt> {
+  parts: Vec<(u64,u64,F)>,
+}
+
+impl<F> Default for MultiSource<F> where F: ReadAt {
+  fn default() -> Self { Self::new() }
+}
+
+impl<F> MultiSource<F> where F: ReadAt {
+  pub fn new() -> Self {
+    Self { parts: vec![] }
+  }
+  pub fn push(&mut self, source: F, len: u64) -> usize {
+    let base = self.len();
+    self.parts.push((base,len,source));
+    self.parts.len() - 1
+  }
+  pub fn len(&self) -> u64 {
+    self.parts.last().map(|(base,len,_)| base + len).unwrap_or(0)
+  }
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+  pub fn part_count(&self) -> usize {
+    self.parts.len()
+  }
+  pub fn get_part(&self, i: usize) -> Option<&F> {
+    self.parts.get(i).map(|(_,_,source)| source)
+  }
+  pub fn get_part_range(&self, i: usize) -> Option<(u64,u64)> {
+    self.parts.get(i).map(|(base,len,_)| (*base, base + len))
+  }
+  pub fn locate(&self, offset: u64) -> Option<(usize,u64)> {
+    let i = self.parts.partition_point(|(base,len,_)| base + len <= offset);
+    self.parts.get(i).map(|(base,_,_)| (i, offset - base))
+  }
+  pub fn offset_of(&self, part: usize, offset: u64) -> Option<u64> {
+    self.parts.get(part)
+      .filter(|(_,len,_)| offset < *len)
+      .map(|(base,_,_)| base + offset)
+  }
+}
+
+impl MultiSource<std::fs::File> {
+  pub fn open<P: AsRef<std::path::Path>>(paths: &[P]) -> Result<Self,Error> {
+    let mut multi = Self::new();
+    for path in paths.iter() {
+      let h = std::fs::File::open(path)?;
+      let len = h.metadata()?.len();
+      multi.push(h, len);
+    }
+    Ok(multi)
+  }
+}
+
+impl<F> ReadAt for MultiSource<F> where F: ReadAt {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    let mut n = 0;
+    while n < buf.len() {
+      let (i,local) = self.locate(offset + n as u64)
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+      let (_,len,source) = &self.parts[i];
+      let count = ((len - local) as usize).min(buf.len() - n);
+      source.read_at(local, &mut buf[n..n+count])?;
+      n += count;
+    }
+    Ok(())
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    let (i,local) = self.locate(offset)?;
+    let (_,part_len,source) = &self.parts[i];
+    if local + len as u64 > *part_len { return None }
+    source.slice_at(local, len)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Parser,Scan,ScanTable,Element};
+  use crate::test_util::{DEG,node,way,write,scan_data};
+
+  #[test]
+  fn locate_at_boundaries() {
+    let mut multi = MultiSource::new();
+    assert_eq![multi.locate(0), None];
+    multi.push(vec![1u8,2,3], 3);
+    multi.push(vec![], 0);
+    multi.push(vec![4u8,5], 2);
+    assert_eq![multi.len(), 5];
+    assert_eq![multi.locate(0), Some((0,0))];
+    assert_eq![multi.locate(2), Some((0,2))];
+    // the empty part never owns an offset
+    assert_eq![multi.locate(3), Some((2,0))];
+    assert_eq![multi.locate(4), Some((2,1))];
+    assert_eq![multi.locate(5), None];
+    assert_eq![multi.offset_of(0, 2), Some(2)];
+    assert_eq![multi.offset_of(0, 3), None];
+    assert_eq![multi.offset_of(1, 0), None];
+    assert_eq![multi.offset_of(2, 0), Some(3)];
+    assert_eq![multi.offset_of(2, 2), None];
+    assert_eq![multi.offset_of(3, 0), None];
+    assert_eq![multi.get_part_range(2), Some((3,5))];
+    // reads span parts
+    let mut buf = [0;4];
+    multi.read_at(1, &mut buf).unwrap();
+    assert_eq![buf, [2,3,4,5]];
+    assert![multi.read_at(2, &mut buf).is_err()];
+    assert_eq![multi.slice_at(0, 3), Some(&[1u8,2,3][..])];
+    assert_eq![multi.slice_at(1, 3), None];
+  }
+
+  #[test]
+  fn same_intervals_in_each_part() {
+    // both parts cover the same ids, with different coordinates
+    let part = |lon: i64| write(vec![node(1, lon, 0), node(2, lon, 0), way(1, &[1,2])]);
+    let (a,b) = (part(DEG), part(2*DEG));
+    let mut multi = MultiSource::new();
+    multi.push(a.clone(), a.len() as u64);
+    multi.push(b.clone(), b.len() as u64);
+    let len = multi.len();
+    let mut scan = Scan::new(Parser::new(Box::new(multi)));
+    scan.scan(0, len).unwrap();
+
+    let offsets = scan.get_node_blob_offsets_for_id(2);
+    assert_eq![offsets.len(), 2];
+    let multi = &scan.parser.handle;
+    let parts = offsets.iter().map(|(offset,_,_)| multi.locate(*offset).unwrap().0)
+      .collect::<Vec<_>>();
+    assert_eq![parts, vec![0,1]];
+    for (offset,byte_len,_) in offsets {
+      let lon = match &scan.parser.read_primitive(offset, byte_len).unwrap().decode().unwrap()[1] {
+        Element::Node(node) => node.nano_lon,
+        e => panic!["expected a node, found {:?}", e],
+      };
+      let part = multi.locate(offset).unwrap().0;
+      assert_eq![lon, [DEG,2*DEG][part]];
+    }
+    assert_eq![scan.get_way_blob_offsets_for_id(1).len(), 2];
+
+    // tables scanned per part and merged with their bases match the combined scan
+    let mut table = ScanTable::default();
+    table.extend_with_offset(&scan_data(a.clone()).table, 0);
+    table.extend_with_offset(&scan_data(b).table, a.len() as u64);
+    for id in [1,2].iter() {
+      assert_eq![table.get_node_blob_offsets_for_id(*id), scan.get_node_blob_offsets_for_id(*id)];
+    }
+    assert_eq![table.get_way_blob_offsets_for_id(1), scan.get_way_blob_offsets_for_id(1)];
+  }
+}
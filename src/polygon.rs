@@ -0,0 +1,412 @@
+use crate::{Scan,ReadAt,BBox,Element,MemberType,Node,Way,Error};
+use crate::extract::for_each;
+use hashbrown::{HashMap,HashSet};
+use std::io::Write;
+
+// a closed ring of (lon,lat) nanodegree coordinates. the last point may repeat the first
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Ring {
+  pub coords: Vec<(i64,i64)>,
+  pub hole: bool,
+}
+
+// an area made of outer rings with optional holes. a point is inside when it is inside an outer
+// ring and not inside one of that ring's holes, which are the holes with a vertex inside it. an
+// outer ring inside a hole (an island) is part of the area
+#[derive(Debug,Clone,PartialEq,Eq,Default)]
+pub struct Polygon {
+  pub rings: Vec<Ring>,
+}
+
+impl Ring {
+  pub fn new(coords: Vec<(i64,i64)>, hole: bool) -> Self {
+    Self { coords, hole }
+  }
+  // even-odd crossing test. points on an edge may land on either side
+  pub fn contains(&self, lon: i64, lat: i64) -> bool {
+    let c = &self.coords;
+    if c.len() < 3 { return false }
+    let mut inside = false;
+    let mut j = c.len() - 1;
+    for i in 0..c.len() {
+      let (xi,yi) = c[i];
+      let (xj,yj) = c[j];
+      if (yi > lat) != (yj > lat) {
+        // lon < xi + (lat-yi)*(xj-xi)/(yj-yi), without division
+        let lhs = (lon as i128 - xi as i128) * (yj as i128 - yi as i128);
+        let rhs = (lat as i128 - yi as i128) * (xj as i128 - xi as i128);
+        if (yj > yi && lhs < rhs) || (yj < yi && lhs > rhs) {
+          inside = !inside;
+        }
+      }
+      j = i;
+    }
+    inside
+  }
+}
+
+impl Polygon {
+  pub fn new(rings: Vec<Ring>) -> Self {
+    Self { rings }
+  }
+  pub fn contains(&self, lon: i64, lat: i64) -> bool {
+    let holes = self.rings.iter().filter(|r| r.hole && r.contains(lon, lat));
+    self.rings.iter().filter(|r| !r.hole && r.contains(lon, lat)).any(|outer| {
+      !holes.clone().any(|h| h.coords.iter().any(|(x,y)| outer.contains(*x, *y)))
+    })
+  }
+  pub fn contains_node(&self, node: &Node) -> bool {
+    self.contains(node.nano_lon, node.nano_lat)
+  }
+  // read an osmosis .poly file: a name line, then sections of "lon lat" lines in degrees that
+  // each end with END, and a final END. sections whose name starts with ! are holes
+  pub fn parse_poly(text: &str) -> Result<Self,Error> {
+    let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+    if lines.next().is_none() {
+      return Err("empty .poly file".into());
+    }
+    let mut rings = vec![];
+    loop {
+      let section = match lines.next() {
+        Some("END") => break,
+        Some(section) => section,
+        None => return Err("missing END at the end of .poly file".into()),
+      };
+      let mut coords = vec![];
+      loop {
+        let line = match lines.next() {
+          Some("END") => break,
+          Some(line) => line,
+          None => return Err(format!["missing END for section {} of .poly file", section].into()),
+        };
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+          (Some(lon),Some(lat),None) => {
+            coords.push((parse_degrees(lon, 180.0)?, parse_degrees(lat, 90.0)?));
+          },
+          _ => return Err(format!["expected lon lat in .poly file, found {:?}", line].into()),
+        }
+      }
+      rings.push(Ring::new(coords, section.starts_with('!')));
+    }
+    Ok(Self { rings })
+  }
+  // write the polygon in osmosis .poly format. sections are numbered from 1
+  pub fn write_poly<W: Write>(&self, out: &mut W, name: &str) -> Result<(),Error> {
+    writeln![out, "{}", name]?;
+    for (i,ring) in self.rings.iter().enumerate() {
+      writeln![out, "{}{}", if ring.hole { "!" } else { "" }, i+1]?;
+      for (lon,lat) in ring.coords.iter() {
+        writeln![out, "\t{}\t{}", format_degrees(*lon), format_degrees(*lat)]?;
+      }
+      writeln![out, "END"]?;
+    }
+    writeln![out, "END"]?;
+    Ok(())
+  }
+  pub fn to_poly(&self, name: &str) -> String {
+    let mut buf = vec![];
+    // writing to a vec can't fail
+    self.write_poly(&mut buf, name).unwrap();
+    String::from_utf8(buf).unwrap()
+  }
+  // bounding box of the outer rings, or None without any points
+  pub fn bbox(&self) -> Option<BBox> {
+    let mut points = self.rings.iter().filter(|r| !r.hole).flat_map(|r| r.coords.iter());
+    let (lon,lat) = *points.next()?;
+    let mut b = BBox { left: lon, right: lon, top: lat, bottom: lat };
+    for (lon,lat) in points {
+      b.left = b.left.min(*lon);
+      b.right = b.right.max(*lon);
+      b.bottom = b.bottom.min(*lat);
+      b.top = b.top.max(*lat);
+    }
+    Some(b)
+  }
+}
+
+// a coordinate in degrees within -limit..=limit as nanodegrees
+fn parse_degrees(s: &str, limit: f64) -> Result<i64,Error> {
+  let x = s.parse::<f64>().ok().filter(|x| x.is_finite() && x.abs() <= limit)
+    .ok_or_else(|| format!["invalid coordinate {:?} in .poly file", s])?;
+  Ok((x * 1e9).round() as i64)
+}
+
+// nanodegrees as an exact decimal without trailing zeros
+fn format_degrees(x: i64) -> String {
+  let sign = if x < 0 { "-" } else { "" };
+  let (int,frac) = (x.unsigned_abs() / 1_000_000_000, x.unsigned_abs() % 1_000_000_000);
+  if frac == 0 {
+    format!["{}{}", sign, int]
+  } else {
+    format!["{}{}.{}", sign, int, format!["{:09}", frac].trim_end_matches('0')]
+  }
+}
+
+impl BBox {
+  // inclusive on all sides
+  pub fn contains(&self, lon: i64, lat: i64) -> bool {
+    lon >= self.left && lon <= self.right && lat >= self.bottom && lat <= self.top
+  }
+}
+
+impl<F> Scan<F> where F: ReadAt {
+  // build a polygon from the member ways of a multipolygon or boundary relation. ways with the
+  // role inner become holes and all other ways outer rings. the ways are joined end to end into
+  // closed rings. member nodes and relations are ignored. returns None if there is no relation
+  // with this id
+  pub fn get_relation_polygon(&self, id: i64) -> Result<Option<Polygon>,Error> {
+    let relation = match self.get_relation(id)? {
+      Some(relation) => relation,
+      None => return Ok(None),
+    };
+    let members = relation.members.iter()
+      .filter(|m| m.member_type == MemberType::Way)
+      .collect::<Vec<_>>();
+    let way_ids = members.iter().map(|m| m.id).collect::<HashSet<_>>();
+    let mut ways = HashMap::new();
+    let offsets = way_ids.iter().flat_map(|id| self.get_way_blob_offsets_for_id(*id));
+    for_each(self, offsets, |e| {
+      if let Element::Way(way) = e {
+        if way_ids.contains(&way.id) { ways.insert(way.id, way.clone()); }
+      }
+    })?;
+    let node_ids = ways.values()
+      .filter(|w| w.nano_coords.len() != w.refs.len())
+      .flat_map(|w| w.refs.iter().copied())
+      .collect::<HashSet<_>>();
+    let mut coords = HashMap::new();
+    for way in ways.values() {
+      coords.extend(way.refs.iter().copied().zip(way.nano_coords.iter().copied()));
+    }
+    // ways without locations on them need their nodes looked up
+    let offsets = node_ids.iter().flat_map(|id| self.get_node_blob_offsets_for_id(*id));
+    for_each(self, offsets, |e| {
+      if let Element::Node(node) = e {
+        if node_ids.contains(&node.id) { coords.insert(node.id, (node.nano_lon,node.nano_lat)); }
+      }
+    })?;
+    let mut rings = vec![];
+    for hole in [false,true].iter() {
+      let mut parts = vec![];
+      for m in members.iter().filter(|m| (m.role == "inner") == *hole) {
+        match ways.get(&m.id) {
+          Some(way) if !way.refs.is_empty() => parts.push(way),
+          Some(_) => {},
+          None => return Err(format!["way {} of relation {} not found", m.id, id].into()),
+        }
+      }
+      for refs in join_rings(parts, id)? {
+        let ring = refs.iter().map(|r| coords.get(r).copied()
+          .ok_or_else(|| Error::from(format!["node {} of relation {} not found", r, id])))
+          .collect::<Result<Vec<_>,_>>()?;
+        rings.push(Ring::new(ring, *hole));
+      }
+    }
+    Ok(Some(Polygon::new(rings)))
+  }
+}
+
+// join ways that share end nodes into closed rings of node ids. a ring needs at least four refs,
+// the last of which repeats the first
+fn join_rings(mut parts: Vec<&Way>, relation: i64) -> Result<Vec<Vec<i64>>,Error> {
+  let mut rings = vec![];
+  while let Some(way) = parts.pop() {
+    let mut ring = way.refs.clone();
+    while ring.first() != ring.last() {
+      let end = *ring.last().unwrap();
+      let i = parts.iter().position(|w| w.refs.first() == Some(&end) || w.refs.last() == Some(&end))
+        .ok_or_else(|| format!["ring of relation {} is not closed at node {}", relation, end])?;
+      let next = parts.swap_remove(i);
+      if next.refs.first() == Some(&end) {
+        ring.extend(next.refs[1..].iter());
+      } else {
+        ring.extend(next.refs.iter().rev().skip(1));
+      }
+    }
+    if ring.len() < 4 {
+      return Err(format!["ring of relation {} has only {} refs: {:?}", relation, ring.len(), ring]
+        .into());
+    }
+    rings.push(ring);
+  }
+  Ok(rings)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::{self,DEG,node,relation,scan};
+
+  fn square(a: i64, b: i64, hole: bool) -> Ring {
+    Ring::new(vec![(a,a),(b,a),(b,b),(a,b),(a,a)], hole)
+  }
+  fn way(id: i64, refs: &[i64]) -> Way {
+    match test_util::way(id, refs) {
+      Element::Way(way) => way,
+      _ => unreachable![],
+    }
+  }
+
+  #[test]
+  fn ring_contains() {
+    let ring = square(0, 10, false);
+    assert![ring.contains(5, 5)];
+    assert![ring.contains(1, 9)];
+    assert![!ring.contains(-1, 5)];
+    assert![!ring.contains(5, 11)];
+    assert![!ring.contains(11, 11)];
+    // a point level with a vertex is only counted once
+    assert![!ring.contains(-5, 10)];
+    assert![!ring.contains(-5, 0)];
+    // concave ring
+    let c = Ring::new(vec![(0,0),(10,0),(10,10),(5,2),(0,10)], false);
+    assert![c.contains(1, 1)];
+    assert![!c.contains(5, 5)];
+    assert![c.contains(9, 8)];
+    // degenerate rings contain nothing
+    assert![!Ring::new(vec![(0,0),(10,10)], false).contains(5, 5)];
+    assert![!Ring::new(vec![], false).contains(0, 0)];
+    // large coordinates don't overflow
+    let big = square(-180*DEG, 180*DEG, false);
+    assert![big.contains(179*DEG, -179*DEG)];
+    assert![!big.contains(181*DEG, 0)];
+  }
+
+  #[test]
+  fn holes() {
+    let p = Polygon::new(vec![
+      square(0, 100, false), square(40, 60, true), square(200, 300, false),
+    ]);
+    assert![p.contains(10, 10)];
+    assert![!p.contains(50, 50)];
+    assert![p.contains(39, 50)];
+    assert![!p.contains(150, 150)];
+    assert![p.contains(250, 299)];
+    // an island in the hole is inside, and a hole in the island is not
+    let island = Polygon::new(vec![
+      square(0, 100, false), square(20, 80, true), square(30, 70, false), square(45, 55, true),
+    ]);
+    assert![island.contains(10, 10)];
+    assert![!island.contains(25, 25)];
+    assert![island.contains(35, 35)];
+    assert![!island.contains(50, 50)];
+    assert![island.contains(60, 60)];
+    assert![!island.contains(75, 75)];
+    // the order of the rings doesn't matter
+    let mut reversed = island.clone();
+    reversed.rings.reverse();
+    for x in 0..=100 {
+      assert_eq![reversed.contains(x, x), island.contains(x, x), "{}", x];
+    }
+    assert_eq![p.bbox(), Some(BBox { left: 0, bottom: 0, right: 300, top: 300 })];
+    assert_eq![Polygon::default().bbox(), None];
+    match node(1, 10, 10) {
+      Element::Node(node) => assert![p.contains_node(&node)],
+      _ => unreachable![],
+    }
+  }
+
+  #[test]
+  fn poly_round_trip() {
+    let p = Polygon::new(vec![
+      Ring::new(vec![
+        (-1_500_000_000,0), (10*DEG,-123_456_789), (10*DEG,10*DEG), (-1_500_000_000,0),
+      ], false),
+      square(DEG, 2*DEG, true),
+      square(20*DEG, 21*DEG, false),
+    ]);
+    let text = p.to_poly("test");
+    assert![text.starts_with("test\n1\n\t-1.5\t0\n\t10\t-0.123456789\n")];
+    assert![text.contains("\n!2\n")];
+    assert![text.ends_with("END\nEND\n")];
+    assert_eq![Polygon::parse_poly(&text).unwrap(), p];
+  }
+
+  #[test]
+  fn parse_poly() {
+    let text = "berlin\n1\n  1.0E+01  5.5\n  20 5.5\n\n 20 6\nEND\n\
+      !hole\n 15 5.7\n 16 5.7\n 16 5.8\nEND\nEND\n";
+    let p = Polygon::parse_poly(text).unwrap();
+    assert_eq![p.rings.len(), 2];
+    let outer = vec![(10*DEG,5_500_000_000), (20*DEG,5_500_000_000), (20*DEG,6*DEG)];
+    assert_eq![p.rings[0], Ring::new(outer, false)];
+    assert![p.rings[1].hole];
+    assert![Polygon::parse_poly("x\nEND\n").unwrap().rings.is_empty()];
+    for bad in [
+      "", "x\n", "x\n1\n 1 2\nEND\n", "x\n1\n 1 2\n", "x\n1\n 1\nEND\nEND\n",
+      "x\n1\n 1 2 3\nEND\nEND\n", "x\n1\n 1 a\nEND\nEND\n", "x\n1\n nan 0\nEND\nEND\n",
+      "x\n1\n 0 inf\nEND\nEND\n", "x\n1\n 180.5 0\nEND\nEND\n", "x\n1\n 0 -90.1\nEND\nEND\n",
+      "x\n1\n 1e300 0\nEND\nEND\n",
+    ].iter() {
+      assert![Polygon::parse_poly(bad).is_err(), "{:?}", bad];
+    }
+    assert![Polygon::parse_poly("x\n1\n -180 90\nEND\nEND\n").is_ok()];
+  }
+
+  #[test]
+  fn join() {
+    // a ring split into three ways, one of them reversed
+    let (a,b,c) = (way(1, &[1,2,3]), way(2, &[5,4,3]), way(3, &[5,6,1]));
+    let rings = join_rings(vec![&a,&b,&c], 1).unwrap();
+    assert_eq![rings.len(), 1];
+    let ring = &rings[0];
+    assert_eq![ring.len(), 7];
+    assert_eq![ring.first(), ring.last()];
+    let mut nodes = ring[1..].to_vec();
+    nodes.sort_unstable();
+    assert_eq![nodes, vec![1,2,3,4,5,6]];
+    // two closed ways make two rings
+    let (d,e) = (way(4, &[1,2,3,1]), way(5, &[7,8,9,7]));
+    assert_eq![join_rings(vec![&d,&e], 1).unwrap().len(), 2];
+    // a gap can't be closed
+    let (f,g) = (way(6, &[1,2,3]), way(7, &[4,5,1]));
+    assert![join_rings(vec![&f,&g], 1).is_err()];
+    assert![join_rings(vec![&a], 1).is_err()];
+    // closed ways that are too short for a ring
+    let (h,i,j) = (way(8, &[1]), way(9, &[1,2,1]), way(10, &[1,2,3,1]));
+    assert![join_rings(vec![&h], 1).is_err()];
+    assert![join_rings(vec![&i], 1).is_err()];
+    assert_eq![join_rings(vec![&j], 1).unwrap(), vec![vec![1,2,3,1]]];
+    // two ways that only close into a line
+    let (k,l) = (way(11, &[1,2]), way(12, &[2,1]));
+    assert![join_rings(vec![&k,&l], 1).is_err()];
+  }
+
+  #[test]
+  fn relation_polygon() {
+    let multipolygon = |id, members: &[(i64,&str)]| {
+      let ways = members.iter().map(|(id,_)| (MemberType::Way,*id)).collect::<Vec<_>>();
+      let mut r = relation(id, "multipolygon", &ways);
+      if let Element::Relation(r) = &mut r {
+        for (m,(_,role)) in r.members.iter_mut().zip(members.iter()) { m.role = role.to_string() }
+      }
+      r
+    };
+    // the outer ring is split into two ways running in opposite directions around a hole.
+    // relation 2 only has an unclosed way and relation 3 a missing one
+    let scan = scan(vec![
+      node(1,0,0), node(2,10*DEG,0), node(3,10*DEG,10*DEG), node(4,0,10*DEG),
+      node(5,4*DEG,4*DEG), node(6,6*DEG,4*DEG), node(7,6*DEG,6*DEG), node(8,4*DEG,6*DEG),
+      node(9,-123_456_789,5*DEG/2),
+      test_util::way(1, &[1,2,3]), test_util::way(2, &[1,9,4,3]),
+      test_util::way(3, &[5,6,7,8,5]), test_util::way(4, &[1,2]),
+      multipolygon(1, &[(1,"outer"),(2,""),(3,"inner")]),
+      multipolygon(2, &[(4,"outer")]),
+      multipolygon(3, &[(1,"outer"),(99,"outer")]),
+    ]);
+    let p = scan.get_relation_polygon(1).unwrap().unwrap();
+    assert_eq![p.rings.len(), 2];
+    assert_eq![p.rings[0].coords.len(), 6];
+    assert![!p.rings[0].hole];
+    assert![p.rings[1].hole];
+    assert![p.contains(2*DEG, 2*DEG)];
+    assert![p.contains(-DEG/100, 5*DEG/2)];
+    assert![!p.contains(5*DEG, 5*DEG)];
+    assert![!p.contains(11*DEG, 5*DEG)];
+    assert_eq![Polygon::parse_poly(&p.to_poly("test")).unwrap(), p];
+    assert![scan.get_relation_polygon(2).is_err()];
+    assert![scan.get_relation_polygon(3).is_err()];
+    assert_eq![scan.get_relation_polygon(99).unwrap(), None];
+  }
+}
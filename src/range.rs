@@ -0,0 +1,539 @@
+use crate::{ReadAt,Error};
+use hashbrown::{HashMap,HashSet};
+use std::collections::BTreeMap;
+use std::sync::{Arc,Mutex,MutexGuard,Condvar};
+
+pub trait RangeSource {
+  // fetch up to len bytes starting at offset. fewer bytes are returned only at the end of the data
+  fn read_range(&self, offset: u64, len: u64) -> Result<Vec<u8>,Error>;
+}
+
+impl<T> RangeSource for Arc<T> where T: RangeSource+?Sized {
+  fn read_range(&self, offset: u64, len: u64) -> Result<Vec<u8>,Error> {
+    (**self).read_range(offset, len)
+  }
+}
+
+type Block = Arc<Vec<u8>>;
+
+struct BlockCache {
+  // block number to (last use, data)
+  blocks: HashMap<u64,(u64,Block)>,
+  // last use to block number, oldest first
+  order: BTreeMap<u64,u64>,
+  // blocks that some reader is fetching right now
+  fetching: HashSet<u64>,
+  tick: u64,
+}
+
+impl BlockCache {
+  fn get(&mut self, b: u64) -> Option<Block> {
+    self.tick += 1;
+    let tick = self.tick;
+    let entry = self.blocks.get_mut(&b)?;
+    self.order.remove(&entry.0);
+    self.order.insert(tick, b);
+    entry.0 = tick;
+    Some(entry.1.clone())
+  }
+  fn insert(&mut self, b: u64, data: Block, max_blocks: usize) {
+    self.tick += 1;
+    if let Some((t,_)) = self.blocks.insert(b, (self.tick,data)) {
+      self.order.remove(&t);
+    }
+    self.order.insert(self.tick, b);
+    while self.blocks.len() > max_blocks {
+      let (t,oldest) = match self.order.iter().next() {
+        Some((t,b)) => (*t,*b),
+        None => break,
+      };
+      self.order.remove(&t);
+      self.blocks.remove(&oldest);
+    }
+  }
+}
+
+// fronts a RangeSource with a block cache so that it can be used as a ReadAt source.
+// reads are rounded out to whole blocks and each run of adjacent blocks that a read is missing
+// is fetched with a single range request. a reader that needs a block another reader is already
+// fetching waits for that fetch instead of sending its own. when the cache is full the least
+// recently used block is dropped
+pub struct CachedRange<S: RangeSource> {
+  source: S,
+  block_size: u64,
+  max_blocks: usize,
+  cache: Mutex<BlockCache>,
+  fetched: Condvar,
+}
+
+impl<S> CachedRange<S> where S: RangeSource {
+  pub fn new(source: S) -> Self {
+    Self::with_capacity(source, 1<<20, 256)
+  }
+  pub fn with_capacity(source: S, block_size: u64, max_blocks: usize) -> Self {
+    Self {
+      source,
+      block_size: block_size.max(1),
+      max_blocks: max_blocks.max(1),
+      cache: Mutex::new(BlockCache {
+        blocks: HashMap::new(),
+        order: BTreeMap::new(),
+        fetching: HashSet::new(),
+        tick: 0,
+      }),
+      fetched: Condvar::new(),
+    }
+  }
+  pub fn source(&self) -> &S {
+    &self.source
+  }
+  fn lock(&self) -> MutexGuard<'_,BlockCache> {
+    self.cache.lock().unwrap_or_else(|e| e.into_inner())
+  }
+  // blocks first..=last. blocks past the end of the data are empty
+  fn get_blocks(&self, first: u64, last: u64) -> Result<Vec<Block>,Error> {
+    let mut blocks: Vec<Option<Block>> = vec![None; (last - first + 1) as usize];
+    let mut cache = self.lock();
+    loop {
+      let mut missing = vec![];
+      let mut waiting = false;
+      for (i,b) in (first..=last).enumerate() {
+        if blocks[i].is_some() { continue }
+        if let Some(data) = cache.get(b) {
+          blocks[i] = Some(data);
+        } else if cache.fetching.contains(&b) {
+          waiting = true;
+        } else {
+          missing.push(b);
+        }
+      }
+      if missing.is_empty() {
+        if !waiting { break }
+        cache = self.fetched.wait(cache).unwrap_or_else(|e| e.into_inner());
+        continue;
+      }
+      cache.fetching.extend(missing.iter().copied());
+      drop(cache);
+      let result = self.fetch(&missing);
+      cache = self.lock();
+      for b in missing.iter() {
+        cache.fetching.remove(b);
+      }
+      self.fetched.notify_all();
+      for (b,data) in result? {
+        if !data.is_empty() { cache.insert(b, data.clone(), self.max_blocks) }
+        blocks[(b - first) as usize] = Some(data);
+      }
+    }
+    Ok(blocks.into_iter().flatten().collect())
+  }
+  // fetch each run of adjacent blocks with one request
+  fn fetch(&self, missing: &[u64]) -> Result<Vec<(u64,Block)>,Error> {
+    let mut found = vec![];
+    let mut i = 0;
+    while i < missing.len() {
+      let mut j = i;
+      while j+1 < missing.len() && missing[j+1] == missing[j]+1 { j += 1 }
+      let start = missing[i] * self.block_size;
+      let len = (missing[j] - missing[i] + 1) * self.block_size;
+      let data = self.source.read_range(start, len)?;
+      let mut chunks = data.chunks(self.block_size as usize);
+      for b in missing[i]..=missing[j] {
+        found.push((b, Arc::new(chunks.next().map(|c| c.to_vec()).unwrap_or_default())));
+      }
+      i = j+1;
+    }
+    Ok(found)
+  }
+}
+
+impl<S> ReadAt for CachedRange<S> where S: RangeSource {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    if buf.is_empty() { return Ok(()) }
+    let end = offset + buf.len() as u64;
+    let first = offset / self.block_size;
+    let last = (end - 1) / self.block_size;
+    let mut n = 0;
+    for (i,block) in self.get_blocks(first, last)?.iter().enumerate() {
+      let block_start = (first + i as u64) * self.block_size;
+      let from = (offset + n as u64 - block_start) as usize;
+      if from >= block.len() { break }
+      let count = (block.len() - from).min(buf.len() - n);
+      buf[n..n+count].copy_from_slice(&block[from..from+count]);
+      n += count;
+    }
+    if n < buf.len() {
+      return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature="http")]
+pub use http::HttpSource;
+
+#[cfg(feature="http")]
+mod http {
+  use super::RangeSource;
+  use crate::Error;
+  use std::io::{Read,Write,BufRead,BufReader};
+  use std::net::TcpStream;
+
+  type Headers = Vec<(String,String)>;
+
+  // a minimal HTTP/1.1 client that issues one Range request per connection. chunked replies are
+  // decoded and a reply whose Content-Range differs from the requested range is an error.
+  // only plain http:// urls are supported
+  #[derive(Debug,Clone)]
+  pub struct HttpSource {
+    host: String,
+    port: u16,
+    path: String,
+  }
+
+  impl HttpSource {
+    pub fn new(url: &str) -> Result<Self,Error> {
+      let rest = url.strip_prefix("http://")
+        .ok_or_else(|| format!["unsupported url (expected http://): {}", url])?;
+      let (authority,path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+      };
+      let (host,port) = match authority.rfind(':') {
+        Some(i) => (&authority[..i], authority[i+1..].parse::<u16>()?),
+        None => (authority, 80),
+      };
+      Ok(Self { host: host.to_string(), port, path: path.to_string() })
+    }
+    pub fn size(&self) -> Result<u64,Error> {
+      let (headers,_body) = self.request(0, 1)?;
+      headers.iter()
+        .find(|(k,_)| k == "content-range")
+        .and_then(|(_,v)| v.rsplit('/').next())
+        .and_then(|total| total.trim().parse::<u64>().ok())
+        .ok_or_else(|| "server did not report the total length in content-range".into())
+    }
+    fn request(&self, offset: u64, len: u64) -> Result<(Headers,Vec<u8>),Error> {
+      let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+      let host = match self.port {
+        80 => self.host.clone(),
+        port => format!["{}:{}", self.host, port],
+      };
+      write![stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: close\r\n\r\n",
+        self.path, host, offset, offset + len - 1
+      ]?;
+      stream.flush()?;
+      let mut reader = BufReader::new(stream);
+      let mut line = String::new();
+      reader.read_line(&mut line)?;
+      let status = line.split_whitespace().nth(1).unwrap_or("");
+      match status {
+        "206" => {},
+        "416" => return Ok((vec![],vec![])),
+        _ => return Err(format!["unexpected http response to range request: {}", line.trim()].into()),
+      }
+      let headers = read_headers(&mut reader)?;
+      let header = |name: &str| headers.iter().find(|(k,_)| k == name).map(|(_,v)| v.as_str());
+      // the server may answer with a different range than the one asked for
+      let range = header("content-range")
+        .and_then(|v| v.strip_prefix("bytes "))
+        .and_then(|v| v.split('/').next())
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start,end)| {
+          Some((start.trim().parse::<u64>().ok()?, end.trim().parse::<u64>().ok()?))
+        });
+      match range {
+        Some((start,end)) if start == offset && end >= start && end < offset + len => {},
+        _ => return Err(format![
+          "content-range {:?} does not match the requested range {}-{}",
+          header("content-range").unwrap_or(""), offset, offset + len - 1
+        ].into()),
+      }
+      let chunked = header("transfer-encoding")
+        .map(|v| v.to_lowercase().contains("chunked"))
+        .unwrap_or(false);
+      let content_length = header("content-length").and_then(|v| v.parse::<u64>().ok());
+      let mut body = vec![];
+      if chunked {
+        read_chunked(&mut reader, &mut body)?;
+      } else {
+        match content_length {
+          Some(n) => { reader.take(n).read_to_end(&mut body)?; },
+          None => { reader.read_to_end(&mut body)?; },
+        }
+      }
+      Ok((headers,body))
+    }
+  }
+
+  fn read_headers(reader: &mut impl BufRead) -> Result<Headers,Error> {
+    let mut headers = vec![];
+    let mut line = String::new();
+    loop {
+      line.clear();
+      if reader.read_line(&mut line)? == 0 { break }
+      let l = line.trim_end();
+      if l.is_empty() { break }
+      if let Some(i) = l.find(':') {
+        headers.push((l[..i].trim().to_lowercase(), l[i+1..].trim().to_string()));
+      }
+    }
+    Ok(headers)
+  }
+
+  // decode a Transfer-Encoding: chunked body. trailers are read and dropped
+  fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>) -> Result<(),Error> {
+    let mut line = String::new();
+    loop {
+      line.clear();
+      if reader.read_line(&mut line)? == 0 {
+        return Err("http response ended inside a chunked body".into());
+      }
+      let size = line.trim().split(';').next().unwrap_or("");
+      let size = u64::from_str_radix(size.trim(), 16)
+        .map_err(|_| format!["invalid chunk size in http response: {:?}", line.trim()])?;
+      if size == 0 {
+        read_headers(reader)?;
+        return Ok(());
+      }
+      let n = reader.by_ref().take(size).read_to_end(body)?;
+      if n as u64 != size {
+        return Err("http response ended inside a chunk".into());
+      }
+      line.clear();
+      reader.read_line(&mut line)?;
+      if !line.trim().is_empty() {
+        return Err("missing line break after a chunk in http response".into());
+      }
+    }
+  }
+
+  impl RangeSource for HttpSource {
+    fn read_range(&self, offset: u64, len: u64) -> Result<Vec<u8>,Error> {
+      if len == 0 { return Ok(vec![]) }
+      let (_headers,mut body) = self.request(offset, len)?;
+      body.truncate(len as usize);
+      Ok(body)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{CachedRange,RangeSource};
+  use crate::{ReadAt,Error};
+  use std::sync::{Mutex,atomic::{AtomicUsize,Ordering}};
+
+  // serves ranges of data from memory, recording each request
+  struct Memory {
+    data: Vec<u8>,
+    requests: Mutex<Vec<(u64,u64)>>,
+    delay: std::time::Duration,
+  }
+
+  impl Memory {
+    fn new(len: usize) -> Self {
+      let data = (0..len).map(|i| (i % 251) as u8).collect();
+      Self { data, requests: Mutex::new(vec![]), delay: std::time::Duration::from_millis(0) }
+    }
+    fn requests(&self) -> Vec<(u64,u64)> {
+      self.requests.lock().unwrap().clone()
+    }
+  }
+
+  impl RangeSource for Memory {
+    fn read_range(&self, offset: u64, len: u64) -> Result<Vec<u8>,Error> {
+      self.requests.lock().unwrap().push((offset,len));
+      std::thread::sleep(self.delay);
+      let start = (offset as usize).min(self.data.len());
+      let end = (offset + len).min(self.data.len() as u64) as usize;
+      Ok(self.data[start..end].to_vec())
+    }
+  }
+
+  fn read(cached: &CachedRange<Memory>, offset: u64, len: usize) -> Vec<u8> {
+    let mut buf = vec![0;len];
+    cached.read_at(offset, &mut buf).unwrap();
+    buf
+  }
+
+  #[test]
+  fn coalesce_and_evict() {
+    let cached = CachedRange::with_capacity(Memory::new(1050), 100, 3);
+    let data = cached.source().data.clone();
+    // blocks 1 and 2 are fetched together
+    assert_eq![read(&cached, 150, 100), data[150..250].to_vec()];
+    assert_eq![cached.source().requests(), vec![(100,200)]];
+    // block 2 is cached, so only block 3 is fetched
+    assert_eq![read(&cached, 250, 100), data[250..350].to_vec()];
+    assert_eq![cached.source().requests().last(), Some(&(300,100))];
+    // using block 1 again makes block 2 the least recently used, which block 5 pushes out
+    read(&cached, 120, 10);
+    read(&cached, 500, 10);
+    assert_eq![cached.source().requests().len(), 3];
+    read(&cached, 120, 10);
+    read(&cached, 320, 10);
+    assert_eq![cached.source().requests().len(), 3];
+    read(&cached, 220, 10);
+    assert_eq![cached.source().requests().last(), Some(&(200,100))];
+    // reads past the end of the data fail, reads up to it don't
+    assert_eq![read(&cached, 1000, 50), data[1000..].to_vec()];
+    let mut buf = vec![0;60];
+    assert![cached.read_at(1000, &mut buf).is_err()];
+    assert![cached.read_at(2000, &mut buf).is_err()];
+  }
+
+  #[test]
+  fn concurrent_reads_share_fetches() {
+    let mut source = Memory::new(10_000);
+    source.delay = std::time::Duration::from_millis(50);
+    let cached = CachedRange::with_capacity(source, 1000, 16);
+    let done = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+      for i in 0..8 {
+        let (cached,done) = (&cached,&done);
+        s.spawn(move || {
+          let offset = 1500 + i * 10;
+          assert_eq![read(cached, offset, 2000), cached.source().data[offset as usize..][..2000]];
+          done.fetch_add(1, Ordering::SeqCst);
+        });
+      }
+    });
+    assert_eq![done.load(Ordering::SeqCst), 8];
+    // every thread needs blocks 1 to 3, which one request fetches while the others wait for it
+    assert_eq![cached.source().requests(), vec![(1000,3000)]];
+  }
+
+  #[cfg(feature="http")]
+  mod http {
+    use super::super::{CachedRange,HttpSource,RangeSource};
+    use crate::{Parser,Scan,PbfWriter,Element,ReadAt};
+    use crate::test_util::{node,way,scan_data};
+    use std::io::{BufRead,BufReader,Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc,atomic::{AtomicUsize,Ordering}};
+
+    fn pbf() -> Vec<u8> {
+      let mut writer = PbfWriter::new(vec![]);
+      writer.set_block_size(100);
+      for id in 1..=5000 {
+        let mut e = node(id, id * 100_000, id * 500_000);
+        let name = format!["{:x}", (id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)];
+        if let Element::Node(node) = &mut e { node.tags.push(("name".into(),name)) }
+        writer.write(e).unwrap();
+      }
+      for id in 1..=100 {
+        let mut e = way(id, &(id*10-9..=id*10).collect::<Vec<_>>());
+        if let Element::Way(way) = &mut e { way.tags.push(("highway".into(),"path".into())) }
+        writer.write(e).unwrap();
+      }
+      writer.finish().unwrap()
+    }
+
+    // serves data with range request support and counts the requests it answers. with chunked,
+    // bodies are sent with Transfer-Encoding: chunked. with shift, the reply starts one byte late.
+    // requests without a Host header naming the port are answered with 400
+    fn serve(data: Vec<u8>, chunked: bool, shift: usize) -> (String,Arc<AtomicUsize>) {
+      let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+      let host = format!["127.0.0.1:{}", listener.local_addr().unwrap().port()];
+      let url = format!["http://{}/file.pbf", host];
+      let requests = Arc::new(AtomicUsize::new(0));
+      let count = requests.clone();
+      std::thread::spawn(move || {
+        for stream in listener.incoming() {
+          let mut stream = match stream { Ok(s) => s, Err(_) => continue };
+          let mut reader = BufReader::new(stream.try_clone().unwrap());
+          let mut range = None;
+          let mut host_ok = false;
+          let mut line = String::new();
+          while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+            if let Some(r) = line.to_lowercase().strip_prefix("range: bytes=") {
+              let (start,end) = r.trim().split_once('-').unwrap();
+              range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+            }
+            if let Some(h) = line.to_lowercase().strip_prefix("host:") {
+              host_ok = h.trim() == host;
+            }
+            line.clear();
+          }
+          count.fetch_add(1, Ordering::SeqCst);
+          if !host_ok {
+            let _ = write![stream, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n"];
+            continue;
+          }
+          let (start,end) = range.unwrap_or((0,data.len()-1));
+          if start >= data.len() {
+            let _ = write![stream,
+              "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n\r\n"];
+            continue;
+          }
+          let (start,end) = ((start + shift).min(data.len()-1), end.min(data.len()-1));
+          let body = &data[start..=end];
+          let _ = write![stream,
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            start, end, data.len()];
+          if chunked {
+            let _ = write![stream, "Transfer-Encoding: chunked\r\n\r\n"];
+            for chunk in body.chunks(1000) {
+              let _ = write![stream, "{:x}\r\n", chunk.len()];
+              let _ = stream.write_all(chunk);
+              let _ = write![stream, "\r\n"];
+            }
+            let _ = write![stream, "0\r\n\r\n"];
+          } else {
+            let _ = write![stream, "Content-Length: {}\r\n\r\n", body.len()];
+            let _ = stream.write_all(body);
+          }
+        }
+      });
+      (url,requests)
+    }
+
+    #[test]
+    fn http_range_reads() {
+      let data = pbf();
+      assert![data.len() > 16*1024];
+      for chunked in [false,true].iter() {
+        let (url,requests) = serve(data.clone(), *chunked, 0);
+        let source = HttpSource::new(&url).unwrap();
+        assert_eq![source.size().unwrap(), data.len() as u64];
+        assert_eq![source.read_range(10, 3000).unwrap(), data[10..3010].to_vec()];
+        let end = data.len() as u64 - 5;
+        assert_eq![source.read_range(end, 100).unwrap(), data[end as usize..].to_vec()];
+        assert_eq![requests.load(Ordering::SeqCst), 3];
+
+        // four missing blocks in a row are fetched with one request, then served from the cache
+        let cached = CachedRange::with_capacity(HttpSource::new(&url).unwrap(), 1024, 64);
+        let mut buf = vec![0;4000];
+        cached.read_at(100, &mut buf).unwrap();
+        assert_eq![buf, data[100..4100].to_vec()];
+        assert_eq![requests.load(Ordering::SeqCst), 4];
+        cached.read_at(1500, &mut buf[..2000]).unwrap();
+        assert_eq![&buf[..2000], &data[1500..3500]];
+        assert_eq![requests.load(Ordering::SeqCst), 4];
+      }
+    }
+
+    #[test]
+    fn http_lookups_match_local() {
+      let data = pbf();
+      let local = scan_data(data.clone());
+      let (url,requests) = serve(data.clone(), true, 0);
+      let source = CachedRange::with_capacity(HttpSource::new(&url).unwrap(), 1024, 64);
+      let remote = Scan::from_table(Parser::new(Box::new(source)), local.table.clone());
+      for id in [1,2500,5000].iter() {
+        assert_eq![remote.get_node(*id).unwrap(), local.get_node(*id).unwrap()];
+      }
+      assert_eq![remote.get_way(42).unwrap(), local.get_way(42).unwrap()];
+      assert![remote.get_way(42).unwrap().is_some()];
+      assert![requests.load(Ordering::SeqCst) < data.len() / 1024];
+    }
+
+    #[test]
+    fn http_rejects_mismatched_content_range() {
+      let (url,_) = serve(pbf(), false, 1);
+      assert![HttpSource::new(&url).unwrap().read_range(10, 100).is_err()];
+    }
+  }
+}
@@ -0,0 +1,142 @@
+use crate::Error;
+use std::io::{Read,Seek,SeekFrom};
+use std::sync::{Arc,Mutex};
+
+pub trait ReadAt {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error>;
+  // sources that already hold the bytes in memory can lend them out to skip a copy
+  fn slice_at(&self, _offset: u64, _len: usize) -> Option<&[u8]> { None }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)?;
+    Ok(())
+  }
+}
+
+#[cfg(windows)]
+impl ReadAt for std::fs::File {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    let mut n = 0;
+    while n < buf.len() {
+      let r = std::os::windows::fs::FileExt::seek_read(self, &mut buf[n..], offset + n as u64)?;
+      if r == 0 {
+        return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+      }
+      n += r;
+    }
+    Ok(())
+  }
+}
+
+impl ReadAt for [u8] {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    let start = offset as usize;
+    let end = start.checked_add(buf.len()).filter(|end| *end <= self.len());
+    match end {
+      Some(end) => {
+        buf.copy_from_slice(&self[start..end]);
+        Ok(())
+      },
+      None => Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))),
+    }
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    let start = offset as usize;
+    let end = start.checked_add(len).filter(|end| *end <= self.len())?;
+    Some(&self[start..end])
+  }
+}
+
+impl ReadAt for Vec<u8> {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    self.as_slice().read_at(offset, buf)
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    self.as_slice().slice_at(offset, len)
+  }
+}
+
+impl<T> ReadAt for &T where T: ReadAt+?Sized {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    (**self).read_at(offset, buf)
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    (**self).slice_at(offset, len)
+  }
+}
+
+impl<T> ReadAt for Box<T> where T: ReadAt+?Sized {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    (**self).read_at(offset, buf)
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    (**self).slice_at(offset, len)
+  }
+}
+
+impl<T> ReadAt for Arc<T> where T: ReadAt+?Sized {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    (**self).read_at(offset, buf)
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    (**self).slice_at(offset, len)
+  }
+}
+
+#[cfg(feature="mmap")]
+pub struct MmapSource {
+  mmap: memmap2::Mmap,
+}
+
+#[cfg(feature="mmap")]
+impl MmapSource {
+  pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self,Error> {
+    Self::from_file(&std::fs::File::open(path)?)
+  }
+  pub fn from_file(file: &std::fs::File) -> Result<Self,Error> {
+    let mmap = unsafe { memmap2::Mmap::map(file)? };
+    Ok(Self { mmap })
+  }
+  pub fn len(&self) -> u64 {
+    self.mmap.len() as u64
+  }
+  pub fn is_empty(&self) -> bool {
+    self.mmap.is_empty()
+  }
+}
+
+#[cfg(feature="mmap")]
+impl ReadAt for MmapSource {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    self.mmap[..].read_at(offset, buf)
+  }
+  fn slice_at(&self, offset: u64, len: usize) -> Option<&[u8]> {
+    self.mmap[..].slice_at(offset, len)
+  }
+}
+
+// adapts any Read+Seek handle by serializing seek+read pairs behind a lock
+pub struct SeekReader<F: Read+Seek> {
+  handle: Mutex<F>,
+}
+
+impl<F> SeekReader<F> where F: Read+Seek {
+  pub fn new(handle: F) -> Self {
+    Self { handle: Mutex::new(handle) }
+  }
+  pub fn into_inner(self) -> F {
+    self.handle.into_inner().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+impl<F> ReadAt for SeekReader<F> where F: Read+Seek {
+  fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(),Error> {
+    let mut handle = self.handle.lock().unwrap_or_else(|e| e.into_inner());
+    handle.seek(SeekFrom::Start(offset))?;
+    handle.read_exact(buf)?;
+    Ok(())
+  }
+}
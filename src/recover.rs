@@ -0,0 +1,308 @@
+use crate::{Parser,Scan,ReadAt,BlobHeader,BlobRef,element,Error};
+use crate::decode::{check_blob_header_size,check_blob_size};
+use quick_protobuf::{MessageRead,BytesReader};
+
+// a region of the file that could not be decoded and was skipped over by a recovering reader.
+// start is where the bad fileblock began and end is where the next valid fileblock was found
+#[derive(Debug,Clone,PartialEq)]
+pub struct SkippedRange {
+  pub start: u64,
+  pub end: u64,
+  pub error: String,
+}
+
+impl SkippedRange {
+  pub fn len(&self) -> u64 {
+    self.end - self.start
+  }
+  pub fn is_empty(&self) -> bool {
+    self.start == self.end
+  }
+}
+
+// add a skipped range, merging it into the previous one when a resync point turns out to be bad too
+pub(crate) fn push_skipped(skipped: &mut Vec<SkippedRange>, start: u64, end: u64, error: &Error) {
+  match skipped.last_mut() {
+    Some(last) if last.end == start => last.end = end,
+    _ => skipped.push(SkippedRange { start, end, error: error.to_string() }),
+  }
+}
+
+// every blob header starts with its type string (field 1) so a fileblock can be recognized by
+// the bytes 0x0a <len> "OSMData" or "OSMHeader" following the 4-byte header length
+const SIGNATURES: &[&[u8]] = &[b"\x0a\x07OSMData", b"\x0a\x09OSMHeader"];
+pub(crate) const SIGNATURE_OVERLAP: usize = 4 + 11;
+
+// position of the first possible fileblock start in buf
+pub(crate) fn find_signature(buf: &[u8]) -> Option<usize> {
+  (4..buf.len()).find(|i| SIGNATURES.iter().any(|sig| buf[*i..].starts_with(sig))).map(|i| i-4)
+}
+
+// how a possible fileblock at the start of a buffer checks out
+pub(crate) enum Candidate {
+  // a fileblock that decodes cleanly
+  Valid,
+  Invalid,
+  // the buffer has to hold at least this many bytes to tell
+  Incomplete(usize),
+}
+
+// check that the bytes at the start of buf hold a fileblock whose header and blob both decode.
+// this is what both the file and stream readers use to pick a place to resume
+pub(crate) fn check_candidate(buf: &[u8]) -> Candidate {
+  if buf.len() < 4 { return Candidate::Incomplete(4) }
+  let len = u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as usize;
+  if check_blob_header_size(len).is_err() { return Candidate::Invalid }
+  if buf.len() < 4+len { return Candidate::Incomplete(4+len) }
+  let header_buf = &buf[4..4+len];
+  let mut reader = BytesReader::from_bytes(header_buf);
+  let blob_header = match BlobHeader::from_reader(&mut reader, header_buf) {
+    Ok(blob_header) => blob_header,
+    Err(_) => return Candidate::Invalid,
+  };
+  let datasize = match check_blob_size(blob_header.datasize as i64) {
+    Ok(datasize) => datasize,
+    Err(_) => return Candidate::Invalid,
+  };
+  let total = 4 + len + datasize;
+  if buf.len() < total { return Candidate::Incomplete(total) }
+  let blob = match BlobRef::from_bytes(&buf[4+len..total]) {
+    Ok(blob) => blob,
+    Err(_) => return Candidate::Invalid,
+  };
+  let ok = match blob_header.type_pb.as_str() {
+    "OSMHeader" => blob.decode_header().is_ok(),
+    "OSMData" => blob.decode_primitive().is_ok(),
+    _ => false,
+  };
+  if ok { Candidate::Valid } else { Candidate::Invalid }
+}
+
+const SEARCH_WINDOW: u64 = 1<<20;
+
+impl<F> Parser<F> where F: ReadAt {
+  // search forward from start for the next offset before end where a fileblock decodes cleanly
+  pub fn find_blob(&self, start: u64, end: u64) -> Result<Option<u64>,Error> {
+    let mut window_start = start;
+    while window_start < end {
+      let len = (end - window_start).min(SEARCH_WINDOW) as usize;
+      let mut buf = vec![0u8;len];
+      self.handle.read_at(window_start, &mut buf)?;
+      let mut i = 0;
+      while let Some(j) = find_signature(&buf[i..]) {
+        let offset = window_start + (i + j) as u64;
+        if self.check_fileblock(offset, end) { return Ok(Some(offset)) }
+        i += j+1;
+      }
+      if window_start + len as u64 >= end { break }
+      window_start += (len - SIGNATURE_OVERLAP) as u64;
+    }
+    Ok(None)
+  }
+  fn check_fileblock(&self, offset: u64, end: u64) -> bool {
+    let mut n = 4;
+    loop {
+      if offset + n as u64 > end { return false }
+      let owned;
+      let buf = match self.handle.slice_at(offset, n) {
+        Some(buf) => buf,
+        None => {
+          let mut buf = vec![0u8;n];
+          if self.handle.read_at(offset, &mut buf).is_err() { return false }
+          owned = buf;
+          &owned
+        },
+      };
+      match check_candidate(buf) {
+        Candidate::Valid => return true,
+        Candidate::Invalid => return false,
+        Candidate::Incomplete(m) => n = m,
+      }
+    }
+  }
+  // iterate over the fileblocks between start and end
+  pub fn blocks(&self, start: u64, end: u64) -> Blocks<'_,F> {
+    Blocks { parser: self, offset: start, end, recover: false, skipped: vec![] }
+  }
+}
+
+// yields (offset, byte length, elements) for each fileblock like StreamParser. without recovery the
+// first error ends the iteration. in recovery mode a bad fileblock is recorded in skipped() and
+// iteration resumes at the next valid fileblock
+pub struct Blocks<'a,F: ReadAt> {
+  parser: &'a Parser<F>,
+  offset: u64,
+  end: u64,
+  recover: bool,
+  skipped: Vec<SkippedRange>,
+}
+
+impl<'a,F> Blocks<'a,F> where F: ReadAt {
+  pub fn recover(mut self, recover: bool) -> Self {
+    self.recover = recover;
+    self
+  }
+  pub fn skipped(&self) -> &[SkippedRange] {
+    &self.skipped
+  }
+  pub fn into_skipped(self) -> Vec<SkippedRange> {
+    self.skipped
+  }
+}
+
+impl<'a,F> Iterator for Blocks<'a,F> where F: ReadAt {
+  type Item = Result<(u64,u64,Vec<element::Element>),Error>;
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.offset < self.end {
+      let offset = self.offset;
+      match self.parser.read(offset) {
+        Ok((len,items)) => {
+          self.offset += len;
+          return Some(Ok((offset,len,items)));
+        },
+        Err(e) if self.recover => {
+          let next = match self.parser.find_blob(offset+1, self.end) {
+            Ok(next) => next.unwrap_or(self.end),
+            Err(e) => { self.offset = self.end; return Some(Err(e)) },
+          };
+          push_skipped(&mut self.skipped, offset, next, &e);
+          self.offset = next;
+        },
+        Err(e) => {
+          self.offset = self.end;
+          return Some(Err(e));
+        },
+      }
+    }
+    None
+  }
+}
+
+impl<F> Scan<F> where F: ReadAt {
+  // like scan(), but skips over fileblocks that fail to decode and returns the skipped ranges.
+  // only errors from the underlying reads while searching for the next fileblock are returned
+  pub fn scan_recover(&mut self, start: u64, end: u64) -> Result<Vec<SkippedRange>,Error> {
+    let mut skipped = vec![];
+    let mut offset = start;
+    while offset < end {
+      match self.scan_fileblock(offset) {
+        Ok(len) => offset += len,
+        Err(e) => {
+          let next = self.parser.find_blob(offset+1, end)?.unwrap_or(end);
+          push_skipped(&mut skipped, offset, next, &e);
+          offset = next;
+        },
+      }
+    }
+    Ok(skipped)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{PbfWriter,Element};
+  use crate::test_util::{node,fileblocks,corrupt,bad_strings_block};
+
+  fn data() -> Vec<u8> {
+    let mut writer = PbfWriter::new(vec![]);
+    writer.set_block_size(50);
+    writer.write_all((1..=300).map(|id| {
+      let mut e = node(id, id * 1000, id * 1000);
+      if let Element::Node(node) = &mut e { node.tags.push(("name".into(),format!["node {}", id])) }
+      e
+    })).unwrap();
+    writer.finish().unwrap()
+  }
+
+  fn recovered(data: &[u8]) -> (Vec<(u64,u64,usize)>,Vec<SkippedRange>) {
+    let parser = Parser::new(Box::new(data.to_vec()));
+    let mut blocks = parser.blocks(0, data.len() as u64).recover(true);
+    let read = (&mut blocks).map(|r| r.map(|(offset,len,items)| (offset,len,items.len())))
+      .collect::<Result<Vec<_>,_>>().unwrap();
+    (read, blocks.into_skipped())
+  }
+
+  fn ranges(skipped: &[SkippedRange]) -> Vec<(u64,u64)> {
+    skipped.iter().map(|s| (s.start,s.end)).collect()
+  }
+
+  #[test]
+  fn push_skipped_merges() {
+    let mut skipped = vec![];
+    let (e1,e2): (Error,Error) = ("first".into(),"second".into());
+    push_skipped(&mut skipped, 10, 20, &e1);
+    // a resync point that turned out to be bad extends the range, keeping the first error
+    push_skipped(&mut skipped, 20, 30, &e2);
+    push_skipped(&mut skipped, 40, 50, &e2);
+    assert_eq![skipped, vec![
+      SkippedRange { start: 10, end: 30, error: "first".into() },
+      SkippedRange { start: 40, end: 50, error: "second".into() },
+    ]];
+    assert_eq![skipped[0].len(), 20];
+  }
+
+  #[test]
+  fn blocks_recover() {
+    let clean = data();
+    let fb = fileblocks(&clean);
+    assert_eq![fb.len(), 7];
+    let mut data = clean.clone();
+    corrupt(&mut data, fb[2]);
+    // without recovery the first error ends the iteration
+    let parser = Parser::new(Box::new(data.clone()));
+    let results = parser.blocks(0, data.len() as u64).collect::<Vec<_>>();
+    assert_eq![results.len(), 3];
+    assert![results[2].is_err()];
+    assert_eq![parser.find_blob(fb[2].0 + 1, data.len() as u64).unwrap(), Some(fb[3].0)];
+
+    let (read,skipped) = recovered(&data);
+    let offsets = read.iter().map(|(offset,_,_)| *offset).collect::<Vec<_>>();
+    assert_eq![offsets, vec![fb[0].0,fb[1].0,fb[3].0,fb[4].0,fb[5].0,fb[6].0]];
+    assert_eq![read.iter().map(|(_,_,n)| n).sum::<usize>(), 250];
+    assert_eq![ranges(&skipped), vec![(fb[2].0,fb[3].0)]];
+
+    // neighbouring bad blocks are one range, and a bad last block runs to the end
+    let mut data = clean.clone();
+    for i in [3,4,6].iter() { corrupt(&mut data, fb[*i]) }
+    let (read,skipped) = recovered(&data);
+    assert_eq![read.len(), 4];
+    assert_eq![ranges(&skipped), vec![(fb[3].0,fb[5].0),(fb[6].0,clean.len() as u64)]];
+  }
+
+  #[test]
+  fn blocks_recover_merges_bad_resync_points() {
+    // a corrupt block followed by one that passes the resync check but fails to decode fully
+    let clean = data();
+    let fb = fileblocks(&clean);
+    let bad = bad_strings_block();
+    let at = fb[3].0 as usize;
+    let mut data = clean[..at].to_vec();
+    data.extend(&bad);
+    data.extend(&clean[at..]);
+    corrupt(&mut data, fb[2]);
+    let (read,skipped) = recovered(&data);
+    assert_eq![read.len(), 6];
+    assert_eq![ranges(&skipped), vec![(fb[2].0,(at + bad.len()) as u64)]];
+    assert![!skipped[0].error.contains("string index")];
+  }
+
+  #[test]
+  fn scan_recover() {
+    let clean = data();
+    let fb = fileblocks(&clean);
+    let mut data = clean.clone();
+    corrupt(&mut data, fb[2]);
+    let len = data.len() as u64;
+    assert![Scan::new(Parser::new(Box::new(data.clone()))).scan(0, len).is_err()];
+    let mut scan = Scan::new(Parser::new(Box::new(data)));
+    let skipped = scan.scan_recover(0, len).unwrap();
+    assert_eq![ranges(&skipped), vec![(fb[2].0,fb[3].0)]];
+    // nodes 51 to 100 were in the skipped block
+    assert![scan.get_node(50).unwrap().is_some()];
+    assert![scan.get_node(51).unwrap().is_none()];
+    assert![scan.get_node(100).unwrap().is_none()];
+    assert![scan.get_node(101).unwrap().is_some()];
+    assert_eq![scan.get_node_blob_offsets().count(), 5];
+  }
+}
@@ -1,22 +1,24 @@
 use hashbrown::HashMap;
-use crate::{Parser,element,Element,Error};
+use crate::{Parser,ReadAt,element,Element,Error};
 use unbounded_interval_tree::IntervalTree;
 use std::ops::{Bound::Included,Bound};
-use std::io::{Read,Seek};
 
-pub struct Scan<F: Read+Seek> {
+pub struct Scan<F: ReadAt> {
   pub parser: Parser<F>,
   pub table: ScanTable,
 }
 
+// blobs covering each id interval. history files can have several blobs with the same interval
+pub type IntervalOffsets = HashMap<(Bound<i64>,Bound<i64>),Vec<(u64,usize,usize)>>;
+
 #[derive(Debug,Clone)]
 pub struct ScanTable {
   pub nodes: IntervalTree<i64>,
   pub ways: IntervalTree<i64>,
   pub relations: IntervalTree<i64>,
-  pub node_interval_offsets: HashMap<(Bound<i64>,Bound<i64>),(u64,usize,usize)>,
-  pub way_interval_offsets: HashMap<(Bound<i64>,Bound<i64>),(u64,usize,usize)>,
-  pub relation_interval_offsets: HashMap<(Bound<i64>,Bound<i64>),(u64,usize,usize)>,
+  pub node_interval_offsets: IntervalOffsets,
+  pub way_interval_offsets: IntervalOffsets,
+  pub relation_interval_offsets: IntervalOffsets,
 }
 impl Default for ScanTable {
   fn default() -> Self {
@@ -34,57 +36,138 @@ impl Default for ScanTable {
 impl ScanTable {
   pub fn extend(&mut self, other: &ScanTable) {
     for range in other.nodes.iter() {
-      self.nodes.insert(range.clone());
+      self.nodes.insert(*range);
     }
     for range in other.ways.iter() {
-      self.ways.insert(range.clone());
+      self.ways.insert(*range);
     }
     for range in other.relations.iter() {
-      self.relations.insert(range.clone());
+      self.relations.insert(*range);
+    }
+    for (iv,offsets) in other.node_interval_offsets.iter() {
+      self.node_interval_offsets.entry(*iv).or_insert_with(Vec::new).extend(offsets.iter());
+    }
+    for (iv,offsets) in other.way_interval_offsets.iter() {
+      self.way_interval_offsets.entry(*iv).or_insert_with(Vec::new).extend(offsets.iter());
+    }
+    for (iv,offsets) in other.relation_interval_offsets.iter() {
+      self.relation_interval_offsets.entry(*iv).or_insert_with(Vec::new).extend(offsets.iter());
+    }
+  }
+  // merge a table built for one part of a MultiSource, shifting its offsets by the part's base
+  pub fn extend_with_offset(&mut self, other: &ScanTable, base: u64) {
+    for range in other.nodes.iter() {
+      self.nodes.insert(*range);
+    }
+    for range in other.ways.iter() {
+      self.ways.insert(*range);
+    }
+    for range in other.relations.iter() {
+      self.relations.insert(*range);
+    }
+    for (iv,offsets) in other.node_interval_offsets.iter() {
+      self.node_interval_offsets.entry(*iv).or_insert_with(Vec::new)
+        .extend(offsets.iter().map(|(offset,byte_len,len)| (offset+base,*byte_len,*len)));
+    }
+    for (iv,offsets) in other.way_interval_offsets.iter() {
+      self.way_interval_offsets.entry(*iv).or_insert_with(Vec::new)
+        .extend(offsets.iter().map(|(offset,byte_len,len)| (offset+base,*byte_len,*len)));
+    }
+    for (iv,offsets) in other.relation_interval_offsets.iter() {
+      self.relation_interval_offsets.entry(*iv).or_insert_with(Vec::new)
+        .extend(offsets.iter().map(|(offset,byte_len,len)| (offset+base,*byte_len,*len)));
+    }
+  }
+  pub fn insert_items(&mut self, blob_offset: u64, blob_len: usize, items: &[Element]) {
+    let mut etype = element::MemberType::Node;
+    let mut min_id = i64::MAX;
+    let mut max_id = i64::MIN;
+    let mut count = 0;
+    for item in items.iter() {
+      match item {
+        Element::Node(node) => {
+          min_id = node.id.min(min_id);
+          max_id = node.id.max(max_id);
+          count += 1;
+        },
+        Element::Way(way) => {
+          etype = element::MemberType::Way;
+          min_id = way.id.min(min_id);
+          max_id = way.id.max(max_id);
+          count += 1;
+        },
+        Element::Relation(relation) => {
+          etype = element::MemberType::Relation;
+          min_id = relation.id.min(min_id);
+          max_id = relation.id.max(max_id);
+          count += 1;
+        },
+        Element::Changeset(_) => {}, // not indexed
+      }
+    }
+    if count > 0 {
+      let iv = (Included(min_id),Included(max_id));
+      match etype {
+        element::MemberType::Node => {
+          self.node_interval_offsets.entry(iv).or_insert_with(Vec::new)
+            .push((blob_offset,blob_len,count));
+          self.nodes.insert(iv);
+        },
+        element::MemberType::Way => {
+          self.way_interval_offsets.entry(iv).or_insert_with(Vec::new)
+            .push((blob_offset,blob_len,count));
+          self.ways.insert(iv);
+        },
+        element::MemberType::Relation => {
+          self.relation_interval_offsets.entry(iv).or_insert_with(Vec::new)
+            .push((blob_offset,blob_len,count));
+          self.relations.insert(iv);
+        },
+      }
     }
-    self.node_interval_offsets.extend(other.node_interval_offsets.iter());
-    self.way_interval_offsets.extend(other.way_interval_offsets.iter());
-    self.relation_interval_offsets.extend(other.relation_interval_offsets.iter());
   }
   pub fn get_node_blob_offsets(&self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
-    self.node_interval_offsets.values().cloned()
+    self.node_interval_offsets.values().flatten().cloned()
   }
   pub fn get_node_blob_offsets_for_id(&self, id: i64) -> Vec<(u64,usize,usize)> {
     let q = (Included(id),Included(id));
-    self.nodes.get_interval_overlaps(&q).iter()
-      .map(|iv| self.node_interval_offsets.get(iv))
-      .filter(|o_pair| o_pair.is_some())
-      .map(|o_pair| o_pair.unwrap())
+    let mut offsets = self.nodes.get_interval_overlaps(&q).iter()
+      .filter_map(|iv| self.node_interval_offsets.get(iv))
+      .flatten()
       .cloned()
-      .collect()
+      .collect::<Vec<_>>();
+    offsets.sort_unstable();
+    offsets
   }
   pub fn get_way_blob_offsets(&self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
-    self.way_interval_offsets.values().cloned()
+    self.way_interval_offsets.values().flatten().cloned()
   }
   pub fn get_way_blob_offsets_for_id(&self, id: i64) -> Vec<(u64,usize,usize)> {
     let q = (Included(id),Included(id));
-    self.ways.get_interval_overlaps(&q).iter()
-      .map(|iv| self.way_interval_offsets.get(iv))
-      .filter(|o_pair| o_pair.is_some())
-      .map(|o_pair| o_pair.unwrap())
+    let mut offsets = self.ways.get_interval_overlaps(&q).iter()
+      .filter_map(|iv| self.way_interval_offsets.get(iv))
+      .flatten()
       .cloned()
-      .collect()
+      .collect::<Vec<_>>();
+    offsets.sort_unstable();
+    offsets
   }
   pub fn get_relation_blob_offsets(&self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
-    self.relation_interval_offsets.values().cloned()
+    self.relation_interval_offsets.values().flatten().cloned()
   }
   pub fn get_relation_blob_offsets_for_id(&self, id: i64) -> Vec<(u64,usize,usize)> {
     let q = (Included(id),Included(id));
-    self.relations.get_interval_overlaps(&q).iter()
-      .map(|iv| self.relation_interval_offsets.get(iv))
-      .filter(|o_pair| o_pair.is_some())
-      .map(|o_pair| o_pair.unwrap())
+    let mut offsets = self.relations.get_interval_overlaps(&q).iter()
+      .filter_map(|iv| self.relation_interval_offsets.get(iv))
+      .flatten()
       .cloned()
-      .collect()
+      .collect::<Vec<_>>();
+    offsets.sort_unstable();
+    offsets
   }
 }
 
-impl<F> Scan<F> where F: Read+Seek {
+impl<F> Scan<F> where F: ReadAt {
   pub fn new(parser: Parser<F>) -> Self {
     Self {
       parser,
@@ -97,78 +180,31 @@ impl<F> Scan<F> where F: Read+Seek {
   pub fn scan(&mut self, start: u64, end: u64) -> Result<(),Error> {
     let mut offset = start;
     while offset < end {
-      let (blob_header_len,blob_header) = self.parser.read_blob_header(offset)?;
-      let blob_offset = offset + blob_header_len;
-      let blob_len = blob_header.datasize as usize;
-      let blob = self.parser.read_blob(blob_offset, blob_len)?;
-      let len = blob_header_len + blob_len as u64;
-      if offset == 0 { // skip header
-        offset += len;
-        continue;
-      }
-      let items = blob.decode_primitive()?.decode();
-
-      let mut etype = element::MemberType::Node;
-      let mut min_id = i64::MAX;
-      let mut max_id = i64::MIN;
-      for item in items.iter() {
-        match item {
-          Element::Node(node) => {
-            min_id = node.id.min(min_id);
-            max_id = node.id.max(max_id);
-          },
-          Element::Way(way) => {
-            etype = element::MemberType::Way;
-            min_id = way.id.min(min_id);
-            max_id = way.id.max(max_id);
-          },
-          Element::Relation(relation) => {
-            etype = element::MemberType::Relation;
-            min_id = relation.id.min(min_id);
-            max_id = relation.id.max(max_id);
-          },
-        }
-      }
-      if !items.is_empty() {
-        let iv = (Included(min_id),Included(max_id));
-        match etype {
-          element::MemberType::Node => {
-            self.table.node_interval_offsets.insert(
-              iv.clone(),
-              (blob_offset,blob_len,items.len())
-            );
-            self.table.nodes.insert(iv);
-          },
-          element::MemberType::Way => {
-            self.table.way_interval_offsets.insert(
-              iv.clone(),
-              (blob_offset,blob_len,items.len())
-            );
-            self.table.ways.insert(iv);
-          },
-          element::MemberType::Relation => {
-            self.table.relation_interval_offsets.insert(
-              iv.clone(),
-              (blob_offset,blob_len,items.len())
-            );
-            self.table.relations.insert(iv);
-          },
-        }
-      }
-      offset += len;
+      offset += self.scan_fileblock(offset)?;
     }
     Ok(())
   }
+  // index the fileblock at offset and return its length
+  pub(crate) fn scan_fileblock(&mut self, offset: u64) -> Result<u64,Error> {
+    let (blob_header_len,blob_header) = self.parser.read_blob_header(offset)?;
+    let blob_offset = offset + blob_header_len;
+    let blob_len = blob_header.datasize as usize;
+    let len = blob_header_len + blob_len as u64;
+    if blob_header.type_pb != "OSMHeader" {
+      let items = self.parser.read_primitive(blob_offset, blob_len)?.decode()?;
+      self.table.insert_items(blob_offset, blob_len, &items);
+    }
+    Ok(len)
+  }
   pub fn get_node_blob_offsets(&self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
     self.table.get_node_blob_offsets()
   }
-  pub fn get_node_blob_offsets_for_id(&mut self, id: i64) -> Vec<(u64,usize,usize)> {
+  pub fn get_node_blob_offsets_for_id(&self, id: i64) -> Vec<(u64,usize,usize)> {
     self.table.get_node_blob_offsets_for_id(id)
   }
-  pub fn get_node(&mut self, id: i64) -> Result<Option<element::Node>,Error> {
+  pub fn get_node(&self, id: i64) -> Result<Option<element::Node>,Error> {
     for (offset,byte_len,_len) in self.get_node_blob_offsets_for_id(id) {
-      let blob = self.parser.read_blob(offset,byte_len)?;
-      let items = blob.decode_primitive()?.decode();
+      let items = self.parser.read_primitive(offset,byte_len)?.decode()?;
       for item in items {
         match item {
           Element::Node(node) => {
@@ -180,16 +216,15 @@ impl<F> Scan<F> where F: Read+Seek {
     }
     Ok(None)
   }
-  pub fn get_way_blob_offsets(&mut self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
+  pub fn get_way_blob_offsets(&self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
     self.table.get_way_blob_offsets()
   }
-  pub fn get_way_blob_offsets_for_id(&mut self, id: i64) -> Vec<(u64,usize,usize)> {
+  pub fn get_way_blob_offsets_for_id(&self, id: i64) -> Vec<(u64,usize,usize)> {
     self.table.get_way_blob_offsets_for_id(id)
   }
-  pub fn get_way(&mut self, id: i64) -> Result<Option<element::Way>,Error> {
+  pub fn get_way(&self, id: i64) -> Result<Option<element::Way>,Error> {
     for (offset,byte_len,_len) in self.get_way_blob_offsets_for_id(id) {
-      let blob = self.parser.read_blob(offset,byte_len)?;
-      let items = blob.decode_primitive()?.decode();
+      let items = self.parser.read_primitive(offset,byte_len)?.decode()?;
       for item in items {
         match item {
           Element::Way(way) => {
@@ -201,16 +236,15 @@ impl<F> Scan<F> where F: Read+Seek {
     }
     Ok(None)
   }
-  pub fn get_relation_blob_offsets(&mut self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
+  pub fn get_relation_blob_offsets(&self) -> impl Iterator<Item=(u64,usize,usize)>+'_ {
     self.table.get_relation_blob_offsets()
   }
-  pub fn get_relation_blob_offsets_for_id(&mut self, id: i64) -> Vec<(u64,usize,usize)> {
+  pub fn get_relation_blob_offsets_for_id(&self, id: i64) -> Vec<(u64,usize,usize)> {
     self.table.get_relation_blob_offsets_for_id(id)
   }
-  pub fn get_relation(&mut self, id: i64) -> Result<Option<element::Relation>,Error> {
+  pub fn get_relation(&self, id: i64) -> Result<Option<element::Relation>,Error> {
     for (offset,byte_len,_len) in self.get_relation_blob_offsets_for_id(id) {
-      let blob = self.parser.read_blob(offset,byte_len)?;
-      let items = blob.decode_primitive()?.decode();
+      let items = self.parser.read_primitive(offset,byte_len)?.decode()?;
       for item in items {
         match item {
           Element::Relation(relation) => {
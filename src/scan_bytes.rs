@@ -10,34 +10,43 @@ impl ToBytes for ScanTable {
   }
   fn write_bytes(&self, buf: &mut [u8]) -> Result<usize,Error> {
     let mut offset = 0;
-    offset += varint::encode(self.node_interval_offsets.len() as u64, &mut buf[offset..])?;
-    for (id_range,(byte_offset,byte_len,item_len)) in self.node_interval_offsets.iter() {
-      if let (Included(low),Included(high)) = id_range {
-        offset += varint::encode(*low as u64, &mut buf[offset..])?;
-        offset += varint::encode(*high as u64, &mut buf[offset..])?;
-        offset += varint::encode(*byte_offset, &mut buf[offset..])?;
-        offset += varint::encode(*byte_len as u64, &mut buf[offset..])?;
-        offset += varint::encode(*item_len as u64, &mut buf[offset..])?;
+    let node_len = self.node_interval_offsets.values().map(|v| v.len()).sum::<usize>();
+    offset += varint::encode(node_len as u64, &mut buf[offset..])?;
+    for (id_range,offsets) in self.node_interval_offsets.iter() {
+      for (byte_offset,byte_len,item_len) in offsets.iter() {
+        if let (Included(low),Included(high)) = id_range {
+          offset += varint::encode(*low as u64, &mut buf[offset..])?;
+          offset += varint::encode(*high as u64, &mut buf[offset..])?;
+          offset += varint::encode(*byte_offset, &mut buf[offset..])?;
+          offset += varint::encode(*byte_len as u64, &mut buf[offset..])?;
+          offset += varint::encode(*item_len as u64, &mut buf[offset..])?;
+        }
       }
     }
-    offset += varint::encode(self.way_interval_offsets.len() as u64, &mut buf[offset..])?;
-    for (id_range,(byte_offset,byte_len,item_len)) in self.way_interval_offsets.iter() {
-      if let (Included(low),Included(high)) = id_range {
-        offset += varint::encode(*low as u64, &mut buf[offset..])?;
-        offset += varint::encode(*high as u64, &mut buf[offset..])?;
-        offset += varint::encode(*byte_offset, &mut buf[offset..])?;
-        offset += varint::encode(*byte_len as u64, &mut buf[offset..])?;
-        offset += varint::encode(*item_len as u64, &mut buf[offset..])?;
+    let way_len = self.way_interval_offsets.values().map(|v| v.len()).sum::<usize>();
+    offset += varint::encode(way_len as u64, &mut buf[offset..])?;
+    for (id_range,offsets) in self.way_interval_offsets.iter() {
+      for (byte_offset,byte_len,item_len) in offsets.iter() {
+        if let (Included(low),Included(high)) = id_range {
+          offset += varint::encode(*low as u64, &mut buf[offset..])?;
+          offset += varint::encode(*high as u64, &mut buf[offset..])?;
+          offset += varint::encode(*byte_offset, &mut buf[offset..])?;
+          offset += varint::encode(*byte_len as u64, &mut buf[offset..])?;
+          offset += varint::encode(*item_len as u64, &mut buf[offset..])?;
+        }
       }
     }
-    offset += varint::encode(self.relation_interval_offsets.len() as u64, &mut buf[offset..])?;
-    for (id_range,(byte_offset,byte_len,item_len)) in self.relation_interval_offsets.iter() {
-      if let (Included(low),Included(high)) = id_range {
-        offset += varint::encode(*low as u64, &mut buf[offset..])?;
-        offset += varint::encode(*high as u64, &mut buf[offset..])?;
-        offset += varint::encode(*byte_offset, &mut buf[offset..])?;
-        offset += varint::encode(*byte_len as u64, &mut buf[offset..])?;
-        offset += varint::encode(*item_len as u64, &mut buf[offset..])?;
+    let relation_len = self.relation_interval_offsets.values().map(|v| v.len()).sum::<usize>();
+    offset += varint::encode(relation_len as u64, &mut buf[offset..])?;
+    for (id_range,offsets) in self.relation_interval_offsets.iter() {
+      for (byte_offset,byte_len,item_len) in offsets.iter() {
+        if let (Included(low),Included(high)) = id_range {
+          offset += varint::encode(*low as u64, &mut buf[offset..])?;
+          offset += varint::encode(*high as u64, &mut buf[offset..])?;
+          offset += varint::encode(*byte_offset, &mut buf[offset..])?;
+          offset += varint::encode(*byte_len as u64, &mut buf[offset..])?;
+          offset += varint::encode(*item_len as u64, &mut buf[offset..])?;
+        }
       }
     }
     Ok(offset)
@@ -47,34 +56,43 @@ impl ToBytes for ScanTable {
 impl CountBytes for ScanTable {
   fn count_bytes(&self) -> usize {
     let mut size = 0;
-    size += varint::length(self.node_interval_offsets.len() as u64);
-    for (id_range,(byte_offset,byte_len,item_len)) in self.node_interval_offsets.iter() {
-      if let (Included(low),Included(high)) = id_range {
-        size += varint::length(*low as u64);
-        size += varint::length(*high as u64);
-        size += varint::length(*byte_offset);
-        size += varint::length(*byte_len as u64);
-        size += varint::length(*item_len as u64);
+    let node_len = self.node_interval_offsets.values().map(|v| v.len()).sum::<usize>();
+    size += varint::length(node_len as u64);
+    for (id_range,offsets) in self.node_interval_offsets.iter() {
+      for (byte_offset,byte_len,item_len) in offsets.iter() {
+        if let (Included(low),Included(high)) = id_range {
+          size += varint::length(*low as u64);
+          size += varint::length(*high as u64);
+          size += varint::length(*byte_offset);
+          size += varint::length(*byte_len as u64);
+          size += varint::length(*item_len as u64);
+        }
       }
     }
-    size += varint::length(self.way_interval_offsets.len() as u64);
-    for (id_range,(byte_offset,byte_len,item_len)) in self.way_interval_offsets.iter() {
-      if let (Included(low),Included(high)) = id_range {
-        size += varint::length(*low as u64);
-        size += varint::length(*high as u64);
-        size += varint::length(*byte_offset);
-        size += varint::length(*byte_len as u64);
-        size += varint::length(*item_len as u64);
+    let way_len = self.way_interval_offsets.values().map(|v| v.len()).sum::<usize>();
+    size += varint::length(way_len as u64);
+    for (id_range,offsets) in self.way_interval_offsets.iter() {
+      for (byte_offset,byte_len,item_len) in offsets.iter() {
+        if let (Included(low),Included(high)) = id_range {
+          size += varint::length(*low as u64);
+          size += varint::length(*high as u64);
+          size += varint::length(*byte_offset);
+          size += varint::length(*byte_len as u64);
+          size += varint::length(*item_len as u64);
+        }
       }
     }
-    size += varint::length(self.relation_interval_offsets.len() as u64);
-    for (id_range,(byte_offset,byte_len,item_len)) in self.relation_interval_offsets.iter() {
-      if let (Included(low),Included(high)) = id_range {
-        size += varint::length(*low as u64);
-        size += varint::length(*high as u64);
-        size += varint::length(*byte_offset);
-        size += varint::length(*byte_len as u64);
-        size += varint::length(*item_len as u64);
+    let relation_len = self.relation_interval_offsets.values().map(|v| v.len()).sum::<usize>();
+    size += varint::length(relation_len as u64);
+    for (id_range,offsets) in self.relation_interval_offsets.iter() {
+      for (byte_offset,byte_len,item_len) in offsets.iter() {
+        if let (Included(low),Included(high)) = id_range {
+          size += varint::length(*low as u64);
+          size += varint::length(*high as u64);
+          size += varint::length(*byte_offset);
+          size += varint::length(*byte_len as u64);
+          size += varint::length(*item_len as u64);
+        }
       }
     }
     size
@@ -102,10 +120,8 @@ impl FromBytes for ScanTable {
       let (s,item_len) = varint::decode(&buf[offset..])?;
       offset += s;
       let id_range = (Included(low as i64),Included(high as i64));
-      table.node_interval_offsets.insert(
-        id_range.clone(),
-        (byte_offset, byte_len as usize, item_len as usize)
-      );
+      table.node_interval_offsets.entry(id_range).or_insert_with(Vec::new)
+        .push((byte_offset, byte_len as usize, item_len as usize));
       table.nodes.insert(id_range);
     }
     let (s,way_len) = varint::decode(&buf[offset..])?;
@@ -122,10 +138,8 @@ impl FromBytes for ScanTable {
       let (s,item_len) = varint::decode(&buf[offset..])?;
       offset += s;
       let id_range = (Included(low as i64),Included(high as i64));
-      table.way_interval_offsets.insert(
-        id_range.clone(),
-        (byte_offset, byte_len as usize, item_len as usize)
-      );
+      table.way_interval_offsets.entry(id_range).or_insert_with(Vec::new)
+        .push((byte_offset, byte_len as usize, item_len as usize));
       table.ways.insert(id_range);
     }
     let (s,relation_len) = varint::decode(&buf[offset..])?;
@@ -142,10 +156,8 @@ impl FromBytes for ScanTable {
       let (s,item_len) = varint::decode(&buf[offset..])?;
       offset += s;
       let id_range = (Included(low as i64),Included(high as i64));
-      table.relation_interval_offsets.insert(
-        id_range.clone(),
-        (byte_offset, byte_len as usize, item_len as usize)
-      );
+      table.relation_interval_offsets.entry(id_range).or_insert_with(Vec::new)
+        .push((byte_offset, byte_len as usize, item_len as usize));
       table.relations.insert(id_range);
     }
     Ok((offset,table))
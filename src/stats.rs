@@ -0,0 +1,246 @@
+use crate::{Parser,ReadAt,ScanTable,IntervalOffsets,Blob,Element,BBox,Error};
+use crate::element::nano_to_degrees;
+use std::ops::Bound::Included;
+
+// counts and id range for one element type. blobs is the number of blobs holding this type
+#[derive(Debug,Clone,PartialEq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct TypeStats {
+  pub count: u64,
+  pub blobs: u64,
+  pub min_id: Option<i64>,
+  pub max_id: Option<i64>,
+}
+
+impl TypeStats {
+  fn add_id(&mut self, id: i64) {
+    self.count += 1;
+    self.min_id = Some(self.min_id.map_or(id, |x| x.min(id)));
+    self.max_id = Some(self.max_id.map_or(id, |x| x.max(id)));
+  }
+}
+
+// compressed_bytes is the size of the data blobs as stored. the fields wrapped in Option are None
+// when the report was built from a ScanTable, which does not store them. timestamps are in seconds
+// since the unix epoch and bbox is in nanodegrees, covering every node
+#[derive(Debug,Clone,PartialEq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct Stats {
+  pub blobs: u64,
+  pub compressed_bytes: u64,
+  pub raw_bytes: Option<u64>,
+  pub nodes: TypeStats,
+  pub ways: TypeStats,
+  pub relations: TypeStats,
+  pub changesets: TypeStats,
+  pub first_timestamp: Option<i64>,
+  pub last_timestamp: Option<i64>,
+  pub tags: Option<u64>,
+  pub way_nodes: Option<u64>,
+  pub relation_members: Option<u64>,
+  pub bbox: Option<BBox>,
+  pub ordered: Option<bool>,
+  pub multiple_versions: Option<bool>,
+}
+
+impl Stats {
+  // decompressed size over stored size
+  pub fn compression_ratio(&self) -> Option<f64> {
+    match (self.raw_bytes, self.compressed_bytes) {
+      (Some(raw),c) if c > 0 => Some(raw as f64 / c as f64),
+      _ => None,
+    }
+  }
+}
+
+impl std::fmt::Display for Stats {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    fn opt<T: std::fmt::Display>(x: &Option<T>) -> String {
+      x.as_ref().map(|x| x.to_string()).unwrap_or_else(|| "-".into())
+    }
+    writeln![f, "blobs: {}", self.blobs]?;
+    writeln![f, "compressed bytes: {}", self.compressed_bytes]?;
+    writeln![f, "raw bytes: {}", opt(&self.raw_bytes)]?;
+    writeln![f, "compression ratio: {}", opt(&self.compression_ratio().map(|r| format!["{:.2}", r]))]?;
+    for (name,t) in [("nodes",&self.nodes),("ways",&self.ways),("relations",&self.relations),
+    ("changesets",&self.changesets)].iter() {
+      writeln![f, "{}: {} in {} blobs, ids {}..{}", name, t.count, t.blobs, opt(&t.min_id), opt(&t.max_id)]?;
+    }
+    writeln![f, "first timestamp: {}", opt(&self.first_timestamp)]?;
+    writeln![f, "last timestamp: {}", opt(&self.last_timestamp)]?;
+    writeln![f, "tags: {}", opt(&self.tags)]?;
+    writeln![f, "way nodes: {}", opt(&self.way_nodes)]?;
+    writeln![f, "relation members: {}", opt(&self.relation_members)]?;
+    writeln![f, "bbox: {}", opt(&self.bbox.map(|b| format!["{},{},{},{}",
+      nano_to_degrees(b.left), nano_to_degrees(b.bottom), nano_to_degrees(b.right), nano_to_degrees(b.top)
+    ]))]?;
+    writeln![f, "ordered: {}", opt(&self.ordered)]?;
+    write![f, "multiple versions: {}", opt(&self.multiple_versions)]
+  }
+}
+
+// collects Stats from any sequence of blobs and elements, such as the output of StreamParser.
+// call add_blob once per data blob and add_element for each of its elements
+pub struct StatsCollector {
+  stats: Stats,
+  // (type,id) of the last element, for the order checks
+  last: Option<(usize,i64)>,
+}
+
+impl Default for StatsCollector {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl StatsCollector {
+  pub fn new() -> Self {
+    Self {
+      stats: Stats {
+        raw_bytes: Some(0),
+        tags: Some(0),
+        way_nodes: Some(0),
+        relation_members: Some(0),
+        ordered: Some(true),
+        multiple_versions: Some(false),
+        ..Stats::default()
+      },
+      last: None,
+    }
+  }
+  // byte_len is the blob's datasize from its blob header. elements are the decoded contents
+  pub fn add_blob(&mut self, byte_len: u64, blob: &Blob, elements: &[Element]) {
+    let s = &mut self.stats;
+    s.blobs += 1;
+    s.compressed_bytes += byte_len;
+    let raw = blob.raw_size.map(|n| n as u64)
+      .or_else(|| blob.raw.as_ref().map(|r| r.len() as u64))
+      .unwrap_or(0);
+    s.raw_bytes = s.raw_bytes.map(|x| x + raw);
+    let mut seen = [false;4];
+    for e in elements.iter() {
+      seen[type_index(e)] = true;
+      self.add_element(e);
+    }
+    let s = &mut self.stats;
+    let mut types = [&mut s.nodes, &mut s.ways, &mut s.relations, &mut s.changesets];
+    for (t,seen) in types.iter_mut().zip(seen.iter()) {
+      if *seen { t.blobs += 1 }
+    }
+  }
+  pub fn add_element(&mut self, element: &Element) {
+    let s = &mut self.stats;
+    let (info,tags) = match element {
+      Element::Node(node) => {
+        s.nodes.add_id(node.id);
+        s.bbox = Some(match s.bbox {
+          Some(b) => BBox {
+            left: b.left.min(node.nano_lon),
+            right: b.right.max(node.nano_lon),
+            top: b.top.max(node.nano_lat),
+            bottom: b.bottom.min(node.nano_lat),
+          },
+          None => BBox {
+            left: node.nano_lon,
+            right: node.nano_lon,
+            top: node.nano_lat,
+            bottom: node.nano_lat,
+          },
+        });
+        (&node.info, node.tags.len())
+      },
+      Element::Way(way) => {
+        s.ways.add_id(way.id);
+        s.way_nodes = s.way_nodes.map(|x| x + way.refs.len() as u64);
+        (&way.info, way.tags.len())
+      },
+      Element::Relation(relation) => {
+        s.relations.add_id(relation.id);
+        s.relation_members = s.relation_members.map(|x| x + relation.members.len() as u64);
+        (&relation.info, relation.tags.len())
+      },
+      Element::Changeset(changeset) => {
+        s.changesets.add_id(changeset.id);
+        s.tags = s.tags.map(|x| x + changeset.tags.len() as u64);
+        return;
+      },
+    };
+    s.tags = s.tags.map(|x| x + tags as u64);
+    if let Some(t) = info.as_ref().and_then(|i| i.timestamp) {
+      s.first_timestamp = Some(s.first_timestamp.map_or(t, |x| x.min(t)));
+      s.last_timestamp = Some(s.last_timestamp.map_or(t, |x| x.max(t)));
+    }
+    let key = (type_index(element), element.id());
+    if let Some(last) = self.last {
+      if key < last { s.ordered = Some(false) }
+      if key == last { s.multiple_versions = Some(true) }
+    }
+    self.last = Some(key);
+  }
+  pub fn finish(self) -> Stats {
+    self.stats
+  }
+}
+
+// index into the per-type stats: node, way, relation, changeset
+fn type_index(element: &Element) -> usize {
+  element.member_type().map(|t| t as usize).unwrap_or(3)
+}
+
+impl<F> Parser<F> where F: ReadAt {
+  // decode every blob between start and end and collect statistics
+  pub fn stats(&self, start: u64, end: u64) -> Result<Stats,Error> {
+    let mut collector = StatsCollector::new();
+    let mut offset = start;
+    while offset < end {
+      let (len,blob_header,blob) = self.read_fileblock(offset)?;
+      if blob_header.type_pb == "OSMData" {
+        let elements = blob.decode_primitive()?.decode()?;
+        collector.add_blob(blob_header.datasize as u64, &blob, &elements);
+      }
+      offset += len;
+    }
+    Ok(collector.finish())
+  }
+}
+
+fn table_stats(offsets: &IntervalOffsets) -> TypeStats {
+  let mut t = TypeStats::default();
+  for (range,entries) in offsets.iter() {
+    if let (Included(low),Included(high)) = range {
+      t.min_id = Some(t.min_id.map_or(*low, |x| x.min(*low)));
+      t.max_id = Some(t.max_id.map_or(*high, |x| x.max(*high)));
+    }
+    t.blobs += entries.len() as u64;
+    t.count += entries.iter().map(|(_,_,n)| *n as u64).sum::<u64>();
+  }
+  t
+}
+
+impl ScanTable {
+  // counts, id ranges and blob sizes from the stored item counts without reading the file.
+  // a blob holding several element types is counted under the one type it is indexed as
+  pub fn stats(&self) -> Stats {
+    let nodes = table_stats(&self.node_interval_offsets);
+    let ways = table_stats(&self.way_interval_offsets);
+    let relations = table_stats(&self.relation_interval_offsets);
+    let mut offsets = self.node_interval_offsets.values()
+      .chain(self.way_interval_offsets.values())
+      .chain(self.relation_interval_offsets.values())
+      .flatten()
+      .map(|(offset,len,_)| (*offset,*len))
+      .collect::<Vec<_>>();
+    // insert_items indexes each blob under one type only, the last non-node type it holds, so
+    // this only drops blobs that were scanned more than once
+    offsets.sort_unstable();
+    offsets.dedup();
+    Stats {
+      blobs: offsets.len() as u64,
+      compressed_bytes: offsets.iter().map(|(_,len)| *len as u64).sum(),
+      nodes,
+      ways,
+      relations,
+      ..Stats::default()
+    }
+  }
+}
@@ -0,0 +1,332 @@
+use crate::{Blob,BlobHeader,element,Error};
+use crate::decode::{check_blob_header_size,check_blob_size};
+use crate::recover::{SkippedRange,Candidate,push_skipped,find_signature,check_candidate};
+use crate::recover::SIGNATURE_OVERLAP;
+use quick_protobuf::{MessageRead,Reader};
+use std::collections::VecDeque;
+use std::io::Read;
+
+// reads fileblocks front to back from a non-seekable source such as stdin or a pipe,
+// counting bytes so that reported offsets line up with the same file on disk
+pub struct StreamParser<R: Read> {
+  reader: R,
+  offset: u64,
+  recover: bool,
+  // bytes handed back after a failed fileblock, read again before the reader
+  pending: VecDeque<u8>,
+  // bytes of the current fileblock, kept in recovery mode so they can be searched after a failure
+  consumed: Vec<u8>,
+  skipped: Vec<SkippedRange>,
+}
+
+impl<R> StreamParser<R> where R: Read {
+  pub fn new(reader: R) -> Self {
+    Self::with_offset(reader, 0)
+  }
+  pub fn with_offset(reader: R, offset: u64) -> Self {
+    Self {
+      reader,
+      offset,
+      recover: false,
+      pending: VecDeque::new(),
+      consumed: vec![],
+      skipped: vec![],
+    }
+  }
+  // in recovery mode a fileblock that fails to decode is recorded in skipped() and reading
+  // resumes at the next valid fileblock in the stream instead of returning the error
+  pub fn set_recover(&mut self, recover: bool) {
+    self.recover = recover;
+  }
+  pub fn skipped(&self) -> &[SkippedRange] {
+    &self.skipped
+  }
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+  pub fn into_inner(self) -> R {
+    self.reader
+  }
+  fn read_some(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let n = if self.pending.is_empty() {
+      loop {
+        match self.reader.read(buf) {
+          Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+          r => break r?,
+        }
+      }
+    } else {
+      let n = buf.len().min(self.pending.len());
+      for (b,p) in buf.iter_mut().zip(self.pending.drain(..n)) { *b = p }
+      n
+    };
+    if self.recover {
+      self.consumed.extend_from_slice(&buf[..n]);
+    }
+    Ok(n)
+  }
+  // fill buf, returning how many bytes were read before the end of the stream
+  fn read_full(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+      match self.read_some(&mut buf[n..])? {
+        0 => break,
+        r => n += r,
+      }
+    }
+    Ok(n)
+  }
+  fn read_exact(&mut self, buf: &mut [u8]) -> Result<(),Error> {
+    if self.read_full(buf)? < buf.len() {
+      return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+    }
+    Ok(())
+  }
+  pub fn read_blob_header(&mut self) -> Result<Option<(u64,BlobHeader)>,Error> {
+    let mut len_buf = [0,0,0,0];
+    match self.read_full(&mut len_buf)? {
+      0 => return Ok(None),
+      4 => {},
+      n => return Err(format!["unexpected end of stream at offset {}", self.offset + n as u64].into()),
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    check_blob_header_size(len)?;
+    let mut buf = vec![0u8;len];
+    self.read_exact(&mut buf)?;
+    let mut reader = Reader::from_bytes(buf);
+    let blob_header = reader.read(BlobHeader::from_reader)?;
+    check_blob_size(blob_header.datasize as i64)?;
+    self.offset += (len+4) as u64;
+    Ok(Some(((len+4) as u64, blob_header)))
+  }
+  pub fn read_blob(&mut self, len: usize) -> Result<Blob,Error> {
+    check_blob_size(len as i64)?;
+    let mut buf = vec![0u8;len];
+    self.read_exact(&mut buf)?;
+    let mut reader = Reader::from_bytes(buf);
+    let blob = reader.read(Blob::from_reader)?;
+    self.offset += len as u64;
+    Ok(blob)
+  }
+  pub fn read_fileblock(&mut self) -> Result<Option<(u64,u64,BlobHeader,Blob)>,Error> {
+    let offset = self.offset;
+    match self.read_blob_header()? {
+      None => Ok(None),
+      Some((s,blob_header)) => {
+        let blob = self.read_blob(blob_header.datasize as usize)?;
+        Ok(Some((offset, s + blob_header.datasize as u64, blob_header, blob)))
+      },
+    }
+  }
+  pub fn read(&mut self) -> Result<Option<(u64,u64,Vec<element::Element>)>,Error> {
+    if !self.recover {
+      return self.read_elements();
+    }
+    loop {
+      let start = self.offset;
+      self.consumed.clear();
+      let err = match self.read_elements() {
+        Ok(r) => return Ok(r),
+        Err(e) => e,
+      };
+      // hand back everything after the first byte of the bad fileblock and search it for the
+      // start of the next one
+      let consumed = std::mem::take(&mut self.consumed);
+      for b in consumed.iter().skip(1).rev() {
+        self.pending.push_front(*b);
+      }
+      self.offset = start + 1;
+      let found = self.resync()?;
+      push_skipped(&mut self.skipped, start, self.offset, &err);
+      if !found { return Ok(None) }
+    }
+  }
+  // drop bytes until pending starts with a fileblock that decodes cleanly, using the same check
+  // as Parser::find_blob. returns false at the end of the stream
+  fn resync(&mut self) -> Result<bool,Error> {
+    loop {
+      let i = match find_signature(self.pending.make_contiguous()) {
+        Some(i) => i,
+        None => {
+          let keep = self.pending.len().min(SIGNATURE_OVERLAP);
+          let drop = self.pending.len() - keep;
+          self.pending.drain(..drop);
+          self.offset += drop as u64;
+          if !self.fill(self.pending.len() + 1)? {
+            self.offset += self.pending.len() as u64;
+            self.pending.clear();
+            return Ok(false);
+          }
+          continue;
+        },
+      };
+      loop {
+        match check_candidate(&self.pending.make_contiguous()[i..]) {
+          Candidate::Valid => {
+            self.pending.drain(..i);
+            self.offset += i as u64;
+            return Ok(true);
+          },
+          Candidate::Incomplete(n) if self.fill(i + n)? => {},
+          _ => break,
+        }
+      }
+      // not a fileblock after all. search again from the next byte
+      self.pending.drain(..=i);
+      self.offset += i as u64 + 1;
+    }
+  }
+  // read from the reader until pending holds at least n bytes. false if the stream ends first
+  fn fill(&mut self, n: usize) -> Result<bool,Error> {
+    let mut chunk = vec![0u8;1<<16];
+    while self.pending.len() < n {
+      let r = match self.reader.read(&mut chunk) {
+        Ok(r) => r,
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+        Err(e) => return Err(Box::new(e)),
+      };
+      if r == 0 { return Ok(false) }
+      self.pending.extend(&chunk[..r]);
+    }
+    Ok(true)
+  }
+  fn read_elements(&mut self) -> Result<Option<(u64,u64,Vec<element::Element>)>,Error> {
+    match self.read_fileblock()? {
+      None => Ok(None),
+      Some((offset,len,blob_header,_blob)) if blob_header.type_pb == "OSMHeader" => {
+        Ok(Some((offset, len, vec![])))
+      },
+      Some((offset,len,_blob_header,blob)) => {
+        Ok(Some((offset, len, blob.decode_primitive()?.decode()?)))
+      },
+    }
+  }
+}
+
+impl<R> Iterator for StreamParser<R> where R: Read {
+  type Item = Result<(u64,u64,Vec<element::Element>),Error>;
+  fn next(&mut self) -> Option<Self::Item> {
+    self.read().transpose()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Parser,PbfWriter};
+  use crate::test_util::{node,way,fileblocks,corrupt,bad_strings_block};
+
+  // hands out at most 7 bytes per read so fileblocks arrive in pieces
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let n = buf.len().min(self.0.len()).min(7);
+      buf[..n].copy_from_slice(&self.0[..n]);
+      self.0 = &self.0[n..];
+      Ok(n)
+    }
+  }
+
+  fn data() -> Vec<u8> {
+    let mut writer = PbfWriter::new(vec![]);
+    writer.set_block_size(50);
+    writer.write_all((1..=120).map(|id| node(id, id, -id))).unwrap();
+    writer.write_all((1..=60).map(|id| way(id, &[id,id+1]))).unwrap();
+    writer.finish().unwrap()
+  }
+
+  #[test]
+  fn offsets_match_file() {
+    let data = data();
+    let parser = Parser::new(Box::new(data.clone()));
+    let expected = parser.blocks(0, data.len() as u64).collect::<Result<Vec<_>,_>>().unwrap();
+    assert_eq![expected.len(), 1 + 3 + 2];
+    let blocks = StreamParser::new(Trickle(&data)).collect::<Result<Vec<_>,_>>().unwrap();
+    assert_eq![blocks, expected];
+    let mut offset = 0;
+    for (o,len,_) in blocks.iter() {
+      assert_eq![*o, offset];
+      offset += len;
+    }
+    assert_eq![offset, data.len() as u64];
+
+    // starting part way through a file, as when reading a byte range of it
+    let (start,_,_) = expected[2];
+    let mut stream = StreamParser::with_offset(Trickle(&data[start as usize..]), start);
+    let blocks = (&mut stream).collect::<Result<Vec<_>,_>>().unwrap();
+    assert_eq![blocks, expected[2..].to_vec()];
+    assert_eq![stream.offset(), data.len() as u64];
+  }
+
+  #[test]
+  fn truncated_stream() {
+    let data = data();
+    let mut stream = StreamParser::new(Trickle(&data[..data.len()-10]));
+    let results = (&mut stream).collect::<Vec<_>>();
+    assert_eq![results.len(), 6];
+    assert![results[..5].iter().all(|r| r.is_ok())];
+    assert![results[5].is_err()];
+    // a partial length prefix at the end is an error too, not the end of the stream
+    let mut stream = StreamParser::new(&data[..2]);
+    assert![stream.read().is_err()];
+    assert![StreamParser::new(&data[..0]).read().unwrap().is_none()];
+  }
+
+  type Block = (u64,u64,Vec<element::Element>);
+
+  fn recover(data: &[u8]) -> (Vec<Block>,Vec<SkippedRange>) {
+    let mut stream = StreamParser::new(Trickle(data));
+    stream.set_recover(true);
+    let blocks = (&mut stream).collect::<Result<Vec<_>,_>>().unwrap();
+    assert_eq![stream.offset(), data.len() as u64];
+    (blocks, stream.skipped().to_vec())
+  }
+
+  #[test]
+  fn recover_matches_file() {
+    let clean = data();
+    let fb = fileblocks(&clean);
+    assert_eq![fb.len(), 6];
+    let mut data = clean.clone();
+    // the fake signature in the corrupt block is not taken as the next fileblock
+    corrupt(&mut data, fb[2]);
+    let parser = Parser::new(Box::new(data.clone()));
+    let mut blocks = parser.blocks(0, data.len() as u64).recover(true);
+    let expected = (&mut blocks).collect::<Result<Vec<_>,_>>().unwrap();
+    let (read,skipped) = recover(&data);
+    assert_eq![read, expected];
+    assert_eq![skipped, blocks.skipped().to_vec()];
+    let ranges = skipped.iter().map(|s| (s.start,s.end)).collect::<Vec<_>>();
+    assert_eq![ranges, vec![(fb[2].0,fb[3].0)]];
+    // without recovery the error is returned. the block's framing is intact, so the blocks after
+    // it still follow
+    let results = StreamParser::new(Trickle(&data)).collect::<Vec<_>>();
+    assert_eq![results.iter().map(|r| r.is_ok()).collect::<Vec<_>>(),
+      vec![true,true,false,true,true,true]];
+
+    // a bad last block is skipped to the end of the stream
+    let mut data = clean.clone();
+    corrupt(&mut data, fb[5]);
+    let (read,skipped) = recover(&data);
+    assert_eq![read.len(), 5];
+    assert_eq![skipped.iter().map(|s| (s.start,s.end)).collect::<Vec<_>>(),
+      vec![(fb[5].0,clean.len() as u64)]];
+  }
+
+  #[test]
+  fn recover_merges_bad_resync_points() {
+    let clean = data();
+    let fb = fileblocks(&clean);
+    let bad = bad_strings_block();
+    let at = fb[3].0 as usize;
+    let mut data = clean[..at].to_vec();
+    data.extend(&bad);
+    data.extend(&clean[at..]);
+    corrupt(&mut data, fb[2]);
+    let (read,skipped) = recover(&data);
+    assert_eq![read.len(), 5];
+    assert_eq![skipped.iter().map(|s| (s.start,s.end)).collect::<Vec<_>>(),
+      vec![(fb[2].0,(at + bad.len()) as u64)]];
+  }
+}
@@ -0,0 +1,214 @@
+use crate::{Parser,ReadAt,Element,Error};
+use crate::top::{TopCounter,Tally,Hll};
+use hashbrown::HashMap;
+
+// occurrences of a key, tag or key pair. total is an upper bound on the true count and
+// total - error a lower bound. both are exact (error 0) unless the counter had to evict entries
+// to stay within its capacity. nodes, ways and relations count the occurrences seen since the
+// entry was last added, so they are lower bounds
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct Count {
+  pub total: u64,
+  pub error: u64,
+  pub nodes: u64,
+  pub ways: u64,
+  pub relations: u64,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct KeyCount {
+  pub key: String,
+  pub count: Count,
+  // estimated number of distinct values
+  pub values: u64,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct TagCount {
+  pub key: String,
+  pub value: String,
+  pub count: Count,
+}
+
+// two keys that appear together on the same element, with a < b
+#[derive(Debug,Clone,PartialEq)]
+pub struct KeyPairCount {
+  pub a: String,
+  pub b: String,
+  pub count: Count,
+}
+
+impl Tally for Count {
+  fn with_floor(floor: u64) -> Self {
+    Count { total: floor, error: floor, ..Count::default() }
+  }
+  fn total(&self) -> u64 {
+    self.total
+  }
+  fn merge(&mut self, other: &Self) {
+    self.total += other.total;
+    self.error += other.error;
+    self.nodes += other.nodes;
+    self.ways += other.ways;
+    self.relations += other.relations;
+  }
+  fn add_floor(&mut self, floor: u64) {
+    self.total += floor;
+    self.error += floor;
+  }
+}
+
+impl Count {
+  fn add(&mut self, t: usize) {
+    self.total += 1;
+    match t {
+      0 => self.nodes += 1,
+      1 => self.ways += 1,
+      _ => self.relations += 1,
+    }
+  }
+}
+
+// taginfo-style tag usage counts for nodes, ways and relations. each of the key, tag and key pair
+// counters holds up to 2*capacity entries, so memory stays bounded on planet files. for files with
+// fewer distinct entries than capacity the counts are exact. distinct values per key are always
+// estimated
+#[derive(Debug,Clone)]
+pub struct TagStats {
+  keys: TopCounter<String,Count>,
+  tags: TopCounter<(String,String),Count>,
+  pairs: TopCounter<(String,String),Count>,
+  values: HashMap<String,Hll>,
+  elements: [u64;3],
+  tagged: [u64;3],
+}
+
+const DEFAULT_CAPACITY: usize = 10_000;
+
+impl Default for TagStats {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TagStats {
+  pub fn new() -> Self {
+    Self::with_capacity(DEFAULT_CAPACITY)
+  }
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      keys: TopCounter::new(capacity),
+      tags: TopCounter::new(capacity),
+      pairs: TopCounter::new(capacity),
+      values: HashMap::new(),
+      elements: [0;3],
+      tagged: [0;3],
+    }
+  }
+  // changesets are ignored
+  pub fn add_element(&mut self, element: &Element) {
+    let (t,tags) = match element {
+      Element::Node(node) => (0,&node.tags),
+      Element::Way(way) => (1,&way.tags),
+      Element::Relation(relation) => (2,&relation.tags),
+      Element::Changeset(_) => return,
+    };
+    self.elements[t] += 1;
+    if tags.is_empty() { return }
+    self.tagged[t] += 1;
+    for (i,(k,v)) in tags.iter().enumerate() {
+      self.keys.update(k.clone(), |c| c.add(t));
+      self.tags.update((k.clone(),v.clone()), |c| c.add(t));
+      match self.values.get_mut(k) {
+        Some(h) => h.add(v),
+        None => {
+          let mut h = Hll::new();
+          h.add(v);
+          self.values.insert(k.clone(), h);
+        },
+      }
+      for (k2,_) in tags[i+1..].iter() {
+        let pair = if k < k2 { (k.clone(),k2.clone()) } else { (k2.clone(),k.clone()) };
+        self.pairs.update(pair, |c| c.add(t));
+      }
+    }
+    if self.values.len() > self.keys.counts.len() {
+      let keys = &self.keys.counts;
+      self.values.retain(|k,_| keys.contains_key(k));
+    }
+  }
+  pub fn add_elements<'a>(&mut self, elements: impl IntoIterator<Item=&'a Element>) {
+    for e in elements {
+      self.add_element(e);
+    }
+  }
+  // combine counts collected separately, such as from different parts of a file in parallel
+  pub fn merge(&mut self, other: &TagStats) {
+    self.keys.merge(&other.keys);
+    self.tags.merge(&other.tags);
+    self.pairs.merge(&other.pairs);
+    for (k,h) in other.values.iter() {
+      match self.values.get_mut(k) {
+        Some(x) => x.merge(h),
+        None => { self.values.insert(k.clone(), h.clone()); },
+      }
+    }
+    let keys = &self.keys.counts;
+    self.values.retain(|k,_| keys.contains_key(k));
+    for i in 0..3 {
+      self.elements[i] += other.elements[i];
+      self.tagged[i] += other.tagged[i];
+    }
+  }
+  // number of nodes, ways and relations seen
+  pub fn elements(&self) -> [u64;3] {
+    self.elements
+  }
+  // number of nodes, ways and relations with at least one tag
+  pub fn tagged_elements(&self) -> [u64;3] {
+    self.tagged
+  }
+  // keys sorted by count, most frequent first
+  pub fn keys(&self) -> Vec<KeyCount> {
+    self.keys.sorted().into_iter().map(|(k,c)| KeyCount {
+      key: k.clone(),
+      count: *c,
+      values: self.values.get(k).map(|h| h.estimate()).unwrap_or(0),
+    }).collect()
+  }
+  pub fn tags(&self) -> Vec<TagCount> {
+    self.tags.sorted().into_iter().map(|((k,v),c)| TagCount {
+      key: k.clone(),
+      value: v.clone(),
+      count: *c,
+    }).collect()
+  }
+  pub fn key_pairs(&self) -> Vec<KeyPairCount> {
+    self.pairs.sorted().into_iter().map(|((a,b),c)| KeyPairCount {
+      a: a.clone(),
+      b: b.clone(),
+      count: *c,
+    }).collect()
+  }
+  pub fn key(&self, key: &str) -> Option<KeyCount> {
+    self.keys.counts.get(key).map(|c| KeyCount {
+      key: key.to_string(),
+      count: *c,
+      values: self.values.get(key).map(|h| h.estimate()).unwrap_or(0),
+    })
+  }
+  pub fn tag(&self, key: &str, value: &str) -> Option<Count> {
+    self.tags.counts.get(&(key.to_string(),value.to_string())).copied()
+  }
+}
+
+impl<F> Parser<F> where F: ReadAt {
+  // decode every blob between start and end and count tag usage
+  pub fn tag_stats(&self, start: u64, end: u64, capacity: usize) -> Result<TagStats,Error> {
+    let mut stats = TagStats::with_capacity(capacity);
+    for r in self.primitive_blocks(start, end) {
+      stats.add_elements(r?.1.decode()?.iter());
+    }
+    Ok(stats)
+  }
+}
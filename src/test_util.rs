@@ -0,0 +1,102 @@
+// element builders and in-memory files shared by the unit tests
+use crate::{Parser,Scan,PbfWriter,Element,Node,Way,Relation,Member,MemberType,Info};
+
+pub const DEG: i64 = 1_000_000_000;
+
+// coordinates in nanodegrees
+pub fn node(id: i64, nano_lon: i64, nano_lat: i64) -> Element {
+  Element::Node(Node { id, tags: vec![], info: None, nano_lon, nano_lat })
+}
+
+pub fn way(id: i64, refs: &[i64]) -> Element {
+  Element::Way(Way { id, tags: vec![], info: None, refs: refs.to_vec(), nano_coords: vec![] })
+}
+
+// a relation tagged type=kind whose members have empty roles
+pub fn relation(id: i64, kind: &str, members: &[(MemberType,i64)]) -> Element {
+  Element::Relation(Relation {
+    id,
+    tags: vec![("type".into(),kind.into())],
+    info: None,
+    members: members.iter().map(|(t,id)| Member { id: *id, role: "".into(), member_type: *t })
+      .collect(),
+  })
+}
+
+// an element with only version, timestamp and visible set in its info
+pub fn with_info(mut element: Element, version: i32, timestamp: Option<i64>, visible: bool)
+-> Element {
+  let info = Some(Info {
+    version, timestamp, changeset: None, uid: None, user: None, visible: Some(visible),
+  });
+  match &mut element {
+    Element::Node(node) => node.info = info,
+    Element::Way(way) => way.info = info,
+    Element::Relation(relation) => relation.info = info,
+    Element::Changeset(changeset) => changeset.info = info,
+  }
+  element
+}
+
+pub fn write(elements: Vec<Element>) -> Vec<u8> {
+  let mut writer = PbfWriter::new(vec![]);
+  writer.write_all(elements).unwrap();
+  writer.finish().unwrap()
+}
+
+pub fn scan_data(data: Vec<u8>) -> Scan<Vec<u8>> {
+  let len = data.len() as u64;
+  let mut scan = Scan::new(Parser::new(Box::new(data)));
+  scan.scan(0, len).unwrap();
+  scan
+}
+
+pub fn scan(elements: Vec<Element>) -> Scan<Vec<u8>> {
+  scan_data(write(elements))
+}
+
+// every element in a file, in file order
+pub fn read_all(data: &[u8]) -> Vec<Element> {
+  let parser = Parser::new(Box::new(data.to_vec()));
+  let mut elements = vec![];
+  for r in parser.primitive_blocks(0, data.len() as u64) {
+    elements.extend(r.unwrap().1.decode().unwrap());
+  }
+  elements
+}
+
+// (offset, length) of every fileblock in a file
+pub fn fileblocks(data: &[u8]) -> Vec<(u64,u64)> {
+  let parser = Parser::new(Box::new(data.to_vec()));
+  parser.blocks(0, data.len() as u64)
+    .map(|r| r.map(|(offset,len,_)| (offset,len)).unwrap())
+    .collect()
+}
+
+// overwrite the middle of a fileblock with what looks like the start of another fileblock but
+// isn't one, so that both decoding and a naive resync on the signature fail
+pub fn corrupt(data: &mut [u8], (offset,len): (u64,u64)) {
+  let fake = b"\x00\x00\x00\x0b\x0a\x07OSMData\x18\x05\xff\xff\xff\xff\xff";
+  let mid = (offset + len / 2) as usize;
+  data[mid..mid+fake.len()].copy_from_slice(fake);
+}
+
+// a fileblock whose blob decodes but whose elements don't, since a tag points past the end of
+// the string table
+pub fn bad_strings_block() -> Vec<u8> {
+  use crate::proto::osmformat::{PrimitiveBlock,PrimitiveGroup,StringTable,Node as PbfNode};
+  let block = PrimitiveBlock {
+    stringtable: StringTable { s: vec![vec![]] },
+    primitivegroup: vec![PrimitiveGroup {
+      nodes: vec![PbfNode { id: 1, keys: vec![9], vals: vec![9], ..PbfNode::default() }],
+      ..PrimitiveGroup::default()
+    }],
+    granularity: 100,
+    date_granularity: 1000,
+    ..PrimitiveBlock::default()
+  };
+  let mut data = vec![];
+  crate::write::write_fileblock(&mut data, "OSMData", &crate::write::serialize(&block).unwrap())
+    .unwrap();
+  data
+}
@@ -0,0 +1,521 @@
+use crate::{Parser,ReadAt,Blob,Element,MemberType,Error};
+use crate::decode::{BlobError,blob_data};
+use crate::proto::osmformat::{HeaderBlock,PrimitiveBlock,PrimitiveGroup,DenseNodes};
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum ProblemKind {
+  // the blob header could not be read or is outside the spec's limits
+  BlobHeader,
+  // the blob uses a compression this crate does not support
+  Compression,
+  // the blob data does not match raw_size, or a compressed blob has no raw_size
+  Size,
+  // a message failed to parse
+  Protobuf,
+  // a missing header block or an unsupported required feature
+  Header,
+  // a string table index out of range, or a string that is not utf-8
+  StringTable,
+  // parallel arrays with different lengths
+  ArrayLength,
+  // a primitive group holding more than one kind of element
+  Group,
+  // elements out of order in a file whose header lists Sort.Type_then_ID
+  SortOrder,
+}
+
+// offset is the offset of the fileblock. group is the index of the primitive group when the
+// problem is inside one
+#[derive(Debug,Clone,PartialEq)]
+pub struct Problem {
+  pub offset: u64,
+  pub group: Option<usize>,
+  pub kind: ProblemKind,
+  pub message: String,
+}
+
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct VerifyReport {
+  pub blobs: u64,
+  pub elements: u64,
+  // whether the elements are in type-then-id order, whatever the header says
+  pub sorted: bool,
+  pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+  pub fn is_ok(&self) -> bool {
+    self.problems.is_empty()
+  }
+}
+
+impl std::fmt::Display for VerifyReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    for p in self.problems.iter() {
+      match p.group {
+        Some(g) => writeln![f, "offset {} group {}: {:?}: {}", p.offset, g, p.kind, p.message]?,
+        None => writeln![f, "offset {}: {:?}: {}", p.offset, p.kind, p.message]?,
+      }
+    }
+    write![f, "{} blobs, {} elements, {} problems{}",
+      self.blobs, self.elements, self.problems.len(), if self.sorted { "" } else { ", unsorted" }]
+  }
+}
+
+struct Verifier {
+  report: VerifyReport,
+  sort_required: bool,
+  // (type,id) of the last element seen, for the sort order check
+  last: Option<(MemberType,i64)>,
+}
+
+impl Verifier {
+  fn problem(&mut self, offset: u64, group: Option<usize>, kind: ProblemKind, message: String) {
+    self.report.problems.push(Problem { offset, group, kind, message });
+  }
+  fn blob_data(&mut self, offset: u64, blob: &Blob) -> Option<Vec<u8>> {
+    if blob.raw.is_none() && blob.zlib_data.is_some() && blob.raw_size.is_none() {
+      self.problem(offset, None, ProblemKind::Size, "compressed blob without raw_size".into());
+    }
+    // raw_size is optional for uncompressed data, but has to be right when it is there
+    if let (Some(raw),Some(raw_size)) = (&blob.raw, blob.raw_size) {
+      if raw.len() as i64 != raw_size as i64 {
+        self.problem(offset, None, ProblemKind::Size, format![
+          "raw blob is {} bytes, expected raw_size {}", raw.len(), raw_size
+        ]);
+      }
+    }
+    let data = blob_data(
+      blob.raw.as_deref(), blob.zlib_data.as_deref(), blob.lzma_data.as_deref(), blob.raw_size
+    );
+    match data {
+      Ok(data) => Some(data.into_owned()),
+      Err(BlobError::Compression(msg)) => {
+        self.problem(offset, None, ProblemKind::Compression, msg);
+        None
+      },
+      Err(BlobError::Size(msg)) => {
+        self.problem(offset, None, ProblemKind::Size, msg);
+        None
+      },
+    }
+  }
+  fn header(&mut self, offset: u64, data: &[u8]) {
+    match HeaderBlock::from_bytes(data) {
+      Ok(header) => {
+        if let Err(e) = header.check_required_features() {
+          self.problem(offset, None, ProblemKind::Header, e.to_string());
+        }
+        self.sort_required = header.optional_features.iter().any(|f| f == "Sort.Type_then_ID");
+      },
+      Err(e) => self.problem(offset, None, ProblemKind::Protobuf, format!["header block: {}", e]),
+    }
+  }
+  fn primitive(&mut self, offset: u64, data: &[u8]) {
+    let block = match PrimitiveBlock::from_bytes(data) {
+      Ok(block) => block,
+      Err(e) => {
+        self.problem(offset, None, ProblemKind::Protobuf, format!["primitive block: {}", e]);
+        return;
+      },
+    };
+    let problems = self.report.problems.len();
+    if block.stringtable.s.first().map(|s| !s.is_empty()).unwrap_or(false) {
+      self.problem(offset, None, ProblemKind::StringTable, "string 0 is not empty".into());
+    }
+    for (i,s) in block.stringtable.s.iter().enumerate() {
+      if std::str::from_utf8(s).is_err() {
+        self.problem(offset, None, ProblemKind::StringTable, format!["string {} is not utf-8", i]);
+      }
+    }
+    for (g,group) in block.primitivegroup.iter().enumerate() {
+      self.group(offset, g, &block, group);
+    }
+    // only decode blocks that passed the structural checks, since decoding stops at the first error
+    if self.report.problems.len() > problems { return }
+    match block.decode() {
+      Ok(elements) => {
+        self.report.elements += elements.len() as u64;
+        for e in elements.iter() {
+          self.order(offset, e);
+        }
+      },
+      Err(e) => self.problem(offset, None, ProblemKind::Protobuf, e.to_string()),
+    }
+  }
+  fn group(&mut self, offset: u64, g: usize, block: &PrimitiveBlock, group: &PrimitiveGroup) {
+    let n = block.stringtable.s.len();
+    let kinds = [
+      !group.nodes.is_empty(), group.dense.is_some(), !group.ways.is_empty(),
+      !group.relations.is_empty(), !group.changesets.is_empty(),
+    ].iter().filter(|x| **x).count();
+    if kinds > 1 {
+      self.problem(offset, Some(g), ProblemKind::Group, "group mixes element types".into());
+    }
+    for node in group.nodes.iter() {
+      self.tag_lengths(offset, g, "node", node.id, node.keys.len(), node.vals.len());
+      self.strings(offset, g, n, "node tag", node.keys.iter().chain(node.vals.iter()).map(|i| *i as i64));
+      self.strings(offset, g, n, "node user", node.info.iter().filter_map(|i| i.user_sid).map(|i| i as i64));
+    }
+    if let Some(dense) = &group.dense {
+      self.dense(offset, g, dense);
+      self.strings(offset, g, n, "dense nodes keys_vals", dense.keys_vals.iter().map(|i| *i as i64));
+      if let Some(info) = &dense.denseinfo {
+        let mut sid = 0i64;
+        let users = info.user_sid.iter().map(|d| { sid += *d as i64; sid });
+        self.strings(offset, g, n, "dense nodes user", users);
+      }
+    }
+    for way in group.ways.iter() {
+      self.tag_lengths(offset, g, "way", way.id, way.keys.len(), way.vals.len());
+      if way.lat.len() != way.lon.len() || (!way.lat.is_empty() && way.lat.len() != way.refs.len()) {
+        self.problem(offset, Some(g), ProblemKind::ArrayLength, format![
+          "way {} has {} refs, {} lats and {} lons", way.id, way.refs.len(), way.lat.len(), way.lon.len()
+        ]);
+      }
+      self.strings(offset, g, n, "way tag", way.keys.iter().chain(way.vals.iter()).map(|i| *i as i64));
+      self.strings(offset, g, n, "way user", way.info.iter().filter_map(|i| i.user_sid).map(|i| i as i64));
+    }
+    for relation in group.relations.iter() {
+      self.tag_lengths(offset, g, "relation", relation.id, relation.keys.len(), relation.vals.len());
+      let (m,r,t) = (relation.memids.len(), relation.roles_sid.len(), relation.types.len());
+      if m != r || m != t {
+        self.problem(offset, Some(g), ProblemKind::ArrayLength, format![
+          "relation {} has {} memids, {} roles and {} types", relation.id, m, r, t
+        ]);
+      }
+      self.strings(offset, g, n, "relation tag",
+        relation.keys.iter().chain(relation.vals.iter()).map(|i| *i as i64));
+      self.strings(offset, g, n, "relation role", relation.roles_sid.iter().map(|i| *i as i64));
+      self.strings(offset, g, n, "relation user",
+        relation.info.iter().filter_map(|i| i.user_sid).map(|i| i as i64));
+    }
+    for changeset in group.changesets.iter() {
+      self.tag_lengths(offset, g, "changeset", changeset.id, changeset.keys.len(), changeset.vals.len());
+      self.strings(offset, g, n, "changeset tag",
+        changeset.keys.iter().chain(changeset.vals.iter()).map(|i| *i as i64));
+    }
+  }
+  fn strings(&mut self, offset: u64, g: usize, n: usize, what: &str, mut ids: impl Iterator<Item=i64>) {
+    if let Some(i) = ids.find(|i| *i < 0 || *i as usize >= n) {
+      self.problem(offset, Some(g), ProblemKind::StringTable,
+        format!["{} refers to string {} of {}", what, i, n]);
+    }
+  }
+  fn tag_lengths(&mut self, offset: u64, g: usize, what: &str, id: i64, keys: usize, vals: usize) {
+    if keys != vals {
+      self.problem(offset, Some(g), ProblemKind::ArrayLength,
+        format!["{} {} has {} keys and {} vals", what, id, keys, vals]);
+    }
+  }
+  fn dense(&mut self, offset: u64, g: usize, dense: &DenseNodes) {
+    let n = dense.id.len();
+    if dense.lat.len() != n || dense.lon.len() != n {
+      self.problem(offset, Some(g), ProblemKind::ArrayLength, format![
+        "dense nodes have {} ids, {} lats and {} lons", n, dense.lat.len(), dense.lon.len()
+      ]);
+    }
+    if let Some(info) = &dense.denseinfo {
+      let lens = [
+        ("version", info.version.len()), ("timestamp", info.timestamp.len()),
+        ("changeset", info.changeset.len()), ("uid", info.uid.len()),
+        ("user_sid", info.user_sid.len()), ("visible", info.visible.len()),
+      ];
+      for (name,len) in lens.iter() {
+        if *len != 0 && *len != n {
+          self.problem(offset, Some(g), ProblemKind::ArrayLength,
+            format!["dense nodes have {} ids but {} {} values", n, len, name]);
+        }
+      }
+    }
+    if !dense.keys_vals.is_empty() {
+      // one run of key,value pairs ending in 0 for each node
+      let mut runs = 0;
+      let mut i = 0;
+      while i < dense.keys_vals.len() {
+        if dense.keys_vals[i] == 0 {
+          runs += 1;
+          i += 1;
+        } else if i+1 < dense.keys_vals.len() {
+          i += 2;
+        } else {
+          self.problem(offset, Some(g), ProblemKind::ArrayLength,
+            "dense nodes keys_vals ends with a key without a value".into());
+          return;
+        }
+      }
+      if runs != n {
+        self.problem(offset, Some(g), ProblemKind::ArrayLength,
+          format!["dense nodes have {} ids but keys_vals has {} entries", n, runs]);
+      }
+    }
+  }
+  fn order(&mut self, offset: u64, e: &Element) {
+    let key = match e.member_type() {
+      Some(t) => (t,e.id()),
+      None => return,
+    };
+    if let Some(last) = self.last {
+      // history files repeat ids, one element per version. every place where the order goes
+      // backwards is reported
+      if key < last {
+        self.report.sorted = false;
+        if self.sort_required {
+          self.problem(offset, None, ProblemKind::SortOrder, format![
+            "{} {} follows {} {}", type_name(key.0), key.1, type_name(last.0), last.1
+          ]);
+        }
+      }
+    }
+    self.last = Some(key);
+  }
+}
+
+fn type_name(t: MemberType) -> &'static str {
+  match t {
+    MemberType::Node => "node",
+    MemberType::Way => "way",
+    MemberType::Relation => "relation",
+  }
+}
+
+impl<F> Parser<F> where F: ReadAt {
+  // check every fileblock between start and end and collect the problems found. damaged
+  // fileblocks are skipped like in recovery mode. only read errors from the source are returned
+  // as errors
+  pub fn verify(&self, start: u64, end: u64) -> Result<VerifyReport,Error> {
+    let mut v = Verifier {
+      report: VerifyReport { sorted: true, ..VerifyReport::default() },
+      sort_required: false,
+      last: None,
+    };
+    let mut offset = start;
+    while offset < end {
+      let (s,blob_header) = match self.read_blob_header(offset) {
+        Ok(r) => r,
+        Err(e) => {
+          v.problem(offset, None, ProblemKind::BlobHeader, e.to_string());
+          offset = self.find_blob(offset+1, end)?.unwrap_or(end);
+          continue;
+        },
+      };
+      let len = s + blob_header.datasize as u64;
+      v.report.blobs += 1;
+      if offset == start && blob_header.type_pb != "OSMHeader" {
+        v.problem(offset, None, ProblemKind::Header, format![
+          "expected OSMHeader, found {}", blob_header.type_pb
+        ]);
+      }
+      let blob = match self.read_blob(offset + s, blob_header.datasize as usize) {
+        Ok(blob) => blob,
+        Err(e) => {
+          v.problem(offset, None, ProblemKind::Protobuf, format!["blob: {}", e]);
+          offset += len;
+          continue;
+        },
+      };
+      if let Some(data) = v.blob_data(offset, &blob) {
+        match blob_header.type_pb.as_str() {
+          "OSMHeader" => v.header(offset, &data),
+          "OSMData" => v.primitive(offset, &data),
+          t => v.problem(offset, None, ProblemKind::BlobHeader, format!["unknown blob type {}", t]),
+        }
+      }
+      offset += len;
+    }
+    Ok(v.report)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{BlobHeader,PbfWriter};
+  use crate::write::{serialize,write_fileblock};
+  use crate::test_util::{node,write};
+  use crate::proto::osmformat::{StringTable,Node,Way,Relation};
+
+  fn verify(data: &[u8]) -> VerifyReport {
+    Parser::new(Box::new(data.to_vec())).verify(0, data.len() as u64).unwrap()
+  }
+
+  fn kinds(report: &VerifyReport) -> Vec<ProblemKind> {
+    report.problems.iter().map(|p| p.kind).collect()
+  }
+
+  // a file with a header block followed by the given fileblocks
+  fn file(fileblocks: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = write(vec![]);
+    for fb in fileblocks { data.extend(fb) }
+    data
+  }
+
+  fn fileblock(type_pb: &str, blob: &Blob) -> Vec<u8> {
+    let blob = serialize(blob).unwrap();
+    let header = serialize(&BlobHeader {
+      type_pb: type_pb.into(), indexdata: None, datasize: blob.len() as i32,
+    }).unwrap();
+    let mut data = (header.len() as u32).to_be_bytes().to_vec();
+    data.extend(header);
+    data.extend(blob);
+    data
+  }
+
+  fn block(group: PrimitiveGroup) -> Vec<u8> {
+    let block = PrimitiveBlock {
+      stringtable: StringTable { s: vec![vec![],b"name".to_vec()] },
+      primitivegroup: vec![group],
+      granularity: 100,
+      date_granularity: 1000,
+      ..PrimitiveBlock::default()
+    };
+    let mut data = vec![];
+    write_fileblock(&mut data, "OSMData", &serialize(&block).unwrap()).unwrap();
+    data
+  }
+
+  fn group_of_nodes(nodes: Vec<Node>) -> PrimitiveGroup {
+    PrimitiveGroup { nodes, ..PrimitiveGroup::default() }
+  }
+
+  #[test]
+  fn clean_file() {
+    let report = verify(&write(vec![node(1, 0, 0), node(2, 0, 0)]));
+    assert![report.is_ok()];
+    assert_eq![(report.blobs,report.elements,report.sorted), (2,2,true)];
+  }
+
+  #[test]
+  fn blob_header() {
+    let mut bad = ((crate::MAX_BLOB_HEADER_SIZE + 1) as u32).to_be_bytes().to_vec();
+    bad.extend(vec![0;8]);
+    let report = verify(&file(&[bad]));
+    assert_eq![kinds(&report), vec![ProblemKind::BlobHeader]];
+    assert_eq![report.problems[0].offset, write(vec![]).len() as u64];
+    let raw = Blob { raw: Some(vec![]), ..Blob::default() };
+    let report = verify(&file(&[fileblock("OSMOther", &raw)]));
+    assert_eq![kinds(&report), vec![ProblemKind::BlobHeader]];
+    assert_eq![report.problems[0].message, "unknown blob type OSMOther"];
+  }
+
+  #[test]
+  fn compression() {
+    let lzma = Blob { raw_size: Some(4), lzma_data: Some(vec![1,2,3]), ..Blob::default() };
+    let empty = Blob::default();
+    let report = verify(&file(&[fileblock("OSMData", &lzma), fileblock("OSMData", &empty)]));
+    assert_eq![kinds(&report), vec![ProblemKind::Compression,ProblemKind::Compression]];
+    assert_eq![report.problems[1].message, "blob has no data"];
+  }
+
+  #[test]
+  fn size() {
+    let empty = PrimitiveBlock { granularity: 100, ..PrimitiveBlock::default() };
+    let data = serialize(&empty).unwrap();
+    let raw = |raw_size| Blob { raw: Some(data.clone()), raw_size, ..Blob::default() };
+    // raw_size may be left out for raw data
+    assert![verify(&file(&[fileblock("OSMData", &raw(None))])).is_ok()];
+    let wrong = raw(Some(data.len() as i32 + 1));
+    let report = verify(&file(&[fileblock("OSMData", &wrong)]));
+    assert_eq![kinds(&report), vec![ProblemKind::Size]];
+    assert_eq![report.problems[0].message,
+      format!["raw blob is {} bytes, expected raw_size {}", data.len(), data.len() + 1]];
+    // zlib data without raw_size, and with the wrong one
+    let good = file(&[block(PrimitiveGroup::default())]);
+    let parser = Parser::new(Box::new(good.clone()));
+    let offset = write(vec![]).len() as u64;
+    let (_,_,blob) = parser.read_fileblock(offset).unwrap();
+    let missing = Blob { raw_size: None, ..blob.clone() };
+    let wrong = Blob { raw_size: blob.raw_size.map(|n| n + 1), ..blob };
+    let report = verify(&file(&[fileblock("OSMData", &missing), fileblock("OSMData", &wrong)]));
+    assert_eq![kinds(&report), vec![ProblemKind::Size,ProblemKind::Size]];
+  }
+
+  #[test]
+  fn protobuf() {
+    let garbage = Blob { raw: Some(vec![0xff,0xff,0xff]), ..Blob::default() };
+    let report = verify(&file(&[fileblock("OSMData", &garbage)]));
+    assert_eq![kinds(&report), vec![ProblemKind::Protobuf]];
+    assert![report.problems[0].message.starts_with("primitive block: ")];
+  }
+
+  #[test]
+  fn header() {
+    let mut writer = PbfWriter::new(vec![]);
+    writer.header_mut().unwrap().required_features.push("Unknown".into());
+    writer.write(node(1, 0, 0)).unwrap();
+    let report = verify(&writer.finish().unwrap());
+    assert_eq![kinds(&report), vec![ProblemKind::Header]];
+    // a file that starts with a data block
+    let report = verify(&block(group_of_nodes(vec![Node { id: 1, ..Node::default() }])));
+    assert_eq![kinds(&report), vec![ProblemKind::Header]];
+    assert_eq![report.problems[0].message, "expected OSMHeader, found OSMData"];
+  }
+
+  #[test]
+  fn string_table() {
+    let tagged = Node { id: 1, keys: vec![1], vals: vec![5], ..Node::default() };
+    let report = verify(&file(&[block(group_of_nodes(vec![tagged]))]));
+    assert_eq![kinds(&report), vec![ProblemKind::StringTable]];
+    assert_eq![report.problems[0].group, Some(0)];
+    assert_eq![report.problems[0].message, "node tag refers to string 5 of 2"];
+    let block = PrimitiveBlock {
+      stringtable: StringTable { s: vec![b"x".to_vec(),vec![0xff]] },
+      granularity: 100,
+      ..PrimitiveBlock::default()
+    };
+    let mut data = vec![];
+    write_fileblock(&mut data, "OSMData", &serialize(&block).unwrap()).unwrap();
+    let report = verify(&file(&[data]));
+    assert_eq![kinds(&report), vec![ProblemKind::StringTable,ProblemKind::StringTable]];
+    assert_eq![report.problems[1].message, "string 1 is not utf-8"];
+  }
+
+  #[test]
+  fn array_length() {
+    let dense = DenseNodes { id: vec![1,1], lat: vec![0,0], lon: vec![0], ..DenseNodes::default() };
+    let tags = Way { id: 7, keys: vec![1], ..Way::default() };
+    let members = Relation { id: 8, memids: vec![1], ..Relation::default() };
+    let report = verify(&file(&[
+      block(PrimitiveGroup { dense: Some(dense), ..PrimitiveGroup::default() }),
+      block(PrimitiveGroup { ways: vec![tags], ..PrimitiveGroup::default() }),
+      block(PrimitiveGroup { relations: vec![members], ..PrimitiveGroup::default() }),
+    ]));
+    assert_eq![kinds(&report), vec![ProblemKind::ArrayLength; 3]];
+    let messages = report.problems.iter().map(|p| p.message.as_str()).collect::<Vec<_>>();
+    assert_eq![messages, vec![
+      "dense nodes have 2 ids, 2 lats and 1 lons",
+      "way 7 has 1 keys and 0 vals",
+      "relation 8 has 1 memids, 0 roles and 0 types",
+    ]];
+  }
+
+  #[test]
+  fn group() {
+    let mixed = PrimitiveGroup {
+      nodes: vec![Node { id: 1, ..Node::default() }],
+      ways: vec![Way { id: 1, ..Way::default() }],
+      ..PrimitiveGroup::default()
+    };
+    let report = verify(&file(&[block(mixed)]));
+    assert_eq![kinds(&report), vec![ProblemKind::Group]];
+    // blocks with structural problems are not decoded
+    assert_eq![report.elements, 0];
+  }
+
+  #[test]
+  fn sort_order() {
+    let elements = || [2,1,3,0,4].iter().map(|id| node(*id, 0, 0)).collect::<Vec<_>>();
+    let report = verify(&write(elements()));
+    assert![report.is_ok()];
+    assert![!report.sorted];
+    let mut writer = PbfWriter::new(vec![]);
+    writer.header_mut().unwrap().optional_features.push("Sort.Type_then_ID".into());
+    writer.write_all(elements()).unwrap();
+    let report = verify(&writer.finish().unwrap());
+    // each place where the order goes backwards is reported
+    assert_eq![kinds(&report), vec![ProblemKind::SortOrder; 2]];
+    assert_eq![report.problems[0].message, "node 1 follows node 2"];
+    assert_eq![report.problems[1].message, "node 0 follows node 3"];
+  }
+}
@@ -0,0 +1,468 @@
+use crate::{Blob,BlobHeader,element,Element,Error,MAX_BLOB_SIZE};
+use crate::proto::osmformat::{
+  self,HeaderBlock,PrimitiveBlock,PrimitiveGroup,StringTable,DenseNodes,DenseInfo,mod_Relation
+};
+use quick_protobuf::{MessageWrite,Writer};
+use flate2::{write::ZlibEncoder,Compression};
+use hashbrown::HashMap;
+use std::io::Write;
+
+const BLOCK_SIZE: usize = 8000;
+// the spec recommends keeping uncompressed blocks under 16MiB
+const BLOCK_BYTES: usize = 16*1024*1024;
+
+// writes elements as a regular pbf file: a header block followed by zlib-compressed primitive
+// blocks of up to BLOCK_SIZE elements of one type. a block is also cut early when an upper bound
+// on its encoded size would pass BLOCK_BYTES. nodes are written as dense nodes.
+// coordinates use the default granularity of 100 nanodegrees and timestamps are in seconds.
+//
+// dense nodes store info in columns. a block without any info leaves the columns out, and so
+// does a field that no node in the block sets. when only some nodes of a block have info or a
+// field, the others are written as version -1 and 0 for the missing fields, which decode as
+// Some(0) or Some("") instead of None
+pub struct PbfWriter<W: Write> {
+  out: W,
+  header: HeaderBlock,
+  header_written: bool,
+  pending: Vec<Element>,
+  pending_bytes: usize,
+  block_size: usize,
+  block_bytes: usize,
+}
+
+impl<W> PbfWriter<W> where W: Write {
+  pub fn new(out: W) -> Self {
+    Self::with_header(out, HeaderBlock {
+      required_features: vec!["OsmSchema-V0.6".into(),"DenseNodes".into()],
+      writingprogram: Some("osmpbf-parser".into()),
+      ..HeaderBlock::default()
+    })
+  }
+  // way coordinates are only written when the header lists LocationsOnWays
+  pub fn with_header(out: W, header: HeaderBlock) -> Self {
+    Self {
+      out, header, header_written: false, pending: vec![], pending_bytes: 0,
+      block_size: BLOCK_SIZE, block_bytes: BLOCK_BYTES,
+    }
+  }
+  pub fn set_block_size(&mut self, block_size: usize) {
+    self.block_size = block_size.max(1);
+  }
+  // lower the byte limit for a block. it can't be raised past BLOCK_BYTES
+  pub fn set_block_bytes(&mut self, block_bytes: usize) {
+    self.block_bytes = block_bytes.min(BLOCK_BYTES);
+  }
+  pub fn header(&self) -> &HeaderBlock {
+    &self.header
+  }
+  // the header can be changed until the first block is written
+  pub fn header_mut(&mut self) -> Option<&mut HeaderBlock> {
+    if self.header_written { None } else { Some(&mut self.header) }
+  }
+  pub fn write(&mut self, element: Element) -> Result<(),Error> {
+    let same_type = self.pending.first()
+      .map(|e| std::mem::discriminant(e) == std::mem::discriminant(&element))
+      .unwrap_or(true);
+    let bytes = max_encoded_size(&element);
+    if !same_type || self.pending.len() >= self.block_size
+    || self.pending_bytes + bytes > self.block_bytes {
+      self.flush_block()?;
+    }
+    self.pending_bytes += bytes;
+    self.pending.push(element);
+    Ok(())
+  }
+  pub fn write_all<I>(&mut self, elements: I) -> Result<(),Error> where I: IntoIterator<Item=Element> {
+    for element in elements {
+      self.write(element)?;
+    }
+    Ok(())
+  }
+  // write out any buffered elements and return the underlying writer
+  pub fn finish(mut self) -> Result<W,Error> {
+    self.flush_block()?;
+    self.write_header()?;
+    self.out.flush()?;
+    Ok(self.out)
+  }
+  fn write_header(&mut self) -> Result<(),Error> {
+    if self.header_written { return Ok(()) }
+    let data = serialize(&self.header)?;
+    write_fileblock(&mut self.out, "OSMHeader", &data)?;
+    self.header_written = true;
+    Ok(())
+  }
+  fn flush_block(&mut self) -> Result<(),Error> {
+    self.write_header()?;
+    if self.pending.is_empty() { return Ok(()) }
+    let elements = std::mem::take(&mut self.pending);
+    self.pending_bytes = 0;
+    let mut block = BlockBuilder::new(self.header.optional_features.iter()
+      .chain(self.header.required_features.iter())
+      .any(|f| f == "LocationsOnWays"));
+    let group = block.group(&elements);
+    let primitive_block = PrimitiveBlock {
+      stringtable: block.strings,
+      primitivegroup: vec![group],
+      granularity: 100,
+      lat_offset: 0,
+      lon_offset: 0,
+      date_granularity: 1000,
+    };
+    let data = serialize(&primitive_block)?;
+    write_fileblock(&mut self.out, "OSMData", &data)
+  }
+}
+
+// a generous upper bound on the bytes an element adds to an encoded block: every string as if it
+// were new to the string table and every number as a 10 byte varint with a tag
+fn max_encoded_size(element: &Element) -> usize {
+  let string = |s: &str| s.len() + 16;
+  let tags = |tags: &element::Tags| tags.iter().map(|(k,v)| string(k) + string(v)).sum::<usize>();
+  let info = |info: &Option<element::Info>| {
+    info.as_ref().map(|i| 80 + i.user.as_deref().map(string).unwrap_or(0)).unwrap_or(0)
+  };
+  64 + match element {
+    Element::Node(node) => tags(&node.tags) + info(&node.info),
+    Element::Way(way) => tags(&way.tags) + info(&way.info) + way.refs.len() * 33,
+    Element::Relation(relation) => tags(&relation.tags) + info(&relation.info)
+      + relation.members.iter().map(|m| 33 + string(&m.role)).sum::<usize>(),
+    Element::Changeset(changeset) => tags(&changeset.tags) + info(&changeset.info) + 128,
+  }
+}
+
+pub(crate) fn serialize<M: MessageWrite>(message: &M) -> Result<Vec<u8>,Error> {
+  let mut buf = vec![];
+  message.write_message(&mut Writer::new(&mut buf))?;
+  Ok(buf)
+}
+
+pub(crate) fn write_fileblock<W: Write>(out: &mut W, type_pb: &str, data: &[u8])
+-> Result<(),Error> {
+  if data.len() > MAX_BLOB_SIZE {
+    return Err(format![
+      "{} block of {} bytes exceeds the limit of {} bytes", type_pb, data.len(), MAX_BLOB_SIZE
+    ].into());
+  }
+  let mut z = ZlibEncoder::new(vec![], Compression::default());
+  z.write_all(data)?;
+  let blob = Blob {
+    raw_size: Some(data.len() as i32),
+    zlib_data: Some(z.finish()?),
+    ..Blob::default()
+  };
+  let blob_buf = serialize(&blob)?;
+  let blob_header = BlobHeader {
+    type_pb: type_pb.to_string(),
+    indexdata: None,
+    datasize: blob_buf.len() as i32,
+  };
+  let header_buf = serialize(&blob_header)?;
+  out.write_all(&(header_buf.len() as u32).to_be_bytes())?;
+  out.write_all(&header_buf)?;
+  out.write_all(&blob_buf)?;
+  Ok(())
+}
+
+struct BlockBuilder {
+  strings: StringTable,
+  string_ids: HashMap<String,u32>,
+  locations_on_ways: bool,
+}
+
+impl BlockBuilder {
+  fn new(locations_on_ways: bool) -> Self {
+    Self {
+      strings: StringTable { s: vec![vec![]] },
+      string_ids: HashMap::new(),
+      locations_on_ways,
+    }
+  }
+  fn string(&mut self, s: &str) -> u32 {
+    if let Some(i) = self.string_ids.get(s) { return *i }
+    let i = self.strings.s.len() as u32;
+    self.strings.s.push(s.as_bytes().to_vec());
+    self.string_ids.insert(s.to_string(), i);
+    i
+  }
+  fn tags(&mut self, tags: &element::Tags) -> (Vec<u32>,Vec<u32>) {
+    tags.iter().map(|(k,v)| (self.string(k), self.string(v))).unzip()
+  }
+  fn info(&mut self, info: &element::Info) -> osmformat::Info {
+    osmformat::Info {
+      version: info.version,
+      timestamp: info.timestamp,
+      changeset: info.changeset,
+      uid: info.uid,
+      user_sid: info.user.as_ref().map(|u| self.string(u)),
+      visible: info.visible,
+    }
+  }
+  fn group(&mut self, elements: &[Element]) -> PrimitiveGroup {
+    let mut group = PrimitiveGroup::default();
+    let nodes = elements.iter()
+      .filter_map(|e| match e { Element::Node(node) => Some(node), _ => None })
+      .collect::<Vec<_>>();
+    if !nodes.is_empty() {
+      group.dense = Some(self.dense(&nodes));
+    }
+    for e in elements.iter() {
+      match e {
+        Element::Node(_) => {},
+        Element::Way(way) => group.ways.push(self.way(way)),
+        Element::Relation(relation) => group.relations.push(self.relation(relation)),
+        Element::Changeset(changeset) => group.changesets.push(self.changeset(changeset)),
+      }
+    }
+    group
+  }
+  fn dense(&mut self, nodes: &[&element::Node]) -> DenseNodes {
+    let mut dense = DenseNodes::default();
+    let with_info = nodes.iter().any(|node| node.info.is_some());
+    // a column is only written when some node sets it, so that fields missing on every node
+    // decode as None again
+    let any = |f: &dyn Fn(&element::Info) -> bool| {
+      nodes.iter().any(|node| node.info.as_ref().map(f).unwrap_or(false))
+    };
+    let with_timestamp = any(&|info| info.timestamp.is_some());
+    let with_changeset = any(&|info| info.changeset.is_some());
+    let with_uid = any(&|info| info.uid.is_some());
+    let with_user = any(&|info| info.user.is_some());
+    let with_visible = any(&|info| info.visible.is_some());
+    let mut dense_info = DenseInfo::default();
+    let (mut prev_id, mut prev_lon, mut prev_lat) = (0,0,0);
+    let (mut prev_timestamp, mut prev_changeset, mut prev_uid, mut prev_user_sid) = (0,0,0,0);
+    let has_tags = nodes.iter().any(|node| !node.tags.is_empty());
+    for node in nodes.iter() {
+      let lon = element::round_nano_to_e7(node.nano_lon);
+      let lat = element::round_nano_to_e7(node.nano_lat);
+      // deltas wrap like the decoder does, so ids from a corrupt file can't overflow
+      dense.id.push(node.id.wrapping_sub(prev_id));
+      dense.lon.push(lon - prev_lon);
+      dense.lat.push(lat - prev_lat);
+      prev_id = node.id;
+      prev_lon = lon;
+      prev_lat = lat;
+      if has_tags {
+        for (k,v) in node.tags.iter() {
+          let (k,v) = (self.string(k), self.string(v));
+          dense.keys_vals.push(k as i32);
+          dense.keys_vals.push(v as i32);
+        }
+        dense.keys_vals.push(0);
+      }
+      if with_info {
+        let info = node.info.as_ref();
+        let timestamp = info.and_then(|i| i.timestamp).unwrap_or(0);
+        let changeset = info.and_then(|i| i.changeset).unwrap_or(0);
+        let uid = info.and_then(|i| i.uid).unwrap_or(0);
+        let user_sid = info.and_then(|i| i.user.as_ref()).map(|u| self.string(u) as i32).unwrap_or(0);
+        dense_info.version.push(info.map(|i| i.version).unwrap_or(-1));
+        if with_timestamp {
+          dense_info.timestamp.push(timestamp.wrapping_sub(prev_timestamp));
+        }
+        if with_changeset {
+          dense_info.changeset.push(changeset.wrapping_sub(prev_changeset));
+        }
+        if with_uid {
+          dense_info.uid.push(uid.wrapping_sub(prev_uid));
+        }
+        if with_user {
+          dense_info.user_sid.push(user_sid.wrapping_sub(prev_user_sid));
+        }
+        if with_visible {
+          dense_info.visible.push(info.and_then(|i| i.visible).unwrap_or(true));
+        }
+        prev_timestamp = timestamp;
+        prev_changeset = changeset;
+        prev_uid = uid;
+        prev_user_sid = user_sid;
+      }
+    }
+    if with_info {
+      dense.denseinfo = Some(dense_info);
+    }
+    dense
+  }
+  fn way(&mut self, way: &element::Way) -> osmformat::Way {
+    let (keys,vals) = self.tags(&way.tags);
+    let mut refs = vec![];
+    let mut prev_ref = 0;
+    for r in way.refs.iter() {
+      refs.push(r.wrapping_sub(prev_ref));
+      prev_ref = *r;
+    }
+    let (mut lon, mut lat) = (vec![], vec![]);
+    if self.locations_on_ways && way.nano_coords.len() == way.refs.len() {
+      let (mut prev_lon, mut prev_lat) = (0,0);
+      for (nano_lon,nano_lat) in way.nano_coords.iter() {
+        let x = element::round_nano_to_e7(*nano_lon);
+        let y = element::round_nano_to_e7(*nano_lat);
+        lon.push(x - prev_lon);
+        lat.push(y - prev_lat);
+        prev_lon = x;
+        prev_lat = y;
+      }
+    }
+    osmformat::Way {
+      id: way.id,
+      keys,
+      vals,
+      info: way.info.as_ref().map(|info| self.info(info)),
+      refs,
+      lat,
+      lon,
+    }
+  }
+  fn relation(&mut self, relation: &element::Relation) -> osmformat::Relation {
+    let (keys,vals) = self.tags(&relation.tags);
+    let mut roles_sid = vec![];
+    let mut memids = vec![];
+    let mut types = vec![];
+    let mut prev_id = 0;
+    for member in relation.members.iter() {
+      roles_sid.push(self.string(&member.role) as i32);
+      memids.push(member.id.wrapping_sub(prev_id));
+      prev_id = member.id;
+      types.push(match member.member_type {
+        element::MemberType::Node => mod_Relation::MemberType::NODE,
+        element::MemberType::Way => mod_Relation::MemberType::WAY,
+        element::MemberType::Relation => mod_Relation::MemberType::RELATION,
+      });
+    }
+    osmformat::Relation {
+      id: relation.id,
+      keys,
+      vals,
+      info: relation.info.as_ref().map(|info| self.info(info)),
+      roles_sid,
+      memids,
+      types,
+    }
+  }
+  fn changeset(&mut self, changeset: &element::Changeset) -> osmformat::ChangeSet {
+    let (keys,vals) = self.tags(&changeset.tags);
+    osmformat::ChangeSet {
+      id: changeset.id,
+      keys,
+      vals,
+      info: changeset.info.as_ref().map(|info| self.info(info)),
+      created_at: changeset.created_at,
+      closetime_delta: changeset.created_at.zip(changeset.closed_at)
+        .map(|(c,d)| d.wrapping_sub(c)),
+      open: changeset.open,
+      bbox: changeset.bbox.as_ref().map(|bbox| osmformat::HeaderBBox {
+        left: bbox.left,
+        right: bbox.right,
+        top: bbox.top,
+        bottom: bbox.bottom,
+      }),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Parser,Changeset,BBox,Info,MemberType};
+  use crate::test_util::{DEG,node,way,relation,read_all};
+
+  fn info(version: i32, user: &str) -> Option<Info> {
+    Some(Info {
+      version,
+      timestamp: Some(1_600_000_000 + version as i64),
+      changeset: Some(900 + version as i64),
+      uid: Some(42),
+      user: Some(user.into()),
+      visible: Some(version != 3),
+    })
+  }
+
+  fn blocks(data: &[u8]) -> Vec<PrimitiveBlock> {
+    let parser = Parser::new(Box::new(data.to_vec()));
+    parser.primitive_blocks(0, data.len() as u64).map(|r| r.unwrap().1).collect()
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut elements = vec![
+      node(1, DEG, -DEG), node(2, -1_800_000_000, 123_456_700), way(3, &[1,2]),
+      relation(4, "multipolygon", &[(MemberType::Way,3),(MemberType::Node,1)]),
+    ];
+    for (i,e) in elements.iter_mut().enumerate() {
+      let user = if i % 2 == 0 { "alice" } else { "bob" };
+      match e {
+        Element::Node(node) => {
+          node.info = info(i as i32 + 1, user);
+          node.tags.push(("name".into(),format!["n{}", i]));
+        },
+        Element::Way(way) => way.info = info(3, user),
+        Element::Relation(relation) => {
+          relation.info = info(4, user);
+          relation.members[0].role = "outer".into();
+        },
+        Element::Changeset(_) => {},
+      }
+    }
+    elements.push(Element::Changeset(Changeset {
+      id: 900,
+      tags: vec![("comment".into(),"fix".into())],
+      info: info(1, "alice"),
+      created_at: Some(1_600_000_000),
+      closed_at: Some(1_600_003_600),
+      open: Some(false),
+      bbox: Some(BBox { left: -DEG, right: DEG, top: 2*DEG, bottom: -2*DEG }),
+    }));
+    elements.push(Element::Changeset(Changeset {
+      id: 901, tags: vec![], info: None, created_at: Some(1_600_000_000), closed_at: None,
+      open: Some(true), bbox: None,
+    }));
+    let mut writer = PbfWriter::new(vec![]);
+    writer.write_all(elements.clone()).unwrap();
+    let data = writer.finish().unwrap();
+    assert_eq![read_all(&data), elements];
+    assert_eq![blocks(&data).len(), 4];
+  }
+
+  #[test]
+  fn closetime_delta_wraps() {
+    let changeset = Element::Changeset(Changeset {
+      id: 1, tags: vec![], info: None, created_at: Some(i64::MIN), closed_at: Some(i64::MAX),
+      open: None, bbox: None,
+    });
+    let mut writer = PbfWriter::new(vec![]);
+    writer.write(changeset).unwrap();
+    let data = writer.finish().unwrap();
+    let group = &blocks(&data)[0].primitivegroup[0];
+    assert_eq![group.changesets[0].closetime_delta, Some(-1)];
+  }
+
+  #[test]
+  fn block_bytes() {
+    let big = |id| {
+      let mut e = node(id, 0, 0);
+      if let Element::Node(node) = &mut e { node.tags.push(("note".into(),"x".repeat(300))) }
+      e
+    };
+    let mut writer = PbfWriter::new(vec![]);
+    writer.set_block_bytes(1000);
+    writer.write_all((1..=5).map(big)).unwrap();
+    writer.write_all((1..=5).map(|id| way(id, &[1,2]))).unwrap();
+    let data = writer.finish().unwrap();
+    let sizes = blocks(&data).iter()
+      .map(|b| b.decode().unwrap().len())
+      .collect::<Vec<_>>();
+    // two 300 byte tags fit under the limit but three don't. the small ways share one block
+    assert_eq![sizes, vec![2,2,1,5]];
+    assert_eq![read_all(&data).len(), 10];
+
+    // a single element too large for any block is an error rather than an unreadable file
+    let mut huge = node(1, 0, 0);
+    if let Element::Node(node) = &mut huge {
+      node.tags.push(("note".into(),"x".repeat(MAX_BLOB_SIZE)));
+    }
+    let mut writer = PbfWriter::new(vec![]);
+    writer.write(huge).unwrap();
+    assert![writer.finish().is_err()];
+  }
+}